@@ -11,12 +11,14 @@ use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use alloc::{format, vec};
 use embassy_executor::Spawner;
-use embassy_net::dns::DnsSocket;
+use embassy_net::dns::{DnsQueryType, DnsSocket};
 use embassy_net::tcp::client::{TcpClient, TcpClientState};
+use embassy_net::tcp::TcpSocket;
 use embassy_net::{Runner, Stack, StackResources};
 use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex};
 use embassy_sync::channel::Channel;
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{Read, Write};
 use embedded_graphics::mono_font::ascii::{FONT_7X13, FONT_7X13_BOLD};
 use esp_hal::clock::CpuClock;
 use esp_hal::rng::Rng;
@@ -37,10 +39,21 @@ use iris_ui::scene::{click_at, draw_scene, event_at_focused, layout_scene};
 use log::{error, info, warn};
 use reqwless::client::{HttpClient, TlsConfig};
 
-use nostd_browser::browser::{handle_action, make_gui_scene, update_view_from_keyboard_input, AppState, GuiResponse, LIGHT_THEME, PAGE_VIEW};
+use nostd_browser::browser::{handle_action, is_editing_form, load_image, make_gui_scene, update_view_from_keyboard_input, AppState, GuiResponse, LIGHT_THEME, PAGE_VIEW};
+use nostd_browser::archive;
+use nostd_browser::cookie::CookieJar;
+use nostd_browser::error::LoadError;
+use nostd_browser::fetch::{fetch_image, PageSink, Session};
+use nostd_browser::form::FormMethod;
+use nostd_browser::gopher;
+use nostd_browser::gopher::GopherRequest;
+use nostd_browser::image::{Bitmap, ImageBudget};
 use nostd_browser::page::Page;
+use nostd_browser::search::DEFAULT_SEARCH_ENGINE;
 use nostd_browser::pageview::PageView;
+use nostd_browser::url::Url;
 use device::common::{NetCommand, NetStatus, NET_COMMANDS, NET_STATUS};
+use device::fetcher::ReqwlessFetcher;
 use device::tdeck::Wrapper;
 
 #[panic_handler]
@@ -68,7 +81,28 @@ const AUTO_CONNECT: Option<&str> = option_env!("AUTO_CONNECT");
 
 static PAGE_BYTES: &[u8] = include_bytes!("homepage.html");
 
-static PAGE_CHANNEL: Channel<CriticalSectionRawMutex, Page, 1> = Channel::new();
+static PAGE_CHANNEL: Channel<CriticalSectionRawMutex, Page, 2> = Channel::new();
+
+/// Decoded images as (page url, image url, bitmap).
+static IMAGE_CHANNEL: Channel<CriticalSectionRawMutex, (String, String, Bitmap), 2> = Channel::new();
+
+/// Smaller than the simulator's so the images fit in the heap next to the
+/// page: one full size bitmap, or a few small ones.
+const IMAGE_BUDGET: ImageBudget = ImageBudget {
+    max_width: 160,
+    max_height: 120,
+    max_encoded: 16 * 1024,
+    max_decoded: 40 * 1024,
+    max_total: 40 * 1024,
+    max_images: 4,
+};
+
+/// How long to wait for a server to send its response headers.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Pages are kept as archives, so this holds a handful of typical pages
+/// without crowding the heap.
+const PAGE_CACHE_BYTES: usize = 24 * 1024;
 
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) {
@@ -100,6 +134,8 @@ async fn update_display(mut wrapper: Wrapper) {
         theme: &LIGHT_THEME,
         font: &FONT_7X13,
         bold_font: &FONT_7X13_BOLD,
+        gopher_search: None,
+        search_engine: DEFAULT_SEARCH_ENGINE,
     };
 
     let handlers: Vec<Callback> = vec![];
@@ -115,6 +151,9 @@ async fn update_display(mut wrapper: Wrapper) {
         //     scene.mark_dirty_view(PAGE_VIEW);
         //     info!("heap is {}", esp_alloc::HEAP.stats());
         // }
+        if let Ok((page_url, src, bitmap)) = IMAGE_CHANNEL.try_receive() {
+            load_image(&mut scene, &page_url, &src, bitmap);
+        }
         // if let Ok(status) = NET_STATUS.try_receive() {
         //     info!("got the status {status:?}");
         //     let txt = match &status {
@@ -139,12 +178,16 @@ async fn update_display(mut wrapper: Wrapper) {
             last_touch_event = point;
         }
         if let Some(key) = wrapper.poll_keyboard() {
-            let text_action = if key == b' ' {
-                info!("doing a space as an action");
-                update_view_from_keyboard_input(&mut scene, &TextAction::TypedAscii(key));
-                TextAction::Enter
-            } else {
-                TextAction::TypedAscii(key)
+            let text_action = match key {
+                b' ' if !is_editing_form(&mut scene) => {
+                    info!("doing a space as an action");
+                    update_view_from_keyboard_input(&mut scene, &TextAction::TypedAscii(key));
+                    TextAction::Enter
+                }
+                // the keyboard sends backspace and return as control characters
+                0x08 => TextAction::BackDelete,
+                b'\r' => TextAction::Enter,
+                _ => TextAction::TypedAscii(key),
             };
             if let Some(result) = event_at_focused(&mut scene, &InputEvent::Text(text_action)) {
                 if let Some(resp) = handle_action(&result, &mut scene, &mut app) {
@@ -206,10 +249,18 @@ async fn handle_file_url(href: &str) {
     info!("loading path {}", path);
 
     let bytes = load_file_url(&href).await;
-    PAGE_CHANNEL
-        .sender()
-        .send(Page::from_bytes(bytes, &href))
-        .await;
+    let page = if archive::is_archive(bytes) {
+        match archive::read_page(bytes) {
+            Ok(page) => page,
+            Err(err) => {
+                NET_STATUS.send(NetStatus::Error(format!("{:?}", err))).await;
+                return;
+            }
+        }
+    } else {
+        Page::from_bytes(bytes, &href)
+    };
+    PAGE_CHANNEL.sender().send(page).await;
 }
 
 async fn handle_bookmarks(href: &str) {
@@ -220,16 +271,25 @@ async fn handle_bookmarks(href: &str) {
 }
 async fn handle_gui_response(gui_response: GuiResponse, _app: &mut AppState) {
     match gui_response {
-        GuiResponse::Net(net) => match net {
-            nostd_browser::browser::NetCommand::Load(href) => {
-                NET_COMMANDS.send(NetCommand::Load(href)).await;
-            }
-        },
+        GuiResponse::Net(net) => NET_COMMANDS.send(net).await,
+        GuiResponse::SavePage(url, bytes) => {
+            // the menu has no Save page item without the std feature, as
+            // there is nowhere to put it until the SD card is hooked up
+            warn!("not saving {}, {} bytes", url, bytes.len());
+        }
     }
 }
 
 
-async fn handle_http_url(href: &str, network_stack: Stack<'static>, tls_seed: u64) {
+/// Loads a web page and then its images. A `form_body` is sent as a URL
+/// encoded POST.
+async fn handle_http_url(
+    href: &str,
+    form_body: Option<&str>,
+    session: &mut Session,
+    network_stack: Stack<'static>,
+    tls_seed: u64,
+) {
     NET_STATUS.send(NetStatus::LoadingPage()).await;
     let mut rx_buffer = [0; 4096 * 2];
     let mut tx_buffer = [0; 4096 * 2];
@@ -244,36 +304,111 @@ async fn handle_http_url(href: &str, network_stack: Stack<'static>, tls_seed: u6
         reqwless::client::TlsVerify::None,
     );
 
-    let mut client = HttpClient::new_with_tls(&tcp, &dns, tls);
-    // let mut client = HttpClient::new(&tcp, &dns);
-    let mut buffer = [0u8; 4096 * 5];
+    let mut fetcher = ReqwlessFetcher::new(HttpClient::new_with_tls(&tcp, &dns, tls), LOAD_TIMEOUT);
     info!("making the actual request to {}", href);
-    // let url = "https://joshondesign.com/2023/07/12/css_text_style_builder";
-    let mut http_req = client
-        .request(reqwless::request::Method::GET, &href)
-        .await
-        .unwrap();
-    let resp = http_req.send(&mut buffer).await;
-    match resp {
-        Ok(response) => {
-            info!("Got response");
-            let res = response.body().read_to_end().await.unwrap();
-            PAGE_CHANNEL
-                .sender()
-                .send(Page::from_bytes(res, &href))
-                .await;
+    // there's no clock, so cookie and cache times count from boot
+    let now = Instant::now().as_secs();
+    let (page_url, sources) = match session.load(&mut fetcher, href, form_body, now, &mut PageChannel).await {
+        Ok(images) => {
+            NET_STATUS.send(NetStatus::PageLoaded()).await;
+            images
+        }
+        Err(err) => {
+            show_load_error(href, err).await;
+            return;
+        }
+    };
+    // images that fail to load or don't fit keep showing their alt text
+    let mut used = 0;
+    for src in sources.iter().take(IMAGE_BUDGET.max_images) {
+        if !(src.starts_with("http:") || src.starts_with("https:")) {
+            continue;
+        }
+        match fetch_image(&mut fetcher, src, &IMAGE_BUDGET.remaining(used)).await {
+            Some(bitmap) => {
+                info!("loaded image {} {:?}", src, bitmap);
+                used += bitmap.byte_size();
+                IMAGE_CHANNEL.sender().send((page_url.clone(), src.clone(), bitmap)).await;
+            }
+            None => warn!("couldn't load the image {}", src),
+        }
+    }
+}
+
+/// Hands loaded pages to the GUI.
+struct PageChannel;
+
+impl PageSink for PageChannel {
+    async fn show(&mut self, page: Page) {
+        PAGE_CHANNEL.sender().send(page).await;
+    }
+}
+
+/// Shows an error page for `href` in place of the page that failed to load.
+async fn show_load_error(href: &str, err: LoadError) {
+    warn!("loading {} failed: {:?}", href, err);
+    PAGE_CHANNEL.sender().send(err.to_page(href)).await;
+    NET_STATUS.send(NetStatus::Error(err.message())).await;
+}
+
+async fn handle_gopher_url(href: &str, network_stack: Stack<'static>) {
+    NET_STATUS.send(NetStatus::LoadingPage()).await;
+    match gopher_request(href, network_stack).await {
+        Ok(page) => {
+            PAGE_CHANNEL.sender().send(page).await;
             NET_STATUS.send(NetStatus::PageLoaded()).await;
         }
         Err(err) => {
             info!("Got error: {:?}", err);
-            NET_STATUS
-                .send(NetStatus::Error(format!("{:?}", err)))
-                .await;
+            NET_STATUS.send(NetStatus::Error(err)).await;
         }
     }
 }
+
+async fn gopher_request(href: &str, network_stack: Stack<'static>) -> Result<Page, String> {
+    let url = Url::parse(href).ok_or_else(|| format!("invalid url {}", href))?;
+    let request = GopherRequest::from_url(&url).ok_or_else(|| format!("invalid gopher url {}", href))?;
+    let addrs = network_stack
+        .dns_query(&request.host, DnsQueryType::A)
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+    let addr = addrs
+        .first()
+        .ok_or_else(|| format!("no address for {}", request.host))?;
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = TcpSocket::new(network_stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(Duration::from_secs(10)));
+    socket
+        .connect((*addr, request.port))
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+    socket
+        .write_all(request.request_line().as_bytes())
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+    let mut body: Vec<u8> = vec![];
+    let mut chunk = [0u8; 1024];
+    while body.len() < gopher::MAX_RESPONSE {
+        match socket.read(&mut chunk).await {
+            Ok(0) => break,
+            Ok(count) => body.extend_from_slice(&chunk[..count]),
+            Err(err) => {
+                warn!("error reading the body {:?}", err);
+                break;
+            }
+        }
+    }
+    socket.close();
+    Ok(gopher::page_from_response(href, &request, &body))
+}
+
 #[embassy_executor::task]
 async fn page_downloader(network_stack: Stack<'static>, tls_seed: u64) {
+    // nowhere to save cookies until the SD card is hooked up, so they only
+    // last until a restart
+    let mut session = Session::new(PAGE_CACHE_BYTES);
+    session.cookies = CookieJar::without_wall_clock();
     loop {
         if let Ok(cmd) = NET_COMMANDS.try_receive() {
             info!("Network command: {:?}", cmd);
@@ -284,11 +419,28 @@ async fn page_downloader(network_stack: Stack<'static>, tls_seed: u64) {
                         handle_bookmarks(&href).await;
                     } else if href.starts_with("file:") {
                         handle_file_url(&href).await;
+                    } else if href.starts_with("gopher:") {
+                        handle_gopher_url(&href, network_stack).await;
+                    } else if href.starts_with("gemini:") {
+                        // needs a TLS client that exposes the server certificate for pinning
+                        NET_STATUS
+                            .send(NetStatus::Error("Gemini is only supported in the simulator".to_string()))
+                            .await;
                     } else {
                         // if !href.starts_with("http") {
                         //     info!("relative url");
                         // }
-                        handle_http_url(&href, network_stack, tls_seed).await;
+                        handle_http_url(&href, None, &mut session, network_stack, tls_seed).await;
+                    }
+                }
+                NetCommand::Submit(submission) => {
+                    let url = submission.url();
+                    info!("Submitting a form to {}", url);
+                    match submission.method {
+                        FormMethod::Get => handle_http_url(&url, None, &mut session, network_stack, tls_seed).await,
+                        FormMethod::Post => {
+                            handle_http_url(&url, Some(&submission.body), &mut session, network_stack, tls_seed).await
+                        }
                     }
                 }
             }
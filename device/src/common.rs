@@ -21,10 +21,7 @@ pub type TDeckDisplay = Display<
 >;
 
 
-#[derive(Debug)]
-pub enum NetCommand {
-    Load(String),
-}
+pub use nostd_browser::browser::NetCommand;
 
 pub static NET_COMMANDS: Channel<CriticalSectionRawMutex, NetCommand, 2> = Channel::new();
 
@@ -0,0 +1,100 @@
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use embassy_net::dns::DnsSocket;
+use embassy_net::tcp::client::TcpClient;
+use embassy_time::{with_timeout, Duration};
+use embedded_io_async::{ErrorKind, Read};
+use nostd_browser::error::LoadError;
+use nostd_browser::fetch::{Fetcher, Method, Request, ResponseHead, ResponseSink};
+use reqwless::client::HttpClient;
+use reqwless::request::RequestBuilder;
+
+/// Room for the status line and headers of a response. Sites that set
+/// several cookies send a lot of header.
+const HEADER_BUFFER_SIZE: usize = 4096 * 5;
+
+/// Fetches with reqwless over the Wi-Fi stack.
+pub struct ReqwlessFetcher<'a> {
+    client: HttpClient<'a, TcpClient<'a, 1, 4096, 4096>, DnsSocket<'a>>,
+    /// holds the response headers
+    buffer: [u8; HEADER_BUFFER_SIZE],
+    /// how long to wait for the response headers
+    timeout: Duration,
+}
+
+impl<'a> ReqwlessFetcher<'a> {
+    pub fn new(client: HttpClient<'a, TcpClient<'a, 1, 4096, 4096>, DnsSocket<'a>>, timeout: Duration) -> Self {
+        ReqwlessFetcher {
+            client,
+            buffer: [0; HEADER_BUFFER_SIZE],
+            timeout,
+        }
+    }
+}
+
+impl Fetcher for ReqwlessFetcher<'_> {
+    async fn fetch(&mut self, request: &Request<'_>, sink: &mut impl ResponseSink) -> Result<(), LoadError> {
+        let method = match request.method {
+            Method::Get => reqwless::request::Method::GET,
+            Method::Post => reqwless::request::Method::POST,
+        };
+        let headers: Vec<(&str, &str)> = request
+            .headers
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        let http_req = self
+            .client
+            .request(method, request.url)
+            .await
+            .map_err(load_error)?;
+        // a GET goes out with an empty body, which only adds a zero Content-Length
+        let mut http_req = http_req
+            .body(request.body.unwrap_or("").as_bytes())
+            .headers(&headers);
+        let response = match with_timeout(self.timeout, http_req.send(&mut self.buffer)).await {
+            Ok(response) => response.map_err(load_error)?,
+            Err(_) => return Err(LoadError::Timeout),
+        };
+        let head = ResponseHead {
+            status: response.status.0,
+            headers: response
+                .headers()
+                .filter_map(|(name, value)| {
+                    let value = core::str::from_utf8(value).ok()?;
+                    Some((name.to_string(), value.to_string()))
+                })
+                .collect(),
+        };
+        if !sink.head(&head).await {
+            return Ok(());
+        }
+        let mut reader = response.body().reader();
+        let mut chunk = [0u8; 1024];
+        loop {
+            match reader.read(&mut chunk).await {
+                Ok(0) => return Ok(()),
+                Ok(count) => {
+                    if !sink.chunk(&chunk[..count]).await {
+                        return Ok(());
+                    }
+                }
+                Err(err) => return Err(load_error(err)),
+            }
+        }
+    }
+}
+
+fn load_error(err: reqwless::Error) -> LoadError {
+    match err {
+        reqwless::Error::Dns => LoadError::Dns,
+        reqwless::Error::InvalidUrl(_) => LoadError::InvalidUrl,
+        reqwless::Error::Tls(_) => LoadError::Tls,
+        // the response headers didn't fit in the buffer
+        reqwless::Error::BufferTooSmall => LoadError::TooLarge,
+        reqwless::Error::Network(ErrorKind::ConnectionRefused) => LoadError::ConnectionRefused,
+        reqwless::Error::Network(ErrorKind::TimedOut) => LoadError::Timeout,
+        err => LoadError::Other(format!("{:?}", err)),
+    }
+}
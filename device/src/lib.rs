@@ -3,3 +3,4 @@ extern crate alloc;
 
 pub mod tdeck;
 pub mod common;
+pub mod fetcher;
@@ -0,0 +1,530 @@
+use crate::form::{ControlKind, Form, FormControl, FormMethod, SelectOption};
+use crate::page::{Anchor, Indent, Page, PageImage, PageTable, TableCell};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use nostd_html_parser::blocks::{Block, BlockType};
+use nostd_html_parser::lines::{RunStyle, TextRun};
+
+/// Saved pages start with these bytes, then a version byte.
+pub const MAGIC: &[u8; 4] = b"NBPA";
+
+/// Bump this when the layout changes. Older versions must stay readable.
+/// Version 2 added the list of images, version 3 the tables, version 4
+/// the preformatted blocks, version 5 the nesting of blocks and version 6
+/// the forms.
+pub const VERSION: u8 = 6;
+
+/// The usual file extension for saved pages.
+pub const EXTENSION: &str = "nbpa";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveError {
+    BadMagic,
+    /// written by a newer version than this one
+    UnsupportedVersion(u8),
+    Truncated,
+    Invalid,
+}
+
+pub fn is_archive(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Serializes a page so it can be reopened without the original document.
+///
+/// After the header comes the URL, the optional title, description, language,
+/// charset and base, the blocks with their spans, the anchors, the main content
+/// range, the images without their decoded bitmaps, the tables as rows of cell
+/// block ranges, the ranges of preformatted blocks, the indents of nested
+/// blocks and the forms with their controls and current values. Numbers are
+/// LEB128 varints and strings are length prefixed UTF-8. Links are not stored,
+/// they are rebuilt from the spans on load. Passwords are never written,
+/// password fields come back empty.
+pub fn write_page(page: &Page) -> Vec<u8> {
+    let mut out = Writer { bytes: vec![] };
+    out.bytes.extend_from_slice(MAGIC);
+    out.bytes.push(VERSION);
+    out.string(&page.url);
+    for field in [
+        &page.title,
+        &page.description,
+        &page.lang,
+        &page.charset,
+        &page.base,
+    ] {
+        out.optional(field.as_deref());
+    }
+    out.number(page.blocks.len());
+    for block in &page.blocks {
+        out.bytes.push(match block.block_type {
+            BlockType::Paragraph => 0,
+            BlockType::ListItem => 1,
+            BlockType::Header => 2,
+        });
+        out.number(block.spans.len());
+        for span in &block.spans {
+            match &span.style {
+                RunStyle::Plain => out.bytes.push(0),
+                RunStyle::Bold => out.bytes.push(1),
+                RunStyle::Link(href) => {
+                    out.bytes.push(2);
+                    out.string(href);
+                }
+            }
+            out.string(&span.text);
+        }
+    }
+    out.number(page.anchors.len());
+    for anchor in &page.anchors {
+        out.string(&anchor.name);
+        out.number(anchor.block);
+    }
+    match &page.main_content {
+        Some(range) => {
+            out.bytes.push(1);
+            out.number(range.start);
+            out.number(range.end);
+        }
+        None => out.bytes.push(0),
+    }
+    out.number(page.images.len());
+    for image in &page.images {
+        out.number(image.block);
+        out.string(&image.src);
+        out.string(&image.alt);
+    }
+    out.number(page.tables.len());
+    for table in &page.tables {
+        out.number(table.rows.len());
+        for row in &table.rows {
+            out.number(row.len());
+            for cell in row {
+                out.number(cell.blocks.start);
+                out.number(cell.blocks.end);
+                out.bytes.push(cell.header as u8);
+            }
+        }
+    }
+    out.number(page.preformatted.len());
+    for range in &page.preformatted {
+        out.number(range.start);
+        out.number(range.end);
+    }
+    out.number(page.indents.len());
+    for indent in &page.indents {
+        out.number(indent.block);
+        out.bytes.push(indent.level);
+        out.bytes.push(indent.quotes);
+        out.optional_number(indent.number.map(|number| number as usize));
+    }
+    out.number(page.forms.len());
+    for form in &page.forms {
+        out.string(&form.action);
+        out.bytes.push(match form.method {
+            FormMethod::Get => 0,
+            FormMethod::Post => 1,
+        });
+    }
+    out.number(page.controls.len());
+    for control in &page.controls {
+        out.bytes.push(match control.kind {
+            ControlKind::Text => 0,
+            ControlKind::Password => 1,
+            ControlKind::Hidden => 2,
+            ControlKind::Checkbox => 3,
+            ControlKind::Radio => 4,
+            ControlKind::Submit => 5,
+            ControlKind::Select => 6,
+            ControlKind::TextArea => 7,
+        });
+        out.optional_number(control.form);
+        out.optional_number(control.block);
+        out.string(&control.name);
+        match control.kind {
+            ControlKind::Password => out.string(""),
+            _ => out.string(&control.value),
+        }
+        out.bytes.push(control.checked as u8);
+        out.number(control.options.len());
+        for option in &control.options {
+            out.string(&option.value);
+            out.string(&option.label);
+        }
+        out.number(control.selected);
+    }
+    out.bytes
+}
+
+/// Reads a page written by [`write_page`].
+pub fn read_page(bytes: &[u8]) -> Result<Page, ArchiveError> {
+    if !is_archive(bytes) {
+        return Err(ArchiveError::BadMagic);
+    }
+    let mut input = Reader {
+        bytes,
+        pos: MAGIC.len(),
+    };
+    let version = input.byte()?;
+    if version == 0 || version > VERSION {
+        return Err(ArchiveError::UnsupportedVersion(version));
+    }
+    let mut page = Page::new();
+    page.url = input.string()?;
+    page.title = input.optional()?;
+    page.description = input.optional()?;
+    page.lang = input.optional()?;
+    page.charset = input.optional()?;
+    page.base = input.optional()?;
+    let count = input.count()?;
+    page.blocks = Vec::with_capacity(count);
+    for _ in 0..count {
+        let block_type = match input.byte()? {
+            0 => BlockType::Paragraph,
+            1 => BlockType::ListItem,
+            2 => BlockType::Header,
+            _ => return Err(ArchiveError::Invalid),
+        };
+        let count = input.count()?;
+        let mut spans = Vec::with_capacity(count);
+        for _ in 0..count {
+            let style = match input.byte()? {
+                0 => RunStyle::Plain,
+                1 => RunStyle::Bold,
+                2 => RunStyle::Link(input.string()?),
+                _ => return Err(ArchiveError::Invalid),
+            };
+            spans.push(TextRun {
+                style,
+                text: input.string()?,
+            });
+        }
+        page.blocks.push(Block { block_type, spans });
+    }
+    let count = input.count()?;
+    for _ in 0..count {
+        let name = input.string()?;
+        let block = input.number()?;
+        page.anchors.push(Anchor { name, block });
+    }
+    if input.byte()? == 1 {
+        let start = input.number()?;
+        let end = input.number()?;
+        if start > end || end > page.blocks.len() {
+            return Err(ArchiveError::Invalid);
+        }
+        page.main_content = Some(start..end);
+    }
+    if version >= 2 {
+        let count = input.count()?;
+        for _ in 0..count {
+            let block = input.number()?;
+            let src = input.string()?;
+            let alt = input.string()?;
+            page.images.push(PageImage {
+                block,
+                src,
+                alt,
+                bitmap: None,
+            });
+        }
+    }
+    if version >= 3 {
+        let count = input.count()?;
+        for _ in 0..count {
+            let count = input.count()?;
+            let mut rows = Vec::with_capacity(count);
+            for _ in 0..count {
+                let count = input.count()?;
+                let mut row = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let start = input.number()?;
+                    let end = input.number()?;
+                    if start > end || end > page.blocks.len() {
+                        return Err(ArchiveError::Invalid);
+                    }
+                    let header = input.byte()? == 1;
+                    row.push(TableCell {
+                        blocks: start..end,
+                        header,
+                    });
+                }
+                rows.push(row);
+            }
+            page.tables.push(PageTable { rows });
+        }
+    }
+    if version >= 4 {
+        let count = input.count()?;
+        for _ in 0..count {
+            let start = input.number()?;
+            let end = input.number()?;
+            if start > end || end > page.blocks.len() {
+                return Err(ArchiveError::Invalid);
+            }
+            page.preformatted.push(start..end);
+        }
+    }
+    if version >= 5 {
+        let count = input.count()?;
+        for _ in 0..count {
+            let block = input.number()?;
+            if block >= page.blocks.len() {
+                return Err(ArchiveError::Invalid);
+            }
+            let level = input.byte()?;
+            let quotes = input.byte()?;
+            let number = match input.optional_number()? {
+                Some(number) => Some(u32::try_from(number).map_err(|_| ArchiveError::Invalid)?),
+                None => None,
+            };
+            page.indents.push(Indent {
+                block,
+                level,
+                quotes,
+                number,
+            });
+        }
+    }
+    if version >= 6 {
+        let count = input.count()?;
+        for _ in 0..count {
+            let action = input.string()?;
+            let method = match input.byte()? {
+                0 => FormMethod::Get,
+                1 => FormMethod::Post,
+                _ => return Err(ArchiveError::Invalid),
+            };
+            page.forms.push(Form { action, method });
+        }
+        let count = input.count()?;
+        for _ in 0..count {
+            let kind = match input.byte()? {
+                0 => ControlKind::Text,
+                1 => ControlKind::Password,
+                2 => ControlKind::Hidden,
+                3 => ControlKind::Checkbox,
+                4 => ControlKind::Radio,
+                5 => ControlKind::Submit,
+                6 => ControlKind::Select,
+                7 => ControlKind::TextArea,
+                _ => return Err(ArchiveError::Invalid),
+            };
+            let form = input.optional_number()?;
+            let block = input.optional_number()?;
+            if form.is_some_and(|form| form >= page.forms.len())
+                || block.is_some_and(|block| block >= page.blocks.len())
+            {
+                return Err(ArchiveError::Invalid);
+            }
+            let mut control = FormControl::new(kind, &input.string()?);
+            control.form = form;
+            control.block = block;
+            control.value = input.string()?;
+            control.checked = input.byte()? == 1;
+            let count = input.count()?;
+            for _ in 0..count {
+                let value = input.string()?;
+                let label = input.string()?;
+                control.options.push(SelectOption { value, label });
+            }
+            control.selected = input.number()?;
+            page.controls.push(control);
+        }
+    }
+    page.index_links();
+    Ok(page)
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn number(&mut self, mut n: usize) {
+        loop {
+            let low = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.bytes.push(low);
+                return;
+            }
+            self.bytes.push(low | 0x80);
+        }
+    }
+    fn string(&mut self, s: &str) {
+        self.number(s.len());
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+    fn optional(&mut self, s: Option<&str>) {
+        match s {
+            Some(s) => {
+                self.bytes.push(1);
+                self.string(s);
+            }
+            None => self.bytes.push(0),
+        }
+    }
+    fn optional_number(&mut self, n: Option<usize>) {
+        match n {
+            Some(n) => {
+                self.bytes.push(1);
+                self.number(n);
+            }
+            None => self.bytes.push(0),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, ArchiveError> {
+        let b = *self.bytes.get(self.pos).ok_or(ArchiveError::Truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+    fn number(&mut self) -> Result<usize, ArchiveError> {
+        let mut n: usize = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            if shift >= usize::BITS {
+                return Err(ArchiveError::Invalid);
+            }
+            n |= ((b & 0x7f) as usize) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+            shift += 7;
+        }
+    }
+    /// A count of items that follow. Each takes at least a byte, so a count
+    /// larger than what is left is corrupt and not worth allocating for.
+    fn count(&mut self) -> Result<usize, ArchiveError> {
+        let n = self.number()?;
+        if n > self.bytes.len() - self.pos {
+            return Err(ArchiveError::Truncated);
+        }
+        Ok(n)
+    }
+    fn string(&mut self) -> Result<String, ArchiveError> {
+        let len = self.count()?;
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ArchiveError::Invalid)
+    }
+    fn optional(&mut self) -> Result<Option<String>, ArchiveError> {
+        match self.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.string()?)),
+            _ => Err(ArchiveError::Invalid),
+        }
+    }
+    fn optional_number(&mut self) -> Result<Option<usize>, ArchiveError> {
+        match self.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.number()?)),
+            _ => Err(ArchiveError::Invalid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::Page;
+
+    const HTML: &str = r#"<html><head><title>Saved</title></head><body>
+        <h1 id="top">Heading</h1>
+        <article><p>Some <b>bold</b> text and <a href="/next">a link</a>.</p></article>
+        <img src="cat.png" alt="A cat">
+        <table><tr><th>Name</th><td>Value</td></tr></table>
+        <pre>line one
+line two</pre>
+        <ol><li>first</li><li>second</li></ol>
+        <form action="/search" method="post"><input name="q" value="rust"><input type="checkbox" name="all" checked>
+        <select name="size"><option value="s">Small</option><option value="l" selected>Large</option></select></form>
+        </body></html>"#;
+
+    fn texts(page: &Page) -> Vec<String> {
+        page.blocks
+            .iter()
+            .map(|block| block.spans.iter().map(|span| span.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let page = Page::from_bytes(HTML.as_bytes(), "http://example.com/dir/page.html");
+        assert!(page.main_content.is_some() && !page.anchors.is_empty() && !page.images.is_empty());
+        assert!(!page.tables.is_empty() && !page.preformatted.is_empty() && !page.indents.is_empty());
+        assert_eq!((page.forms.len(), page.controls.len()), (1, 3));
+        let read = read_page(&write_page(&page)).unwrap();
+        assert_eq!(read.url, page.url);
+        assert_eq!(read.title, page.title);
+        assert_eq!(read.charset, page.charset);
+        assert_eq!(texts(&read), texts(&page));
+        assert_eq!(read.links.len(), page.links.len());
+        assert_eq!(read.anchors.len(), page.anchors.len());
+        assert_eq!(read.main_content, page.main_content);
+        assert_eq!(read.image_sources(), page.image_sources());
+        assert_eq!(read.tables.len(), page.tables.len());
+        assert_eq!(read.preformatted, page.preformatted);
+        assert_eq!(read.indents.len(), page.indents.len());
+        assert_eq!(read.forms.len(), page.forms.len());
+        assert_eq!(read.controls.len(), page.controls.len());
+        for (read, control) in read.controls.iter().zip(&page.controls) {
+            assert_eq!((read.kind, &read.name, &read.value), (control.kind, &control.name, &control.value));
+            assert_eq!((read.checked, read.selected), (control.checked, control.selected));
+        }
+        // writing it again gives the same bytes
+        assert_eq!(write_page(&read), write_page(&page));
+    }
+
+    #[test]
+    fn passwords_are_not_saved() {
+        let html = r#"<form><input name="user" value="ann"><input type="password" name="pw"></form>"#;
+        let mut page = Page::from_bytes(html.as_bytes(), "http://example.com/login");
+        page.controls[1].value = "hunter2secret".into();
+        page.refresh_control(1);
+        let bytes = write_page(&page);
+        assert!(!bytes.windows(7).any(|window| window == b"hunter2"));
+        let read = read_page(&bytes).unwrap();
+        assert_eq!(read.controls[0].value, "ann");
+        assert_eq!(read.controls[1].kind, ControlKind::Password);
+        assert_eq!(read.controls[1].value, "");
+    }
+
+    #[test]
+    fn reads_older_versions() {
+        let mut page = Page::from_bytes(b"<p>Old</p><p>page</p>", "http://example.com/");
+        page.title = Some("Old page".into());
+        let bytes = write_page(&page);
+        // a page without images, tables and the rest ends in one empty count
+        // for each of them, which is exactly what older versions don't have
+        assert!(bytes.ends_with(&[0; 6]));
+        for version in 1..=VERSION {
+            let mut old = bytes[..bytes.len() - (VERSION - version) as usize].to_vec();
+            old[MAGIC.len()] = version;
+            let read = read_page(&old).unwrap();
+            assert_eq!(read.title.as_deref(), Some("Old page"));
+            assert_eq!(texts(&read), ["Old", "page"]);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_archives() {
+        let bytes = write_page(&Page::from_bytes(b"<p>Hello</p>", "http://example.com/"));
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(read_page(&newer), Err(ArchiveError::UnsupportedVersion(_))));
+        assert!(matches!(read_page(b"<html>"), Err(ArchiveError::BadMagic)));
+        for len in MAGIC.len()..bytes.len() {
+            assert!(read_page(&bytes[..len]).is_err(), "read {} of {} bytes", len, bytes.len());
+        }
+    }
+}
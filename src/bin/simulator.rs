@@ -1,6 +1,6 @@
 use embassy_executor::Spawner;
 use embassy_sync::channel::Channel;
-use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embedded_graphics::geometry::{Size};
 use embedded_graphics::mono_font::ascii::{
     FONT_7X13_BOLD,
@@ -17,20 +17,77 @@ use env_logger::Target;
 use iris_ui::geom::Point;
 use iris_ui::input::{InputEvent, TextAction};
 use iris_ui::scene::{click_at, draw_scene, event_at_focused, layout_scene};
-use log::{info, LevelFilter};
+use log::{info, warn, LevelFilter};
 use nostd_browser::browser::{
-    handle_action, load_page, make_gui_scene, update_view_from_keyboard_input, AppState,
-    GuiResponse, NetCommand, LIGHT_THEME, PAGE_VIEW,
+    handle_action, load_image, load_page, make_gui_scene, show_load_status,
+    update_view_from_keyboard_input, AppState, GuiResponse, NetCommand, LIGHT_THEME, PAGE_VIEW,
 };
 use nostd_browser::page::Page;
+use nostd_browser::search::DEFAULT_SEARCH_ENGINE;
 use iris_ui::device::EmbeddedDrawingContext;
 use iris_ui::geom::{Point as GPoint};
 use iris_ui::{Theme, ViewStyle};
-use reqwest::blocking::ClientBuilder;
+use nostd_browser::archive;
+use nostd_browser::content::plain_text_to_page;
+use nostd_browser::cookie::CookieJar;
+use nostd_browser::error::LoadError;
+use nostd_browser::fetch::{fetch_image, PageSink, Session};
+use nostd_browser::form::FormMethod;
+use nostd_browser::gemini;
+use nostd_browser::gemini::{GeminiHeader, KnownHosts, Trust};
+use nostd_browser::gopher;
+use nostd_browser::gopher::GopherRequest;
+use nostd_browser::image::{Bitmap, ImageBudget};
+use nostd_browser::reqwest_fetcher::ReqwestFetcher;
+use nostd_browser::url::Url;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme};
+use std::future::Future;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::pin::pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 static PAGE_BYTES: &[u8] = include_bytes!("homepage.html");
 
-static PAGE_CHANNEL: Channel<ThreadModeRawMutex, Page, 1> = Channel::new();
+// loads run on their own thread, so these use a mutex that works across threads
+/// Pages as (load number, page).
+static PAGE_CHANNEL: Channel<CriticalSectionRawMutex, (u32, Page), 2> = Channel::new();
+
+/// Decoded images as (page url, image url, bitmap).
+static IMAGE_CHANNEL: Channel<CriticalSectionRawMutex, (String, String, Bitmap), 8> = Channel::new();
+
+/// Loads for the page loader thread to do, as (load number, command).
+static NET_COMMANDS: Channel<CriticalSectionRawMutex, (u32, NetCommand), 2> = Channel::new();
+
+/// The number of the latest load asked for. Pages from earlier loads are
+/// thrown away, so the last click wins however slow the ones before it were.
+static LATEST_LOAD: AtomicU32 = AtomicU32::new(0);
+
+/// What the page loader is doing, for the status overlay.
+static LOAD_STATUS: Channel<CriticalSectionRawMutex, String, 4> = Channel::new();
+
+static SAVED_PAGES_DIR: &str = "saved";
+
+static KNOWN_HOSTS: Mutex<KnownHosts> = Mutex::new(KnownHosts::new());
+
+const PAGE_CACHE_BYTES: usize = 4 * 1024 * 1024;
+
+/// How long gemini and gopher servers get to connect and to answer each read
+/// or write. Loads share one thread, so a silent server would hold up every
+/// load after it.
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Set to a file to keep cookies between runs, in the `cookies.txt` format.
+static COOKIES_FILE_VAR: &str = "COOKIES_FILE";
+
+/// Set to a file to keep the pinned gemini certificates between runs.
+static KNOWN_HOSTS_FILE_VAR: &str = "KNOWN_HOSTS_FILE";
 
 #[embassy_executor::main]
 async fn main(spawner:Spawner) {
@@ -39,6 +96,11 @@ async fn main(spawner:Spawner) {
         .filter(None, LevelFilter::Info)
         .init();
 
+    std::thread::Builder::new()
+        .name("page-loader".to_string())
+        .spawn(page_loader)
+        .expect("couldn't start the page loader");
+
     let mut display: SimulatorDisplay<Rgb565> = SimulatorDisplay::new(Size::new(320, 240));
 
 
@@ -71,9 +133,11 @@ async fn main(spawner:Spawner) {
         theme: &LIGHT_THEME,
         font: &embedded_graphics::mono_font::ascii::FONT_7X13,
         bold_font: &FONT_7X13_BOLD,
+        gopher_search: None,
+        search_engine: DEFAULT_SEARCH_ENGINE,
     };
 
-    PAGE_CHANNEL.send(Page::from_bytes(PAGE_BYTES, "homepage.html"));
+    PAGE_CHANNEL.send((0, Page::from_bytes(PAGE_BYTES, "homepage.html")));
 
     'running: loop {
         let mut ctx = EmbeddedDrawingContext::new(&mut display);
@@ -132,8 +196,16 @@ async fn main(spawner:Spawner) {
                 _ => {}
             }
         }
-        if let Ok(page) = PAGE_CHANNEL.try_receive() {
-            load_page(&mut scene, page);
+        while let Ok(status) = LOAD_STATUS.try_receive() {
+            show_load_status(&mut scene, &status);
+        }
+        if let Ok((load, page)) = PAGE_CHANNEL.try_receive() {
+            if is_latest(load) {
+                load_page(&mut scene, page);
+            }
+        }
+        while let Ok((page_url, src, bitmap)) = IMAGE_CHANNEL.try_receive() {
+            load_image(&mut scene, &page_url, &src, bitmap);
         }
     }
 }
@@ -141,23 +213,406 @@ async fn main(spawner:Spawner) {
 async fn handle_gui_response(gui_response: GuiResponse, _app: &mut AppState) {
     match gui_response {
         GuiResponse::Net(net) => {
-            match net {
-                NetCommand::Load(href) => {
-                    let client = ClientBuilder::new()
-                        .use_rustls_tls()
-                        .build()
-                        .unwrap();
-                    let res = client.get(&href).send().unwrap();
-                    let bytes = res.bytes().unwrap();
-                    let page = Page::from_bytes(&bytes, &href);
-                    info!("got result bytes {:?}", page);
-                    PAGE_CHANNEL.send(page).await;
-                }
+            let load = LATEST_LOAD.fetch_add(1, Ordering::SeqCst) + 1;
+            // the loads still waiting are out of date, and the loader may be
+            // busy, so make room rather than wait for it
+            while let Ok((_, old)) = NET_COMMANDS.try_receive() {
+                info!("replacing {:?}", old);
+            }
+            if let Err(err) = NET_COMMANDS.try_send((load, net)) {
+                warn!("couldn't queue {:?}", err);
+            }
+        }
+        GuiResponse::SavePage(url, bytes) => {
+            let path = archive_path(&url);
+            match std::fs::create_dir_all(SAVED_PAGES_DIR).and_then(|_| std::fs::write(&path, &bytes)) {
+                Ok(()) => info!("saved {} to {}", url, path),
+                Err(err) => warn!("couldn't save {}: {}", url, err),
+            }
+        }
+    }
+}
+
+/// Does the loads on a thread of its own, since the network calls block,
+/// so the window keeps drawing and scrolling while a page downloads.
+/// Pages go back to the window over [`PAGE_CHANNEL`].
+fn page_loader() {
+    let mut session = Session::new(PAGE_CACHE_BYTES);
+    if let Ok(path) = std::env::var(COOKIES_FILE_VAR) {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => session.cookies.load_text(&text, unix_time()),
+            Err(err) => warn!("couldn't read the cookies from {}: {}", path, err),
+        }
+    }
+    if let Ok(path) = std::env::var(KNOWN_HOSTS_FILE_VAR) {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => KNOWN_HOSTS.lock().unwrap().load_text(&text),
+            Err(err) => warn!("couldn't read the known hosts from {}: {}", path, err),
+        }
+    }
+    block_on(async {
+        loop {
+            let (load, command) = NET_COMMANDS.receive().await;
+            if is_latest(load) {
+                handle_net_command(load, command, &mut session).await;
+            }
+        }
+    });
+}
+
+async fn handle_net_command(load: u32, command: NetCommand, session: &mut Session) {
+    match command {
+        NetCommand::Load(href) => {
+            show_loading(&href);
+            if let Some(url) = gemini::parse_trust(&href) {
+                PAGE_CHANNEL.send((load, trust_and_load_gemini(url))).await;
+                return;
+            }
+            if href.starts_with("gemini:") {
+                PAGE_CHANNEL.send((load, load_gemini(&href))).await;
+                return;
+            }
+            if href.starts_with("gopher:") {
+                PAGE_CHANNEL.send((load, load_gopher(&href))).await;
+                return;
+            }
+            if href.starts_with("file:") {
+                PAGE_CHANNEL.send((load, load_file(&href))).await;
+                return;
             }
+            load_http(load, &href, None, session).await;
+        }
+        NetCommand::Submit(submission) => {
+            let url = submission.url();
+            show_loading(&url);
+            match submission.method {
+                FormMethod::Get => load_http(load, &url, None, session).await,
+                FormMethod::Post => load_http(load, &url, Some(&submission.body), session).await,
+            }
+        }
+    }
+}
+
+/// Whether `load` is the one the user asked for last.
+fn is_latest(load: u32) -> bool {
+    LATEST_LOAD.load(Ordering::SeqCst) == load
+}
+
+fn show_loading(href: &str) {
+    // only the latest status matters, so a full channel is fine
+    LOAD_STATUS.try_send(format!("Loading {}", href)).ok();
+}
+
+/// Wakes the page loader thread when what it waits on is ready.
+struct ThreadWaker(std::thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future to the end on the current thread, parking it while the
+/// future waits.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
         }
+        std::thread::park();
+    }
+}
+
+/// Loads a web page, showing a preview while it downloads, then fetches its
+/// images. A `form_body` is sent as a URL encoded POST. A load that fails
+/// shows an error page instead.
+async fn load_http(load: u32, href: &str, form_body: Option<&str>, session: &mut Session) {
+    if let Err(err) = fetch_http(load, href, form_body, session).await {
+        warn!("loading {} failed: {:?}", href, err);
+        PAGE_CHANNEL.send((load, err.to_page(href))).await;
     }
 }
 
+async fn fetch_http(load: u32, href: &str, form_body: Option<&str>, session: &mut Session) -> Result<(), LoadError> {
+    let mut fetcher = ReqwestFetcher::new()?;
+    let (page_url, sources) = session
+        .load(&mut fetcher, href, form_body, unix_time(), &mut PageChannel(load))
+        .await?;
+    save_cookies(&session.cookies);
+    load_images(load, &mut fetcher, &page_url, &sources).await;
+    Ok(())
+}
+
+/// Hands the pages of a load to the GUI.
+struct PageChannel(u32);
+
+impl PageSink for PageChannel {
+    async fn show(&mut self, page: Page) {
+        PAGE_CHANNEL.send((self.0, page)).await;
+    }
+}
+
+/// Saves the pinned certificates if they are kept between runs.
+fn save_known_hosts(hosts: &KnownHosts) {
+    if let Ok(path) = std::env::var(KNOWN_HOSTS_FILE_VAR) {
+        if let Err(err) = std::fs::write(&path, hosts.to_text()) {
+            warn!("couldn't save the known hosts to {}: {}", path, err);
+        }
+    }
+}
+
+/// Saves the cookies if they are kept between runs.
+fn save_cookies(jar: &CookieJar) {
+    if let Ok(path) = std::env::var(COOKIES_FILE_VAR) {
+        if let Err(err) = std::fs::write(&path, jar.to_text()) {
+            warn!("couldn't save the cookies to {}: {}", path, err);
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Fetches and decodes the images of a page. Images that fail to load or
+/// don't fit the budget keep showing their alt text. Stops once another load
+/// has been asked for.
+async fn load_images(load: u32, fetcher: &mut ReqwestFetcher, page_url: &str, sources: &[String]) {
+    let budget = ImageBudget::default();
+    // bytes of bitmaps already sent for this page
+    let mut used = 0;
+    for src in sources.iter().take(budget.max_images) {
+        if !is_latest(load) {
+            break;
+        }
+        if !(src.starts_with("http:") || src.starts_with("https:")) {
+            continue;
+        }
+        let bitmap = match fetch_image(fetcher, src, &budget.remaining(used)).await {
+            Some(bitmap) => bitmap,
+            None => {
+                warn!("couldn't load the image {}", src);
+                continue;
+            }
+        };
+        info!("loaded image {} {:?}", src, bitmap);
+        used += bitmap.byte_size();
+        if IMAGE_CHANNEL.try_send((page_url.to_string(), src.clone(), bitmap)).is_err() {
+            warn!("too many images waiting, dropping {}", src);
+        }
+    }
+}
+
+/// Saved pages go in a file named after their URL, and open again as `file:<path>`.
+/// Long URLs are cut short, so a hash of the whole URL keeps pages that start
+/// the same apart.
+fn archive_path(url: &str) -> String {
+    let name: String = url
+        .chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+        .take(64)
+        .collect();
+    // FNV-1a, which unlike the std hashers is the same on every run and build
+    let hash = url.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{}/{}_{:016x}.{}", SAVED_PAGES_DIR, name, hash, archive::EXTENSION)
+}
+
+fn load_file(href: &str) -> Page {
+    let path = href.trim_start_matches("file:");
+    let path = path.strip_prefix("//").unwrap_or(path);
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => return plain_text_to_page(&format!("Can't open {}: {}", path, err), href),
+    };
+    if !archive::is_archive(&bytes) {
+        return Page::from_response(&bytes, href, None);
+    }
+    match archive::read_page(&bytes) {
+        Ok(page) => page,
+        Err(err) => plain_text_to_page(&format!("Can't read the saved page {}: {:?}", path, err), href),
+    }
+}
+
+/// Accepts any certificate during the handshake. Whether to trust it is decided
+/// afterwards against [`KNOWN_HOSTS`], since capsules use self signed certificates.
+#[derive(Debug)]
+struct TofuVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for TofuVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Why a gemini request failed.
+enum GeminiError {
+    /// The host presented a different certificate than the one pinned for it.
+    Changed(String),
+    Failed(String),
+}
+
+impl From<String> for GeminiError {
+    fn from(err: String) -> Self {
+        GeminiError::Failed(err)
+    }
+}
+
+/// Forgets the pinned certificate of the capsule at `href`, after the user
+/// chose to trust the one it has now, and loads it.
+fn trust_and_load_gemini(href: &str) -> Page {
+    if let Some(url) = Url::parse(href) {
+        if let Some(host) = &url.host {
+            let mut hosts = KNOWN_HOSTS.lock().unwrap();
+            hosts.forget(host, url.port.unwrap_or(gemini::DEFAULT_PORT));
+            save_known_hosts(&hosts);
+        }
+    }
+    load_gemini(href)
+}
+
+fn load_gemini(href: &str) -> Page {
+    let mut href = href.to_string();
+    for _ in 0..=gemini::MAX_REDIRECTS {
+        let (header, body) = match gemini_request(&href) {
+            Ok(response) => response,
+            Err(GeminiError::Changed(host)) => return gemini::changed_certificate_page(&href, &host),
+            Err(GeminiError::Failed(err)) => {
+                let header = GeminiHeader { status: 40, meta: err };
+                return gemini::page_from_response(&href, &header, &[]);
+            }
+        };
+        if !header.is_redirect() {
+            return gemini::page_from_response(&href, &header, &body);
+        }
+        info!("gemini redirect to {}", header.meta);
+        href = match Url::parse(&href).and_then(|url| url.join(&header.meta)) {
+            Some(url) => url.to_string(),
+            None => header.meta,
+        };
+    }
+    let header = GeminiHeader { status: 40, meta: "Too many redirects".to_string() };
+    gemini::page_from_response(&href, &header, &[])
+}
+
+fn gemini_request(href: &str) -> Result<(GeminiHeader, Vec<u8>), GeminiError> {
+    let url = Url::parse(href).ok_or_else(|| format!("Invalid URL {}", href))?;
+    let host = url.host.clone().ok_or_else(|| format!("No host in {}", href))?;
+    let port = url.port.unwrap_or(gemini::DEFAULT_PORT);
+    let provider = Arc::new(ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(TofuVerifier(provider)))
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(host.clone()).map_err(|err| err.to_string())?;
+    let mut conn = ClientConnection::new(Arc::new(config), server_name).map_err(|err| err.to_string())?;
+    let mut sock = connect(&host, port).map_err(|err| err.to_string())?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut sock).map_err(|err| err.to_string())?;
+    }
+    let certificate = conn
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .ok_or_else(|| format!("{} sent no certificate", host))?;
+    let mut hosts = KNOWN_HOSTS.lock().unwrap();
+    match hosts.check(&host, port, certificate.as_ref()) {
+        Trust::New => {
+            info!("pinned the certificate for {}:{}", host, port);
+            save_known_hosts(&hosts);
+        }
+        Trust::Trusted => {}
+        Trust::Changed => return Err(GeminiError::Changed(host)),
+    }
+    drop(hosts);
+    let mut tls = rustls::Stream::new(&mut conn, &mut sock);
+    tls.write_all(gemini::request_line(&url).as_bytes())
+        .map_err(|err| err.to_string())?;
+    let mut response = vec![];
+    match tls.take(gemini::MAX_RESPONSE as u64).read_to_end(&mut response) {
+        Ok(_) => {}
+        // plenty of servers close the connection without a close_notify
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => {}
+        Err(err) => return Err(err.to_string().into()),
+    }
+    let (header, body) =
+        gemini::split_header(&response).ok_or_else(|| "Invalid response header".to_string())?;
+    Ok((header, body.to_vec()))
+}
+
+/// Connects to the first address of `host` that answers, with reads and writes
+/// that give up after [`NETWORK_TIMEOUT`].
+fn connect(host: &str, port: u16) -> std::io::Result<TcpStream> {
+    let mut last_err = std::io::Error::new(ErrorKind::NotFound, format!("{} has no addresses", host));
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, NETWORK_TIMEOUT) {
+            Ok(sock) => {
+                sock.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+                sock.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+                return Ok(sock);
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+fn load_gopher(href: &str) -> Page {
+    match gopher_request(href) {
+        Ok(page) => page,
+        Err(err) => {
+            warn!("gopher request for {} failed: {}", href, err);
+            plain_text_to_page(&err, href)
+        }
+    }
+}
+
+fn gopher_request(href: &str) -> Result<Page, String> {
+    let url = Url::parse(href).ok_or_else(|| format!("Invalid URL {}", href))?;
+    let request = GopherRequest::from_url(&url).ok_or_else(|| format!("Invalid gopher URL {}", href))?;
+    let mut sock = connect(&request.host, request.port).map_err(|err| err.to_string())?;
+    sock.write_all(request.request_line().as_bytes())
+        .map_err(|err| err.to_string())?;
+    let mut body = vec![];
+    sock.take(gopher::MAX_RESPONSE as u64)
+        .read_to_end(&mut body)
+        .map_err(|err| err.to_string())?;
+    Ok(gopher::page_from_response(href, &request, &body))
+}
+
 fn keydown_to_char(keycode: Keycode, keymod: Mod) -> TextAction {
     println!("keycode as number {}", keycode.into_i32());
     let ch = keycode.into_i32();
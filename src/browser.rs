@@ -1,9 +1,16 @@
 // use crate::common::{NetCommand, NET_COMMANDS};
+use crate::archive::write_page;
 use crate::comps::make_overlay_label;
+use crate::form::FormSubmission;
+use crate::gopher;
+use crate::image::Bitmap;
 use crate::page::Page;
-use crate::pageview::PageView;
+use crate::pageview::{PageView, FIND_COMMAND, SUBMIT_FORM_COMMAND};
+use crate::search::{url_for_input, SearchEngine, SEARCH_ENGINES};
 use alloc::boxed::Box;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use alloc::{format, vec};
 use embedded_graphics::mono_font::ascii::{
     FONT_6X13, FONT_6X13_BOLD, FONT_7X13_BOLD, FONT_9X15, FONT_9X15_BOLD,
@@ -41,6 +48,9 @@ const INFO_PANEL: &'static ViewId = &ViewId::new("info-panel");
 const INFO_BUTTON: &'static ViewId = &ViewId::new("info-button");
 
 const URL_PANEL: &'static ViewId = &ViewId::new("url-panel");
+const FIND_PANEL: &'static ViewId = &ViewId::new("find-panel");
+const SEARCH_PANEL: &'static ViewId = &ViewId::new("search-panel");
+const OVERLAY_STATUS: &'static ViewId = &ViewId::new("overlay-status");
 
 pub const BASE_FONT: MonoFont = FONT_9X15;
 pub const BOLD_FONT: MonoFont = FONT_9X15_BOLD;
@@ -80,15 +90,23 @@ pub const ACTIVE_THEME: Option<Box<&AppTheme>> = None;
 #[derive(Debug)]
 pub enum NetCommand {
     Load(String),
+    /// a filled in form to send to its action URL
+    Submit(FormSubmission),
 }
 
 #[derive(Debug)]
 pub enum GuiResponse {
     Net(NetCommand),
+    /// an archive of the current page to write to storage, and the page's URL
+    SavePage(String, Vec<u8>),
 }
 
 const CANCEL_URL_COMMAND:&'static str = "cancel-url";
 const LOAD_URL_COMMAND:&'static str = "load-url";
+const CANCEL_FIND_COMMAND:&'static str = "cancel-find";
+const FIND_TEXT_COMMAND:&'static str = "find-text";
+const CANCEL_SEARCH_COMMAND:&'static str = "cancel-search";
+const SEARCH_COMMAND:&'static str = "search";
 const CLOSE_SETTINGS_COMMAND:&'static str = "settings-close-button";
 const OPEN_FONT_SETTINGS_COMMAND:&'static str = "settings-font-button";
 
@@ -99,6 +117,10 @@ pub struct AppState {
     pub theme: &'static AppTheme,
     pub font: &'static MonoFont<'static>,
     pub bold_font: &'static MonoFont<'static>,
+    /// the gopher search item the search panel is asking a query for
+    pub gopher_search: Option<String>,
+    /// what text typed in the URL bar is searched with
+    pub search_engine: &'static SearchEngine,
 }
 pub fn handle_action(
     result:&InputResult,
@@ -109,21 +131,36 @@ pub fn handle_action(
     match &result.action {
         Some(OutputAction::Command(cmd)) => {
             let url_input = ViewId::new("url-input");
+            let find_input = ViewId::new("find-input");
+            let search_input = ViewId::new("search-input");
             match cmd.as_str() {
                 CANCEL_URL_COMMAND => {
                     scene.remove_parent_and_children(URL_PANEL);
                     scene.set_focused(PAGE_VIEW);
                 },
                 LOAD_URL_COMMAND => {
-                    let result = if let Some(view) = scene.get_view(&url_input) {
-                        Some(GuiResponse::Net(NetCommand::Load(view.title.to_string())))
-                    } else {
-                        None
-                    };
+                    let result = scene
+                        .get_view(&url_input)
+                        .and_then(|view| url_for_input(&view.title, app.search_engine))
+                        .map(|url| GuiResponse::Net(NetCommand::Load(url)));
                     scene.remove_parent_and_children(URL_PANEL);
                     scene.set_focused(PAGE_VIEW);
                     return result;
                 },
+                CANCEL_FIND_COMMAND => {
+                    scene.remove_parent_and_children(FIND_PANEL);
+                    scene.set_focused(PAGE_VIEW);
+                },
+                FIND_TEXT_COMMAND => {
+                    find_in_page(scene);
+                    return None;
+                },
+                CANCEL_SEARCH_COMMAND => {
+                    app.gopher_search = None;
+                    scene.remove_parent_and_children(SEARCH_PANEL);
+                    scene.set_focused(PAGE_VIEW);
+                },
+                SEARCH_COMMAND => return search_gopher(scene, app),
                 CLOSE_SETTINGS_COMMAND => {
                     scene.remove_parent_and_children(SETTINGS_PANEL);
                     scene.set_focused(PAGE_VIEW);
@@ -156,7 +193,7 @@ pub fn handle_action(
                     "Network" => show_wifi_panel(scene),
                     "Settings" => {
                         scene.hide_view(MAIN_MENU);
-                        show_settings_panel(scene);
+                        show_settings_panel(scene, app);
                     }
                     "Info" => show_info_panel(scene),
                     "close" => {
@@ -173,6 +210,9 @@ pub fn handle_action(
                     "Open URL" => {
                         show_url_panel(scene);
                     }
+                    "Find" => {
+                        show_find_panel(scene);
+                    }
                     "Bookmarks" => {
                         // show the bookmarks
                         scene.hide_view(MAIN_MENU);
@@ -188,6 +228,26 @@ pub fn handle_action(
                         if let Some(state) = scene.get_view_state::<PageView>(PAGE_VIEW) {
                             state.prev_page();
                         }
+                        show_page_title(scene);
+                        scene.set_focused(PAGE_VIEW);
+                    }
+                    "Save page" => {
+                        scene.hide_view(MAIN_MENU);
+                        scene.hide_view(BROWSER_MENU);
+                        scene.set_focused(PAGE_VIEW);
+                        if let Some(page_view) = scene.get_view_state::<PageView>(PAGE_VIEW) {
+                            let page = page_view.current_page();
+                            return Some(GuiResponse::SavePage(page.url.clone(), write_page(page)));
+                        }
+                    }
+                    "Reader" => {
+                        scene.hide_view(MAIN_MENU);
+                        scene.hide_view(BROWSER_MENU);
+                        if let Some(page_view) = scene.get_view_state::<PageView>(PAGE_VIEW) {
+                            page_view.set_reader_mode(!page_view.reader_mode);
+                        }
+                        scene.mark_dirty_view(PAGE_VIEW);
+                        show_page_title(scene);
                         scene.set_focused(PAGE_VIEW);
                     }
                     "Forward" => {
@@ -196,6 +256,7 @@ pub fn handle_action(
                         if let Some(page_view) = scene.get_view_state::<PageView>(PAGE_VIEW) {
                             page_view.next_page();
                         }
+                        show_page_title(scene);
                         scene.set_focused(PAGE_VIEW);
                     }
                     "close" => {
@@ -225,10 +286,18 @@ pub fn handle_action(
                 scene.hide_view(MAIN_MENU);
                 scene.hide_view(BROWSER_MENU);
                 scene.set_focused(PAGE_VIEW);
-                if let Some(view) = scene.get_view_mut(&url_input) {
-                    return Some(GuiResponse::Net(NetCommand::Load(view.title.to_string())));
+                if let Some(view) = scene.get_view(&url_input) {
+                    return url_for_input(&view.title, app.search_engine)
+                        .map(|url| GuiResponse::Net(NetCommand::Load(url)));
                 }
             }
+            if result.source == find_input {
+                find_in_page(scene);
+                return None;
+            }
+            if result.source == search_input {
+                return search_gopher(scene, app);
+            }
             if result.source == ViewId::new("settings-theme") {
                 if cmd == "Dark" {
                     app.theme = &DARK_THEME;
@@ -239,6 +308,11 @@ pub fn handle_action(
                     scene.mark_dirty_all();
                 }
             }
+            if result.source == ViewId::new("settings-search") {
+                if let Some(engine) = SEARCH_ENGINES.iter().find(|engine| engine.name == cmd) {
+                    app.search_engine = engine;
+                }
+            }
             let font_menu = ViewId::new("font-menu");
             if result.source == ViewId::new("font-menu") {
                 match cmd.as_str() {
@@ -266,6 +340,19 @@ pub fn handle_action(
                 }
             }
             if result.source == *PAGE_VIEW {
+                if cmd == FIND_COMMAND {
+                    show_find_panel(scene);
+                    return None;
+                }
+                if cmd == SUBMIT_FORM_COMMAND {
+                    let state = scene.get_view_state::<PageView>(PAGE_VIEW)?;
+                    return state.take_submission().map(|submission| GuiResponse::Net(NetCommand::Submit(submission)));
+                }
+                if gopher::needs_query(cmd) {
+                    app.gopher_search = Some(cmd.to_string());
+                    show_search_panel(scene);
+                    return None;
+                }
                 return Some(GuiResponse::Net(NetCommand::Load(cmd.to_string())));
             }
         }
@@ -297,6 +384,61 @@ fn show_url_panel(scene: &mut Scene) {
     scene.hide_view(BROWSER_MENU);
     scene.set_focused(&ViewId::new("url-input"));
 }
+fn show_find_panel(scene: &mut Scene) {
+    let panel = make_panel(FIND_PANEL)
+        .with_layout(Some(layout_vbox))
+        .with_flex(Intrinsic, Intrinsic)
+        .with_bounds(Bounds::new(20, 60, 320 - 40, 120));
+    scene.add_view_to_parent(make_label("find-label", "Find in page"), &panel.name);
+    let input = make_text_input("find-input", "").with_flex(Resize, Intrinsic);
+    scene.add_view_to_parent(input, &panel.name);
+    add_command_button_to(scene, "Cancel", CANCEL_FIND_COMMAND, &panel.name);
+    add_command_button_to(scene, "Find", FIND_TEXT_COMMAND, &panel.name);
+    scene.add_view_to_root(panel);
+    scene.hide_view(MAIN_MENU);
+    scene.hide_view(BROWSER_MENU);
+    scene.set_focused(&ViewId::new("find-input"));
+}
+fn show_search_panel(scene: &mut Scene) {
+    let panel = make_panel(SEARCH_PANEL)
+        .with_layout(Some(layout_vbox))
+        .with_flex(Intrinsic, Intrinsic)
+        .with_bounds(Bounds::new(20, 60, 320 - 40, 120));
+    scene.add_view_to_parent(make_label("search-label", "Search for"), &panel.name);
+    let input = make_text_input("search-input", "").with_flex(Resize, Intrinsic);
+    scene.add_view_to_parent(input, &panel.name);
+    add_command_button_to(scene, "Cancel", CANCEL_SEARCH_COMMAND, &panel.name);
+    add_command_button_to(scene, "Search", SEARCH_COMMAND, &panel.name);
+    scene.add_view_to_root(panel);
+    scene.set_focused(&ViewId::new("search-input"));
+}
+/// Closes the search panel and loads the gopher search it was opened for
+/// with what was typed in it.
+fn search_gopher(scene: &mut Scene, app: &mut AppState) -> Option<GuiResponse> {
+    let query = scene.get_view(&ViewId::new("search-input"))?.title.to_string();
+    scene.remove_parent_and_children(SEARCH_PANEL);
+    scene.set_focused(PAGE_VIEW);
+    let href = app.gopher_search.take()?;
+    Some(GuiResponse::Net(NetCommand::Load(gopher::search_url(&href, query.trim()))))
+}
+/// Searches the page for what was typed in the find panel and closes it.
+fn find_in_page(scene: &mut Scene) {
+    let query = match scene.get_view(&ViewId::new("find-input")) {
+        Some(view) => view.title.to_string(),
+        None => return,
+    };
+    scene.remove_parent_and_children(FIND_PANEL);
+    scene.set_focused(PAGE_VIEW);
+    let found = scene
+        .get_view_state::<PageView>(PAGE_VIEW)
+        .map(|state| state.find(query.trim()));
+    scene.mark_dirty_view(PAGE_VIEW);
+    if found == Some(0) && !query.trim().is_empty() {
+        show_status(scene, "Not found");
+    } else {
+        show_find_status(scene);
+    }
+}
 fn show_info_panel(scene: &mut Scene) {
     info!("showing the info panel");
     let panel_bounds = Bounds::new(20, 20, 320 - 40, 240 - 40);
@@ -366,7 +508,7 @@ fn show_wifi_panel(scene: &mut Scene) {
     scene.add_view_to_root(panel);
     scene.hide_view(MAIN_MENU);
 }
-fn show_settings_panel(scene: &mut Scene) {
+fn show_settings_panel(scene: &mut Scene, app: &AppState) {
     info!("showing settings panel");
     let mut panel = make_panel(SETTINGS_PANEL)
         .with_bounds(Bounds::new(20, 20, 320 - 60, 240 - 40-40))
@@ -387,6 +529,20 @@ fn show_settings_panel(scene: &mut Scene) {
         &panel.name,
     );
     add_command_button_to(scene, "Small", OPEN_FONT_SETTINGS_COMMAND, &panel.name);
+    scene.add_view_to_parent(
+        make_label("settings-search-label", "Search"),
+        &panel.name,
+    );
+    let engines = SEARCH_ENGINES.iter().map(|engine| engine.name).collect();
+    let selected = SEARCH_ENGINES
+        .iter()
+        .position(|engine| engine == app.search_engine)
+        .unwrap_or(0);
+    scene.add_view_to_parent(
+        make_toggle_group(&ViewId::new("settings-search"), engines, selected)
+            .with_flex(Resize, Intrinsic),
+        &panel.name,
+    );
     add_command_button_to(scene, "Closey", CLOSE_SETTINGS_COMMAND, &panel.name);
 
     scene.add_view_to_root(panel);
@@ -422,16 +578,23 @@ pub fn make_gui_scene() -> Scene {
             .with_visible(false)
     );
 
+    let mut browser_items = vec![
+        "Bookmarks",
+        "SDCard",
+        "Open URL",
+        "Find",
+        "Back",
+        "Forward",
+        "Reader",
+    ];
+    // saved pages go to the file system, which only the std build has
+    if cfg!(feature = "std") {
+        browser_items.push("Save page");
+    }
+    browser_items.push("close");
     let browser_menu = make_list_view(
         BROWSER_MENU,
-        vec![
-            "Bookmarks",
-            "SDCard",
-            "Open URL",
-            "Back",
-            "Forward",
-            "close",
-        ],
+        browser_items,
         0,
     )
     .position_at(20, 20)
@@ -453,12 +616,8 @@ pub fn make_gui_scene() -> Scene {
             BlockType::Paragraph,
             "This is some long body text that needs to be broken into multiple lines",
         ));
-        let page = Page {
-            selection: 0,
-            blocks,
-            links: vec![],
-            url: "".to_string(),
-        };
+        let mut page = Page::new();
+        page.blocks = blocks;
         page_view.load_page(page);
     }
 
@@ -471,7 +630,7 @@ pub fn make_gui_scene() -> Scene {
 pub fn update_view_from_keyboard_input(scene: &mut Scene, evt: &TextAction) {
     match evt {
         TextAction::TypedAscii(key ) => {
-            if *key == b' ' {
+            if *key == b' ' && !is_editing_form(scene) {
                 if scene.is_visible(MAIN_MENU) == false && scene.is_focused(PAGE_VIEW) {
                     scene.show_view(MAIN_MENU);
                     scene.set_focused(MAIN_MENU);
@@ -508,4 +667,58 @@ pub fn load_page(scene: &mut Scene, page: Page) {
         state.load_page(page);
     }
     scene.mark_dirty_view(PAGE_VIEW);
+    show_page_title(scene);
+}
+
+/// Shows which match of a find is current, or the page title again once
+/// the find is over.
+pub(crate) fn show_find_status(scene: &mut Scene) {
+    let status = match scene.get_view_state::<PageView>(PAGE_VIEW) {
+        Some(state) => state.find_status(),
+        None => return,
+    };
+    let Some((current, count)) = status else {
+        show_page_title(scene);
+        return;
+    };
+    show_status(scene, &format!("{} of {}", current, count));
+}
+
+fn show_page_title(scene: &mut Scene) {
+    let title = match scene.get_view_state::<PageView>(PAGE_VIEW) {
+        Some(state) => state.current_title().to_string(),
+        None => return,
+    };
+    show_status(scene, &title);
+}
+
+/// Shows what the network is doing, like which page is loading. The page
+/// title comes back when the page arrives.
+pub fn show_load_status(scene: &mut Scene, text: &str) {
+    show_status(scene, text);
+}
+
+fn show_status(scene: &mut Scene, text: &str) {
+    if let Some(overlay) = scene.get_view_mut(OVERLAY_STATUS) {
+        overlay.title = text.into();
+    }
+    scene.mark_dirty_view(OVERLAY_STATUS);
+}
+
+/// True while typing goes into a form field on the page, so keys like space
+/// are text rather than shortcuts.
+pub fn is_editing_form(scene: &mut Scene) -> bool {
+    scene.is_focused(PAGE_VIEW)
+        && scene
+            .get_view_state::<PageView>(PAGE_VIEW)
+            .is_some_and(|state| state.editing)
+}
+
+/// Shows a decoded image on the page it was fetched for, if that page is still in the history.
+pub fn load_image(scene: &mut Scene, page_url: &str, src: &str, bitmap: Bitmap) {
+    if let Some(state) = scene.get_view_state::<PageView>(PAGE_VIEW) {
+        if state.set_image(page_url, src, &Arc::new(bitmap)) {
+            scene.mark_dirty_view(PAGE_VIEW);
+        }
+    }
 }
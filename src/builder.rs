@@ -0,0 +1,1171 @@
+use crate::charset;
+use crate::charset::{Decoder, PRESCAN_LENGTH};
+use crate::content::{image_page, plain_text_to_page, unsupported_page, ContentKind};
+use crate::form::{input_control, select_control, textarea_control, ControlKind, Form, FormControl, FormMethod};
+use crate::gemini::gemtext_to_page;
+use crate::html::{charset_from_content_type, preformatted_lines, scan_head, RawTag, TagScanner};
+use crate::markdown::markdown_to_page;
+use crate::page::{Anchor, Indent, Page, PageImage, PageTable, TableCell, MAX_INDENT};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use log::{info, warn};
+use nostd_html_parser::blocks::{Block, BlockParser, BlockType};
+use nostd_html_parser::tags::TagParser;
+
+/// How many blocks make a first screenful worth showing while the rest loads.
+const PREVIEW_BLOCKS: usize = 16;
+
+/// Large pushes are worked through in pieces this big, so a whole document
+/// handed over at once is never copied in one go.
+const PUSH_SLICE: usize = 2048;
+
+/// Links to the rest of a truncated page look like `page-part:<offset>:<url>`.
+const PAGE_PART_PREFIX: &str = "page-part:";
+
+/// How many blocks apart the places a page can be resumed from are kept.
+const PART_SPACING: usize = 16;
+
+/// Inline elements whose style carries on into the blocks inside them.
+const INLINE_STYLE_TAGS: [&str; 7] = ["a", "b", "strong", "i", "em", "code", "tt"];
+
+/// Inline elements left open past a block boundary, beyond this many are dropped.
+const MAX_OPEN_INLINE: usize = 8;
+
+/// Limits on how much of a page we hold in memory. Pages that go over are cut
+/// off at a block boundary and end with a link to load the next part.
+#[derive(Debug, Clone, Copy)]
+pub struct PageBudget {
+    pub max_blocks: usize,
+    /// bytes of text across all blocks
+    pub max_text: usize,
+    /// lines produced by line breaking in the page view
+    pub max_lines: usize,
+}
+
+impl Default for PageBudget {
+    fn default() -> Self {
+        PageBudget {
+            max_blocks: 300,
+            max_text: 24 * 1024,
+            max_lines: 800,
+        }
+    }
+}
+
+/// The href of the link that loads a page starting at `offset`.
+pub fn page_part_href(url: &str, offset: usize) -> String {
+    format!("{}{}:{}", PAGE_PART_PREFIX, offset, url)
+}
+
+/// Splits a `page-part:` href into the page URL and the offset to resume at.
+pub fn parse_page_part(href: &str) -> Option<(&str, usize)> {
+    let rest = href.strip_prefix(PAGE_PART_PREFIX)?;
+    let (offset, url) = rest.split_once(':')?;
+    Some((url, offset.parse().ok()?))
+}
+
+/// The blocks of an element like `<article>` that may contain other block elements.
+struct Region {
+    start: usize,
+    end: Option<usize>,
+    /// how many of the element are open, for nested articles
+    depth: usize,
+}
+
+impl Region {
+    fn open(region: &mut Option<Region>, block: usize) {
+        match region {
+            None => {
+                *region = Some(Region {
+                    start: block,
+                    end: None,
+                    depth: 1,
+                })
+            }
+            Some(region) if region.end.is_none() => region.depth += 1,
+            Some(_) => {}
+        }
+    }
+    fn close(region: &mut Option<Region>, block: usize) {
+        if let Some(region) = region {
+            if region.end.is_none() {
+                region.depth -= 1;
+                if region.depth == 0 {
+                    region.end = Some(block);
+                }
+            }
+        }
+    }
+    /// The blocks of the element, if it has any. One left open runs to `last`.
+    fn range(region: &Option<Region>, last: usize) -> Option<Range<usize>> {
+        let region = region.as_ref()?;
+        let range = region.start..region.end.unwrap_or(last);
+        if range.is_empty() {
+            None
+        } else {
+            Some(range)
+        }
+    }
+}
+
+/// A list or block quote the parser is inside of.
+enum Container {
+    /// `next` is the number of the next item of an ordered list
+    List { next: Option<u32> },
+    Quote,
+}
+
+/// Collects the rows and cells of the innermost open `<table>`. A table that
+/// turns out to hold another one was only there for layout, so it is dropped
+/// and its cells stay ordinary blocks.
+struct TableBuilder {
+    /// the table nesting level this one was opened at
+    depth: usize,
+    rows: Vec<Vec<TableCell>>,
+    row: Option<Vec<TableCell>>,
+    /// the first block and the header flag of the open cell
+    cell: Option<(usize, bool)>,
+}
+
+impl TableBuilder {
+    fn close_cell(&mut self, block: usize) {
+        if let Some((start, header)) = self.cell.take() {
+            self.row.get_or_insert_with(Vec::new).push(TableCell {
+                blocks: start..block,
+                header,
+            });
+        }
+    }
+    fn close_row(&mut self, block: usize) {
+        self.close_cell(block);
+        if let Some(row) = self.row.take() {
+            if !row.is_empty() {
+                self.rows.push(row);
+            }
+        }
+    }
+    /// The finished table, unless it has no content at all.
+    fn finish(mut self, block: usize) -> Option<PageTable> {
+        self.close_row(block);
+        let table = PageTable { rows: self.rows };
+        if table.blocks().is_empty() {
+            None
+        } else {
+            Some(table)
+        }
+    }
+}
+
+/// Builds a [`Page`] from a response body that arrives in chunks.
+///
+/// Each chunk is transcoded to UTF-8 and every complete block level element is
+/// run through the tag and block parsers right away, so only the unfinished tail
+/// of the document is buffered. Call [`PageBuilder::preview`] after each push to
+/// get an early copy of the page to show while the download continues.
+///
+/// Plain text, Markdown and gemtext are buffered whole and converted in
+/// [`PageBuilder::finish`].
+pub struct PageBuilder {
+    page: Page,
+    kind: ContentKind,
+    /// the type from the `Content-Type` header, for the page shown when it
+    /// can't be displayed
+    mime: Option<String>,
+    charset_hint: Option<String>,
+    decoder: Option<Decoder>,
+    /// bytes waiting for the encoding to be worked out
+    raw: Vec<u8>,
+    /// decoded text that has not been parsed yet. Always starts on a block boundary.
+    text: Vec<u8>,
+    /// anchors waiting for the next block to be produced
+    pending_anchors: Vec<String>,
+    in_body: bool,
+    previewed: bool,
+    /// start tags of inline elements like `<b>` and `<a>` still open at the
+    /// end of the last segment
+    open_inline: Vec<Vec<u8>>,
+    budget: PageBudget,
+    /// offset into the decoded document where parsing should begin
+    skip: usize,
+    /// offset into the decoded document of the start of `text`
+    consumed: usize,
+    text_size: usize,
+    truncated: bool,
+    article: Option<Region>,
+    main: Option<Region>,
+    /// where the next part of a truncated text document starts
+    next_part: Option<usize>,
+    table: Option<TableBuilder>,
+    /// how many tables are open, including layout tables around `table`
+    table_depth: usize,
+    /// offset into the decoded document of the end of the last start tag
+    /// handled. Start tags stay in the unparsed text and are scanned again.
+    scanned: usize,
+    containers: Vec<Container>,
+    /// the number of an ordered list item whose first block hasn't been made yet
+    item_number: Option<u32>,
+    /// index into `Page::forms` of the open `<form>`
+    form: Option<usize>,
+}
+
+impl PageBuilder {
+    /// `content_type` is the `Content-Type` header, used as a charset hint.
+    pub fn new(url: &str, content_type: Option<&str>) -> PageBuilder {
+        let mut page = Page::new();
+        page.url = url.to_string();
+        page.loading = true;
+        PageBuilder {
+            page,
+            kind: ContentKind::detect(content_type, url),
+            mime: content_type
+                .and_then(|content_type| content_type.split(';').next())
+                .map(|mime| mime.trim().to_string()),
+            charset_hint: content_type
+                .and_then(charset_from_content_type)
+                .map(|charset| charset.to_string()),
+            decoder: None,
+            raw: vec![],
+            text: vec![],
+            pending_anchors: vec![],
+            in_body: false,
+            previewed: false,
+            open_inline: vec![],
+            budget: PageBudget::default(),
+            skip: 0,
+            consumed: 0,
+            text_size: 0,
+            truncated: false,
+            article: None,
+            main: None,
+            next_part: None,
+            table: None,
+            table_depth: 0,
+            scanned: 0,
+            containers: vec![],
+            item_number: None,
+            form: None,
+        }
+    }
+
+    pub fn with_budget(mut self, budget: PageBudget) -> PageBuilder {
+        self.budget = budget;
+        self
+    }
+
+    /// Skips the blocks before `offset`, to load the next part of a truncated page.
+    pub fn resume_at(mut self, offset: usize) -> PageBuilder {
+        self.skip = offset;
+        self
+    }
+
+    /// False when the body won't be used, for images and types that can't be
+    /// displayed, so it needn't be downloaded.
+    pub fn wants_body(&self) -> bool {
+        self.kind.is_document()
+    }
+
+    /// True once the budget has run out. Anything pushed after that is ignored,
+    /// so the caller can stop downloading.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) {
+        if !self.wants_body() {
+            return;
+        }
+        for piece in chunk.chunks(PUSH_SLICE) {
+            if self.truncated {
+                return;
+            }
+            // text that never reaches a block boundary can't be parsed, so it counts too
+            let too_long = self.text.len() > self.budget.max_text;
+            if too_long && self.page.blocks.is_empty() && self.kind == ContentKind::Html {
+                // a head this big is taken to be a page without a <body>
+                if self.in_body || self.text.len() > 2 * self.budget.max_text {
+                    self.parse_long_text();
+                    if self.truncated {
+                        return;
+                    }
+                }
+            }
+            let too_long = self.text.len() > self.budget.max_text && !self.page.blocks.is_empty();
+            if too_long || self.text.try_reserve(piece.len()).is_err() {
+                warn!("out of room for unparsed text at {}", self.consumed);
+                self.truncate(self.consumed);
+                return;
+            }
+            match &mut self.decoder {
+                Some(decoder) => decoder.decode(piece, &mut self.text),
+                None => {
+                    self.raw.extend_from_slice(piece);
+                    if self.raw.len() < PRESCAN_LENGTH {
+                        continue;
+                    }
+                    self.start_decoding();
+                }
+            }
+            if self.kind == ContentKind::Html {
+                self.parse(false);
+            } else {
+                self.limit_text();
+            }
+        }
+    }
+
+    /// Returns a copy of the first screenful of the page, once, as soon as
+    /// there are enough blocks. Only that much is copied, however far the
+    /// page has got, to keep the extra memory small. The copy has `loading` set.
+    pub fn preview(&mut self) -> Option<Page> {
+        if self.previewed || self.page.blocks.len() < PREVIEW_BLOCKS {
+            return None;
+        }
+        self.previewed = true;
+        Some(self.page.first_blocks(PREVIEW_BLOCKS))
+    }
+
+    /// Parses whatever is left and returns the finished page.
+    pub fn finish(mut self) -> Page {
+        match self.kind {
+            ContentKind::Image => return image_page(&self.page.url),
+            ContentKind::Unsupported => {
+                return unsupported_page(&self.page.url, self.mime.as_deref().unwrap_or_default())
+            }
+            _ => {}
+        }
+        if self.decoder.is_none() {
+            self.start_decoding();
+        }
+        if let Some(decoder) = &mut self.decoder {
+            decoder.finish(&mut self.text);
+            self.page.charset = Some(decoder.encoding().name().to_string());
+        }
+        if self.kind != ContentKind::Html {
+            self.limit_text();
+            self.convert_text();
+            return self.page;
+        }
+        self.parse(true);
+        self.close_table();
+        // anchors at the very end of the page point at the last block
+        let last = self.page.blocks.len().saturating_sub(1);
+        for name in self.pending_anchors.drain(..) {
+            self.page.anchors.push(Anchor { name, block: last });
+        }
+        let last = self.page.blocks.len();
+        self.page.main_content =
+            Region::range(&self.article, last).or_else(|| Region::range(&self.main, last));
+        self.page.loading = false;
+        self.page
+    }
+
+    fn start_decoding(&mut self) {
+        // the prescan may be all ASCII, so an undeclared encoding is decided later
+        let mut decoder = match charset::declared(&self.raw, self.charset_hint.as_deref()) {
+            Some(encoding) => Decoder::new(encoding),
+            None => Decoder::sniffing(),
+        };
+        decoder.decode(&self.raw, &mut self.text);
+        self.raw = vec![];
+        self.decoder = Some(decoder);
+    }
+
+    /// Drops text before the resume offset and cuts the text at a line break
+    /// once it outgrows the budget.
+    fn limit_text(&mut self) {
+        if self.consumed < self.skip {
+            let skipped = (self.skip - self.consumed).min(self.text.len());
+            self.text.drain(..skipped);
+            self.consumed += skipped;
+        }
+        if self.next_part.is_some() || self.text.len() <= self.budget.max_text {
+            return;
+        }
+        let limit = self.budget.max_text;
+        let cut = match self.text[..limit].iter().rposition(|b| *b == b'\n') {
+            Some(n) => n + 1,
+            // no line break, cut before a UTF-8 continuation byte
+            None => (1..=limit).rev().find(|n| self.text[*n] & 0xC0 != 0x80).unwrap_or(limit),
+        };
+        self.text.truncate(cut);
+        self.next_part = Some(self.consumed + cut);
+        self.truncated = true;
+    }
+
+    fn convert_text(&mut self) {
+        let text = String::from_utf8_lossy(&self.text);
+        let url = &self.page.url;
+        let mut page = match self.kind {
+            ContentKind::Markdown => markdown_to_page(&text, url),
+            ContentKind::Gemtext => gemtext_to_page(&text, url),
+            _ => plain_text_to_page(&text, url),
+        };
+        page.charset = self.page.charset.take();
+        self.page = page;
+        if let Some(offset) = self.next_part {
+            self.truncate(offset);
+        }
+        self.page.loading = false;
+    }
+
+    /// Hands every complete segment of `text` to the block parser. A segment ends
+    /// before a block level start tag or after a block level end tag. With `last`
+    /// set the unfinished tail is parsed too.
+    ///
+    /// Each segment gets a block parser of its own, so inline elements that are
+    /// still open at a block boundary, like a `<b>` or `<a>` around a `<div>`,
+    /// have their start tags replayed at the front of the next segment.
+    fn parse(&mut self, last: bool) {
+        let text = core::mem::take(&mut self.text);
+        let mut cut = 0;
+        let mut anchors: Vec<String> = vec![];
+        for tag in TagScanner::new(&text) {
+            // inside a <pre> that has been handled already
+            if tag.start < cut {
+                continue;
+            }
+            if tag.is("body") && !tag.closing {
+                self.add_segment(&text[cut..tag.end], cut, &mut anchors);
+                cut = tag.end;
+                self.in_body = true;
+                continue;
+            }
+            if tag.is_block() && !tag.closing {
+                self.add_segment(&text[cut..tag.start], cut, &mut anchors);
+                cut = tag.start;
+                self.in_body = true;
+                let start = self.consumed + tag.start;
+                if start >= self.scanned && start >= self.skip {
+                    self.scanned = self.consumed + tag.end;
+                    if tag.is("article") {
+                        Region::open(&mut self.article, self.page.blocks.len());
+                    } else if tag.is("main") {
+                        Region::open(&mut self.main, self.page.blocks.len());
+                    }
+                    self.open_table_part(&tag);
+                    self.open_container(&tag);
+                    if tag.is("form") {
+                        self.open_form(&tag);
+                    }
+                }
+            }
+            if let Some(name) = tag.anchor() {
+                anchors.push(name);
+            }
+            if tag.is("pre") && !tag.closing {
+                let close = TagScanner::new(&text[tag.end..])
+                    .find(|close| close.closing && close.is("pre"))
+                    .map(|close| (tag.end + close.start, tag.end + close.end));
+                match close {
+                    Some((content_end, end)) => {
+                        self.add_preformatted(&text[tag.end..content_end], cut, tag.end, &mut anchors);
+                        cut = end;
+                    }
+                    None if last => {
+                        self.add_preformatted(&text[tag.end..], cut, tag.end, &mut anchors);
+                        cut = text.len();
+                    }
+                    // wait for the rest of it
+                    None => break,
+                }
+                continue;
+            }
+            if (tag.is("select") || tag.is("textarea")) && !tag.closing {
+                self.add_segment(&text[cut..tag.start], cut, &mut anchors);
+                cut = tag.start;
+                self.in_body = true;
+                let name = if tag.is("select") { "select" } else { "textarea" };
+                let close = TagScanner::new(&text[tag.end..])
+                    .find(|close| close.closing && close.is(name))
+                    .map(|close| (tag.end + close.start, tag.end + close.end));
+                let (content_end, end) = match close {
+                    Some(close) => close,
+                    None if last => (text.len(), text.len()),
+                    // wait for the rest of it
+                    None => break,
+                };
+                let content = &text[tag.end..content_end];
+                let control = if tag.is("select") {
+                    select_control(&tag, content)
+                } else {
+                    textarea_control(&tag, content)
+                };
+                self.add_control(control, cut, &mut anchors);
+                cut = end;
+                continue;
+            }
+            if tag.is("input") && !tag.closing {
+                self.add_segment(&text[cut..tag.start], cut, &mut anchors);
+                if let Some(control) = input_control(&tag) {
+                    self.add_control(control, cut, &mut anchors);
+                }
+                cut = tag.end;
+                self.in_body = true;
+            }
+            if tag.is("img") && !tag.closing {
+                self.add_segment(&text[cut..tag.start], cut, &mut anchors);
+                self.add_image(&tag, cut, &mut anchors);
+                cut = tag.end;
+                self.in_body = true;
+            }
+            if tag.is_block() && tag.closing {
+                self.add_segment(&text[cut..tag.end], cut, &mut anchors);
+                cut = tag.end;
+                self.in_body = true;
+                if tag.is("article") {
+                    Region::close(&mut self.article, self.page.blocks.len());
+                } else if tag.is("main") {
+                    Region::close(&mut self.main, self.page.blocks.len());
+                }
+                self.close_table_part(&tag);
+                self.close_container(&tag);
+                if tag.is("form") {
+                    self.form = None;
+                }
+            }
+        }
+        if last {
+            self.add_segment(&text[cut..], cut, &mut anchors);
+            cut = text.len();
+        }
+        // anchors after the last cut are found again on the next pass
+        let mut text = text;
+        text.drain(..cut);
+        self.text = text;
+        self.consumed += cut;
+    }
+
+    /// Parses the start of a run of text too long to wait for a block boundary
+    /// in. Once past the resume offset the part ends there, as there is no
+    /// block boundary to cut back to.
+    fn parse_long_text(&mut self) {
+        let end = text_cut(&self.text, self.budget.max_text);
+        let rest = self.text.split_off(end);
+        self.parse(true);
+        self.text = rest;
+        if !self.truncated && self.consumed > self.skip {
+            self.truncate(self.consumed);
+        }
+    }
+
+    /// Parses one segment. `start` is its offset within the unparsed text.
+    fn add_segment(&mut self, segment: &[u8], start: usize, anchors: &mut Vec<String>) {
+        if segment.is_empty() || self.truncated {
+            return;
+        }
+        if !self.in_body {
+            self.add_head_info(segment);
+        }
+        let start = self.consumed + start;
+        if start + segment.len() <= self.skip {
+            // shown in an earlier part of the page
+            self.track_inline(segment);
+            anchors.clear();
+            return;
+        }
+        // the part starts partway into a segment too big for the last part
+        let (segment, start) = match self.skip.checked_sub(start) {
+            Some(shown) if shown > 0 => {
+                self.track_inline(&segment[..shown]);
+                (&segment[shown..], self.skip)
+            }
+            _ => (segment, start),
+        };
+        self.pending_anchors.append(anchors);
+        let first = self.page.blocks.len();
+        // later segments that don't fit go to the next part whole, but the
+        // first one has to be cut to make a part at all
+        let end = if first == 0 {
+            text_cut(segment, self.budget.max_text)
+        } else {
+            segment.len()
+        };
+        let rest = (end < segment.len()).then_some(start + end);
+        let segment = &segment[..end];
+        // the inline elements open at the start of the segment
+        let open = self.open_inline.concat();
+        self.track_inline(segment);
+        let replayed;
+        let segment = if open.is_empty() {
+            segment
+        } else {
+            replayed = [&open[..], segment].concat();
+            &replayed[..]
+        };
+        for block in BlockParser::new(TagParser::new(segment)) {
+            let size: usize = block.spans.iter().map(|span| span.text.len()).sum();
+            // every part gets at least one segment, however big, so it always moves forward
+            let over = self.page.blocks.len() >= self.budget.max_blocks
+                || self.text_size + size > self.budget.max_text;
+            if first > 0 && (over || self.page.blocks.try_reserve(1).is_err()) {
+                self.page.blocks.truncate(first);
+                self.truncate(start);
+                return;
+            }
+            self.text_size += size;
+            self.page.blocks.push(block);
+        }
+        if self.page.blocks.len() > first {
+            for name in self.pending_anchors.drain(..) {
+                self.page.anchors.push(Anchor { name, block: first });
+            }
+            self.page.add_links(first);
+            self.indent_blocks(first);
+            self.mark_part(first, start);
+        }
+        if let Some(offset) = rest {
+            self.truncate(offset);
+        }
+    }
+
+    /// Keeps track of the inline elements opened and closed in `segment`.
+    fn track_inline(&mut self, segment: &[u8]) {
+        for tag in TagScanner::new(segment) {
+            if !INLINE_STYLE_TAGS.iter().any(|name| tag.is(name)) {
+                continue;
+            }
+            if !tag.closing {
+                if self.open_inline.len() < MAX_OPEN_INLINE {
+                    self.open_inline.push(segment[tag.start..tag.end].to_vec());
+                }
+                continue;
+            }
+            // closing an element closes everything opened inside it too
+            let open = self.open_inline.iter().rposition(|open| {
+                TagScanner::new(open).next().is_some_and(|open| open.name.eq_ignore_ascii_case(tag.name))
+            });
+            if let Some(n) = open {
+                self.open_inline.truncate(n);
+            }
+        }
+    }
+
+    /// Adds the content of a `<pre>` as one block per line. `start` and
+    /// `content_start` are the offsets of the tag and of the content within
+    /// the unparsed text.
+    fn add_preformatted(&mut self, content: &[u8], start: usize, content_start: usize, anchors: &mut Vec<String>) {
+        let start = self.consumed + start;
+        let content_start = self.consumed + content_start;
+        if self.truncated || content_start + content.len() <= self.skip {
+            anchors.clear();
+            return;
+        }
+        // the part starts partway into a <pre> too big for the last part
+        let (content, content_start) = match self.skip.checked_sub(content_start) {
+            Some(shown) if shown > 0 => (&content[shown..], self.skip),
+            _ => (content, content_start),
+        };
+        self.pending_anchors.append(anchors);
+        let first = self.page.blocks.len();
+        let end = if first == 0 {
+            line_cut(content, self.budget.max_text, self.budget.max_blocks)
+        } else {
+            content.len()
+        };
+        let rest = (end < content.len()).then_some(content_start + end);
+        for spans in preformatted_lines(&content[..end]) {
+            let size: usize = spans.iter().map(|span| span.text.len()).sum();
+            let over = self.page.blocks.len() >= self.budget.max_blocks
+                || self.text_size + size > self.budget.max_text;
+            if first > 0 && (over || self.page.blocks.try_reserve(1).is_err()) {
+                self.page.blocks.truncate(first);
+                self.truncate(start);
+                return;
+            }
+            self.text_size += size;
+            self.page.blocks.push(Block {
+                block_type: BlockType::Paragraph,
+                spans,
+            });
+        }
+        let last = self.page.blocks.len();
+        if last > first {
+            for name in self.pending_anchors.drain(..) {
+                self.page.anchors.push(Anchor { name, block: first });
+            }
+            self.page.add_links(first);
+            self.page.mark_preformatted(first..last);
+            self.indent_blocks(first);
+            self.mark_part(first, start);
+        }
+        if let Some(offset) = rest {
+            self.truncate(offset);
+        }
+    }
+
+    /// Gives an `<img>` a block of its own, holding its alt text until the image
+    /// is loaded. `start` is the offset of the tag within the unparsed text.
+    fn add_image(&mut self, tag: &RawTag, start: usize, anchors: &mut Vec<String>) {
+        let Some(src) = tag.attr("src") else {
+            return;
+        };
+        if self.truncated || self.consumed + start < self.skip {
+            return;
+        }
+        let alt = tag.attr("alt").unwrap_or_default();
+        let label = if alt.trim().is_empty() {
+            "[image]".to_string()
+        } else {
+            format!("[{}]", alt.trim())
+        };
+        let block = self.page.blocks.len();
+        if block >= self.budget.max_blocks || self.page.blocks.try_reserve(1).is_err() {
+            self.truncate(self.consumed + start);
+            return;
+        }
+        self.page.blocks.push(Block::new_of_type(BlockType::Paragraph, &label));
+        self.text_size += label.len();
+        for name in self.pending_anchors.drain(..).chain(anchors.drain(..)) {
+            self.page.anchors.push(Anchor { name, block });
+        }
+        self.indent_blocks(block);
+        let src = self.page.resolve(&src);
+        self.page.images.push(PageImage {
+            block,
+            src,
+            alt,
+            bitmap: None,
+        });
+    }
+
+    /// Gives a form control a block of its own, showing its value, unless it
+    /// is hidden. `start` is the offset of the tag within the unparsed text.
+    fn add_control(&mut self, mut control: FormControl, start: usize, anchors: &mut Vec<String>) {
+        if self.truncated || self.consumed + start < self.skip {
+            return;
+        }
+        control.form = self.form;
+        let index = self.page.controls.len();
+        if control.kind != ControlKind::Hidden {
+            let block = self.page.blocks.len();
+            if block >= self.budget.max_blocks || self.page.blocks.try_reserve(1).is_err() {
+                self.truncate(self.consumed + start);
+                return;
+            }
+            let shown = control.to_block(index);
+            self.text_size += shown.spans.iter().map(|span| span.text.len()).sum::<usize>();
+            self.page.blocks.push(shown);
+            for name in self.pending_anchors.drain(..).chain(anchors.drain(..)) {
+                self.page.anchors.push(Anchor { name, block });
+            }
+            self.page.add_links(block);
+            self.indent_blocks(block);
+            control.block = Some(block);
+        }
+        self.page.controls.push(control);
+    }
+
+    fn open_form(&mut self, tag: &RawTag) {
+        let action = tag.attr("action").unwrap_or_default();
+        self.form = Some(self.page.forms.len());
+        self.page.forms.push(Form {
+            action: self.page.resolve(action.trim()),
+            method: FormMethod::parse(tag.attr("method").as_deref()),
+        });
+    }
+
+    /// Tracks the lists and quotes that are open, and numbers the items of
+    /// ordered lists.
+    fn open_container(&mut self, tag: &RawTag) {
+        if tag.is("ul") {
+            self.containers.push(Container::List { next: None });
+        } else if tag.is("ol") {
+            let start = tag.attr("start").and_then(|start| start.trim().parse().ok());
+            self.containers.push(Container::List {
+                next: Some(start.unwrap_or(1)),
+            });
+        } else if tag.is("blockquote") {
+            self.containers.push(Container::Quote);
+        } else if tag.is("li") {
+            if let Some(Container::List { next: Some(next) }) = self.containers.last_mut() {
+                let value = tag.attr("value").and_then(|value| value.trim().parse().ok());
+                let number = value.unwrap_or(*next);
+                *next = number.saturating_add(1);
+                self.item_number = Some(number);
+            }
+        }
+    }
+
+    /// Closes the innermost list or quote of the tag's kind, and anything left
+    /// open inside it.
+    fn close_container(&mut self, tag: &RawTag) {
+        let position = if tag.is("ul") || tag.is("ol") {
+            self.containers
+                .iter()
+                .rposition(|container| matches!(container, Container::List { .. }))
+        } else if tag.is("blockquote") {
+            self.containers
+                .iter()
+                .rposition(|container| matches!(container, Container::Quote))
+        } else {
+            None
+        };
+        if let Some(position) = position {
+            self.containers.truncate(position);
+        }
+    }
+
+    /// Records how deep the blocks from `first` on are nested.
+    fn indent_blocks(&mut self, first: usize) {
+        if self.containers.is_empty() {
+            self.item_number = None;
+            return;
+        }
+        let level = self.containers.len().min(MAX_INDENT);
+        let mut quotes = 0;
+        for (n, container) in self.containers.iter().take(MAX_INDENT).enumerate() {
+            if let Container::Quote = container {
+                quotes |= 1 << n;
+            }
+        }
+        for block in first..self.page.blocks.len() {
+            self.page.indents.push(Indent {
+                block,
+                level: level as u8,
+                quotes,
+                number: self.item_number.take(),
+            });
+        }
+    }
+
+    /// Tracks `<table>`, `<tr>`, `<td>` and `<th>` start tags. Cells and rows
+    /// don't need their end tags.
+    fn open_table_part(&mut self, tag: &RawTag) {
+        let block = self.page.blocks.len();
+        if tag.is("table") {
+            self.table_depth += 1;
+            self.table = Some(TableBuilder {
+                depth: self.table_depth,
+                rows: vec![],
+                row: None,
+                cell: None,
+            });
+            return;
+        }
+        let Some(table) = self.table.as_mut().filter(|table| table.depth == self.table_depth) else {
+            return;
+        };
+        if tag.is("tr") {
+            table.close_row(block);
+            table.row = Some(vec![]);
+        } else if tag.is("td") || tag.is("th") {
+            table.close_cell(block);
+            table.cell = Some((block, tag.is("th")));
+        }
+    }
+
+    fn close_table_part(&mut self, tag: &RawTag) {
+        let block = self.page.blocks.len();
+        if tag.is("table") {
+            if self.table.as_ref().is_some_and(|table| table.depth == self.table_depth) {
+                self.close_table();
+            }
+            self.table_depth = self.table_depth.saturating_sub(1);
+            return;
+        }
+        let Some(table) = self.table.as_mut().filter(|table| table.depth == self.table_depth) else {
+            return;
+        };
+        if tag.is("tr") {
+            table.close_row(block);
+        } else if tag.is("td") || tag.is("th") {
+            table.close_cell(block);
+        }
+    }
+
+    /// Ends the table being collected, with what it has so far.
+    fn close_table(&mut self) {
+        if let Some(table) = self.table.take() {
+            if let Some(table) = table.finish(self.page.blocks.len()) {
+                self.page.tables.push(table);
+            }
+        }
+    }
+
+    /// Stops parsing and ends the page with a link to the part starting at `offset`.
+    fn truncate(&mut self, offset: usize) {
+        info!("truncating {} at {}", self.page.url, offset);
+        self.truncated = true;
+        self.close_table();
+        self.pending_anchors.clear();
+        self.page.push_next_part(offset);
+    }
+
+    /// Remembers where the blocks from `first` start in the document, every
+    /// [`PART_SPACING`] blocks, so the page view can offer the rest of the
+    /// page if it runs out of room for lines. Not inside tables, which can't
+    /// be picked up halfway.
+    fn mark_part(&mut self, first: usize, offset: usize) {
+        if self.table_depth > 0 {
+            return;
+        }
+        let last = self.page.part_offsets.last().map_or(0, |(block, _)| *block);
+        if first >= last + PART_SPACING {
+            self.page.part_offsets.push((first, offset));
+        }
+    }
+
+    fn add_head_info(&mut self, segment: &[u8]) {
+        let head = scan_head(segment);
+        let page = &mut self.page;
+        if page.title.is_none() {
+            page.title = head.title;
+        }
+        if page.description.is_none() {
+            page.description = head.description;
+        }
+        if page.lang.is_none() {
+            page.lang = head.lang;
+        }
+        if page.base.is_none() {
+            page.base = head.base;
+        }
+    }
+}
+
+/// Where to cut `text` to keep it within `limit` bytes: before a tag the limit
+/// would split, else after the last space, else between two characters.
+/// Never at the very start, so a part always has something in it.
+fn text_cut(text: &[u8], limit: usize) -> usize {
+    if text.len() <= limit {
+        return text.len();
+    }
+    let head = &text[..limit.max(1)];
+    let tag_end = head.iter().rposition(|b| *b == b'>').map_or(0, |n| n + 1);
+    if let Some(open) = head[tag_end..].iter().position(|b| *b == b'<') {
+        if tag_end + open > 0 {
+            return tag_end + open;
+        }
+    }
+    if let Some(space) = head[tag_end..].iter().rposition(|b| b.is_ascii_whitespace()) {
+        return tag_end + space + 1;
+    }
+    if tag_end > 0 {
+        return tag_end;
+    }
+    (1..head.len()).rev().find(|n| text[*n] & 0xC0 != 0x80).unwrap_or(head.len())
+}
+
+/// Where to cut preformatted text to keep it within `max_bytes` and
+/// `max_lines`, after a line break where there is one.
+fn line_cut(text: &[u8], max_bytes: usize, max_lines: usize) -> usize {
+    let by_lines = text
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(max_lines.saturating_sub(1))
+        .map_or(text.len(), |(n, _)| n + 1);
+    let limit = by_lines.min(max_bytes.max(1));
+    if limit >= text.len() {
+        return text.len();
+    }
+    match text[..limit].iter().rposition(|b| *b == b'\n') {
+        Some(n) => n + 1,
+        None => text_cut(text, limit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pageview::column_widths;
+    use nostd_html_parser::lines::RunStyle;
+
+    fn build(html: &str) -> Page {
+        let mut builder = PageBuilder::new("http://example.com/", Some("text/html"));
+        builder.push(html.as_bytes());
+        builder.finish()
+    }
+
+    fn text(block: &Block) -> String {
+        block.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    fn block_with<'a>(page: &'a Page, text: &str) -> &'a Block {
+        page.blocks
+            .iter()
+            .find(|block| block.spans.iter().any(|span| span.text.contains(text)))
+            .unwrap()
+    }
+
+    #[test]
+    fn inline_elements_carry_over_block_boundaries() {
+        let page = build(
+            "<html><body><a href=\"http://example.com/a\">see <div>inside</div></a><p>after</p></body></html>",
+        );
+        let inside = block_with(&page, "inside");
+        assert!(matches!(inside.spans[0].style, RunStyle::Link(_)));
+        let after = block_with(&page, "after");
+        assert_eq!(after.spans[0].style, RunStyle::Plain);
+    }
+
+    #[test]
+    fn late_latin1_text_is_not_read_as_utf8() {
+        let mut html: Vec<u8> = (0..100).flat_map(|n| format!("<p>paragraph {}</p>", n).into_bytes()).collect();
+        html.extend_from_slice(b"<p>caf\xE9</p>");
+        for size in [7, 2048] {
+            let mut builder = PageBuilder::new("http://example.com/", Some("text/html"));
+            for chunk in html.chunks(size) {
+                builder.push(chunk);
+            }
+            let page = builder.finish();
+            assert!(block_with(&page, "caf").spans[0].text.contains("café"));
+            assert_eq!(page.charset.as_deref(), Some("windows-1252"));
+        }
+    }
+
+    #[test]
+    fn table_rows_and_cells() {
+        let page = build(
+            "<html><body><p>before</p><table><tr><th>Name</th><th>Size</th></tr>\
+             <tr><td>a.txt</td><td>12</td></tr><tr><td>ragged</td></tr>\
+             <tr><th>total</th><td>12</td><td>extra</td></tr></table><p>after</p></body></html>",
+        );
+        assert_eq!(page.tables.len(), 1);
+        let table = &page.tables[0];
+        let cells: Vec<Vec<(String, bool)>> = table
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|cell| {
+                        let text = page.blocks[cell.blocks.clone()].iter().flat_map(|block| &block.spans).map(|span| span.text.as_str()).collect();
+                        (text, cell.header)
+                    })
+                    .collect()
+            })
+            .collect();
+        let cell = |text: &str, header| (text.to_string(), header);
+        assert_eq!(
+            cells,
+            [
+                vec![cell("Name", true), cell("Size", true)],
+                vec![cell("a.txt", false), cell("12", false)],
+                vec![cell("ragged", false)],
+                vec![cell("total", true), cell("12", false), cell("extra", false)],
+            ]
+        );
+        assert_eq!(table.column_count(), 3);
+        assert_eq!(text(&page.blocks[table.blocks().end]), "after");
+    }
+
+    #[test]
+    fn table_columns_fit_or_fall_back() {
+        let page = build(
+            "<html><body><table><tr><th>Name</th><th>Description</th></tr>\
+             <tr><td>a</td><td>a rather long description of the file</td></tr></table></body></html>",
+        );
+        let table = &page.tables[0];
+        // natural widths when there is room
+        assert_eq!(column_widths(&page, table, 80), Some(vec![4, 37]));
+        // the long column gives up room first, the short one keeps its width
+        assert_eq!(column_widths(&page, table, 30), Some(vec![4, 25]));
+        // too narrow for both columns, so the rows are stacked instead
+        assert_eq!(column_widths(&page, table, 10), None);
+        assert_eq!(column_widths(&page, table, 0), None);
+    }
+
+    #[test]
+    fn nested_tables_are_layout() {
+        let page = build(
+            "<html><body><table><tr><td><table><tr><td>inner</td><td>cell</td></tr></table></td><td>side</td></tr></table></body></html>",
+        );
+        assert_eq!(page.tables.len(), 1);
+        assert_eq!(page.tables[0].column_count(), 2);
+        assert_eq!(text(&page.blocks[page.tables[0].rows[0][0].blocks.start]), "inner");
+    }
+
+    #[test]
+    fn preview_copies_only_the_first_screenful() {
+        let mut builder = PageBuilder::new("http://example.com/", Some("text/html"));
+        let html: String = (0..100).map(|n| format!("<p>paragraph {}</p>", n)).collect();
+        builder.push(html.as_bytes());
+        let preview = builder.preview().unwrap();
+        assert_eq!(preview.blocks.len(), PREVIEW_BLOCKS);
+        assert!(preview.loading);
+        assert!(builder.preview().is_none());
+        assert_eq!(builder.finish().blocks.len(), 100);
+    }
+
+    /// Loads every part of a page, following the links to the next part.
+    fn load_parts(html: &str, budget: PageBudget) -> Vec<Page> {
+        let mut parts = vec![];
+        let mut offset = 0;
+        loop {
+            let mut builder = PageBuilder::new("http://example.com/", Some("text/html"))
+                .with_budget(budget)
+                .resume_at(offset);
+            builder.push(html.as_bytes());
+            let page = builder.finish();
+            let next = page.links.iter().find_map(|link| parse_page_part(&link.url).map(|(_, next)| next));
+            parts.push(page);
+            match next {
+                Some(next) => {
+                    assert!(next > offset, "the next part has to move forward");
+                    offset = next;
+                }
+                None => return parts,
+            }
+            assert!(parts.len() < 100);
+        }
+    }
+
+    fn count(parts: &[Page], word: &str) -> usize {
+        parts
+            .iter()
+            .flat_map(|page| page.blocks.iter())
+            .flat_map(|block| block.spans.iter())
+            .map(|span| span.text.matches(word).count())
+            .sum()
+    }
+
+    #[test]
+    fn first_segment_is_cut_to_the_budget() {
+        let budget = PageBudget {
+            max_blocks: 100,
+            max_text: 1000,
+            max_lines: 800,
+        };
+        let html = format!("<html><body>{}</body></html>", "word ".repeat(3000));
+        let parts = load_parts(&html, budget);
+        assert!(parts.len() > 10);
+        for part in &parts {
+            let text: usize = part.blocks.iter().flat_map(|block| &block.spans).map(|span| span.text.len()).sum();
+            assert!(text < 1100, "a part has {} bytes of text", text);
+        }
+        assert_eq!(count(&parts, "word"), 3000);
+    }
+
+    #[test]
+    fn long_preformatted_blocks_are_cut_at_lines() {
+        let budget = PageBudget {
+            max_blocks: 100,
+            max_text: 24 * 1024,
+            max_lines: 800,
+        };
+        let lines: String = (0..500).map(|n| format!("line {}\n", n)).collect();
+        let html = format!("<html><body><pre>{}</pre></body></html>", lines);
+        let parts = load_parts(&html, budget);
+        assert_eq!(parts.len(), 5);
+        for part in &parts {
+            assert!(part.blocks.len() <= 101);
+            assert!(part.is_preformatted(0));
+        }
+        assert_eq!(count(&parts, "line "), 500);
+    }
+
+    #[test]
+    fn pre_blocks_are_one_block_per_line() {
+        let page = build("<html><body><p>before</p><pre>\n  a  <i>b</i>\n\n    c\n</pre><p>after</p></body></html>");
+        let texts: Vec<String> = page.blocks.iter().map(text).collect();
+        let first = texts.iter().position(|text| text == "  a  b").unwrap();
+        assert_eq!(texts[first..first + 3], ["  a  b", "", "    c"]);
+        assert!(!page.is_preformatted(first - 1));
+        assert!((first..first + 3).all(|block| page.is_preformatted(block)));
+        assert!(!page.is_preformatted(first + 3));
+        assert_eq!(page.preformatted.len(), 1);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::archive;
+use crate::cookie::parse_http_date;
+use crate::page::Page;
+use crate::url::Url;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// How a response may be cached, from its headers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CachePolicy {
+    pub no_store: bool,
+    /// stored, but checked with the server before every use
+    pub no_cache: bool,
+    pub max_age: Option<u64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    expires: Option<u64>,
+    date: Option<u64>,
+}
+
+impl CachePolicy {
+    /// Takes in one response header. The ones that don't matter for caching
+    /// are ignored.
+    pub fn header(&mut self, name: &str, value: &str) {
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("cache-control") {
+            for directive in value.split(',') {
+                let (key, arg) = directive.split_once('=').unwrap_or((directive, ""));
+                let key = key.trim();
+                if key.eq_ignore_ascii_case("no-store") {
+                    self.no_store = true;
+                } else if key.eq_ignore_ascii_case("no-cache") {
+                    self.no_cache = true;
+                } else if key.eq_ignore_ascii_case("max-age") {
+                    self.max_age = arg.trim().trim_matches('"').parse().ok();
+                }
+            }
+        } else if name.eq_ignore_ascii_case("pragma") {
+            self.no_cache |= value.eq_ignore_ascii_case("no-cache");
+        } else if name.eq_ignore_ascii_case("etag") {
+            self.etag = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("last-modified") {
+            self.last_modified = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("expires") {
+            // an invalid date means already expired
+            self.expires = Some(parse_http_date(value).unwrap_or(0));
+        } else if name.eq_ignore_ascii_case("date") {
+            self.date = parse_http_date(value);
+        } else if name.eq_ignore_ascii_case("vary") && value == "*" {
+            self.no_store = true;
+        }
+    }
+    /// How many seconds the response stays fresh. `Expires` is only used
+    /// against the server's `Date`, so it works without a real clock.
+    pub fn lifetime(&self) -> u64 {
+        if self.no_cache {
+            return 0;
+        }
+        if let Some(max_age) = self.max_age {
+            return max_age;
+        }
+        match (self.expires, self.date) {
+            (Some(expires), Some(date)) => expires.saturating_sub(date),
+            _ => 0,
+        }
+    }
+    /// Worth keeping: either fresh for a while or cheap to check again.
+    pub fn is_storable(&self) -> bool {
+        !self.no_store
+            && (self.lifetime() > 0 || self.etag.is_some() || self.last_modified.is_some())
+    }
+}
+
+struct CacheEntry {
+    /// the URL without its fragment
+    url: String,
+    /// the page as an archive, which is much smaller than the page itself
+    bytes: Vec<u8>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    fresh_until: u64,
+}
+
+/// Recently loaded pages, least recently used first, kept under a byte
+/// budget. Times are seconds on any clock.
+pub struct PageCache {
+    entries: Vec<CacheEntry>,
+    max_bytes: usize,
+}
+
+impl PageCache {
+    pub const fn new(max_bytes: usize) -> PageCache {
+        PageCache {
+            entries: Vec::new(),
+            max_bytes,
+        }
+    }
+    /// The stored page for `url` if it is still fresh.
+    pub fn fresh_page(&mut self, url: &str, now: u64) -> Option<Page> {
+        let index = self.find(url)?;
+        if self.entries[index].fresh_until <= now {
+            return None;
+        }
+        self.page_at(index, url)
+    }
+    /// Headers that ask the server to answer 304 if the stored page for `url`
+    /// is still current.
+    pub fn conditional_headers(&self, url: &str) -> Vec<(&'static str, String)> {
+        let Some(entry) = self.find(url).map(|index| &self.entries[index]) else {
+            return vec![];
+        };
+        let mut headers = vec![];
+        if let Some(etag) = &entry.etag {
+            headers.push(("If-None-Match", etag.clone()));
+        }
+        if let Some(last_modified) = &entry.last_modified {
+            headers.push(("If-Modified-Since", last_modified.clone()));
+        }
+        headers
+    }
+    /// The stored page for `url` after the server answered 304, fresh again
+    /// for as long as the new `policy` says.
+    pub fn revalidated(&mut self, url: &str, policy: &CachePolicy, now: u64) -> Option<Page> {
+        let index = self.find(url)?;
+        let entry = &mut self.entries[index];
+        entry.fresh_until = now.saturating_add(policy.lifetime());
+        if policy.etag.is_some() {
+            entry.etag = policy.etag.clone();
+        }
+        if policy.last_modified.is_some() {
+            entry.last_modified = policy.last_modified.clone();
+        }
+        self.page_at(index, url)
+    }
+    /// Keeps `page` under its URL if the policy allows, dropping the least
+    /// recently used pages to make room.
+    pub fn store(&mut self, page: &Page, policy: &CachePolicy, now: u64) {
+        let url = cache_key(&page.url);
+        self.entries.retain(|entry| entry.url != url);
+        if !policy.is_storable() {
+            return;
+        }
+        let bytes = archive::write_page(page);
+        if bytes.len() > self.max_bytes {
+            return;
+        }
+        self.entries.push(CacheEntry {
+            url,
+            bytes,
+            etag: policy.etag.clone(),
+            last_modified: policy.last_modified.clone(),
+            fresh_until: now.saturating_add(policy.lifetime()),
+        });
+        while self.size() > self.max_bytes {
+            self.entries.remove(0);
+        }
+    }
+    /// Forgets the page for `url`, like after a POST to it.
+    pub fn remove(&mut self, url: &str) {
+        let url = cache_key(url);
+        self.entries.retain(|entry| entry.url != url);
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+    /// The bytes held for all the pages.
+    pub fn size(&self) -> usize {
+        self.entries.iter().map(|entry| entry.bytes.len()).sum()
+    }
+    fn find(&self, url: &str) -> Option<usize> {
+        let url = cache_key(url);
+        self.entries.iter().position(|entry| entry.url == url)
+    }
+    /// Reads back the page at `index`, marking it the most recently used.
+    /// It takes the fragment of the `url` it was asked for.
+    fn page_at(&mut self, index: usize, url: &str) -> Option<Page> {
+        let entry = self.entries.remove(index);
+        let mut page = archive::read_page(&entry.bytes).ok()?;
+        self.entries.push(entry);
+        let fragment = Url::parse(url).and_then(|url| url.fragment);
+        if let Some(mut page_url) = Url::parse(&page.url) {
+            page_url.fragment = fragment;
+            page.url = page_url.to_string();
+        }
+        Some(page)
+    }
+}
+
+fn cache_key(url: &str) -> String {
+    match Url::parse(url) {
+        Some(url) => url.without_fragment().to_string(),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostd_html_parser::blocks::{Block, BlockType};
+
+    fn policy(headers: &[(&str, &str)]) -> CachePolicy {
+        let mut policy = CachePolicy::default();
+        for (name, value) in headers {
+            policy.header(name, value);
+        }
+        policy
+    }
+
+    fn page(url: &str, text: &str) -> Page {
+        let mut page = Page::new();
+        page.url = url.to_string();
+        page.blocks.push(Block::new_of_type(BlockType::Paragraph, text));
+        page
+    }
+
+    fn text(page: &Page) -> String {
+        page.blocks[0].spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    #[test]
+    fn policies_from_headers() {
+        let cached = policy(&[("Cache-Control", "public, max-age=\"600\"")]);
+        assert_eq!(cached.lifetime(), 600);
+        assert!(cached.is_storable());
+        let expires = policy(&[
+            ("Date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+            ("Expires", "Sun, 06 Nov 1994 09:49:37 GMT"),
+        ]);
+        assert_eq!(expires.lifetime(), 3600);
+        // max-age wins over Expires, and no-cache over both
+        let both = policy(&[("Expires", "Sun, 06 Nov 1994 09:49:37 GMT"), ("cache-control", "max-age=5")]);
+        assert_eq!(both.lifetime(), 5);
+        let revalidate = policy(&[("Cache-Control", "no-cache, max-age=60"), ("ETag", "\"v1\"")]);
+        assert_eq!(revalidate.lifetime(), 0);
+        assert!(revalidate.is_storable());
+        assert_eq!(policy(&[("Pragma", "no-cache"), ("Cache-Control", "max-age=60")]).lifetime(), 0);
+        assert!(!policy(&[("Cache-Control", "no-store, max-age=60")]).is_storable());
+        assert!(!policy(&[("Vary", "*"), ("Cache-Control", "max-age=60")]).is_storable());
+        assert!(!policy(&[("Content-Type", "text/html")]).is_storable());
+        assert!(!policy(&[("Expires", "0"), ("Date", "Sun, 06 Nov 1994 08:49:37 GMT")]).is_storable());
+    }
+
+    #[test]
+    fn fresh_pages_come_from_the_cache() {
+        let mut cache = PageCache::new(64 * 1024);
+        cache.store(&page("http://example.com/a", "first"), &policy(&[("Cache-Control", "max-age=60")]), 100);
+        let hit = cache.fresh_page("http://example.com/a#part", 159).unwrap();
+        assert_eq!(text(&hit), "first");
+        assert_eq!(hit.url, "http://example.com/a#part");
+        assert!(cache.fresh_page("http://example.com/a", 160).is_none());
+        assert!(cache.fresh_page("http://example.com/b", 100).is_none());
+        cache.remove("http://example.com/a#top");
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn stale_pages_are_revalidated() {
+        let mut cache = PageCache::new(64 * 1024);
+        let headers = [("ETag", "\"v1\""), ("Last-Modified", "Sun, 06 Nov 1994 08:49:37 GMT")];
+        cache.store(&page("http://example.com/a", "first"), &policy(&headers), 100);
+        assert!(cache.fresh_page("http://example.com/a", 100).is_none());
+        assert_eq!(
+            cache.conditional_headers("http://example.com/a"),
+            [
+                ("If-None-Match", "\"v1\"".to_string()),
+                ("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT".to_string())
+            ]
+        );
+        assert!(cache.conditional_headers("http://example.com/b").is_empty());
+        // the 304 can make it fresh for a while and update the validators
+        let answer = policy(&[("ETag", "\"v2\""), ("Cache-Control", "max-age=10")]);
+        assert_eq!(text(&cache.revalidated("http://example.com/a", &answer, 200).unwrap()), "first");
+        assert!(cache.fresh_page("http://example.com/a", 209).is_some());
+        assert_eq!(cache.conditional_headers("http://example.com/a")[0].1, "\"v2\"");
+        // a response that can't be stored drops the old page
+        cache.store(&page("http://example.com/a", "second"), &policy(&[]), 300);
+        assert!(cache.conditional_headers("http://example.com/a").is_empty());
+    }
+
+    #[test]
+    fn least_recently_used_pages_go_first() {
+        let one = archive::write_page(&page("http://example.com/0", "text")).len();
+        let mut cache = PageCache::new(one * 2 + one / 2);
+        let fresh = policy(&[("Cache-Control", "max-age=60")]);
+        cache.store(&page("http://example.com/0", "text"), &fresh, 0);
+        cache.store(&page("http://example.com/1", "text"), &fresh, 0);
+        // using the first page makes the second the oldest
+        assert!(cache.fresh_page("http://example.com/0", 1).is_some());
+        cache.store(&page("http://example.com/2", "text"), &fresh, 0);
+        assert!(cache.fresh_page("http://example.com/0", 1).is_some());
+        assert!(cache.fresh_page("http://example.com/1", 1).is_none());
+        assert!(cache.fresh_page("http://example.com/2", 1).is_some());
+        assert!(cache.size() <= one * 2 + one / 2);
+        cache.clear();
+        assert_eq!(cache.size(), 0);
+    }
+}
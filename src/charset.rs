@@ -0,0 +1,376 @@
+use crate::html::scan_head;
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+/// The encodings we know how to turn into UTF-8. ISO-8859-1 and US-ASCII are
+/// decoded as Windows-1252, which is what browsers actually do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Windows1252,
+}
+
+/// How far into the document to look for a `<meta charset>`.
+pub const PRESCAN_LENGTH: usize = 1024;
+
+/// Windows-1252 code points for bytes 0x80 to 0x9F. The rest match Latin-1.
+const WINDOWS_1252_HIGH: [u16; 32] = [
+    0x20AC, 0x0081, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160,
+    0x2039, 0x0152, 0x008D, 0x017D, 0x008F, 0x0090, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022,
+    0x2013, 0x2014, 0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x009D, 0x017E, 0x0178,
+];
+
+impl Encoding {
+    pub fn from_label(label: &str) -> Option<Encoding> {
+        let label = label.trim();
+        let matches = |names: &[&str]| names.iter().any(|name| label.eq_ignore_ascii_case(name));
+        if matches(&["utf-8", "utf8", "unicode-1-1-utf-8"]) {
+            Some(Encoding::Utf8)
+        } else if matches(&["utf-16", "utf-16le"]) {
+            Some(Encoding::Utf16Le)
+        } else if matches(&["utf-16be"]) {
+            Some(Encoding::Utf16Be)
+        } else if matches(&[
+            "windows-1252",
+            "cp1252",
+            "x-cp1252",
+            "iso-8859-1",
+            "iso8859-1",
+            "iso_8859-1",
+            "latin1",
+            "l1",
+            "us-ascii",
+            "ascii",
+        ]) {
+            Some(Encoding::Windows1252)
+        } else {
+            None
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Windows1252 => "windows-1252",
+        }
+    }
+}
+
+fn sniff_bom(bytes: &[u8]) -> Option<(Encoding, usize)> {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        Some((Encoding::Utf8, 3))
+    } else if bytes.starts_with(&[0xFF, 0xFE]) {
+        Some((Encoding::Utf16Le, 2))
+    } else if bytes.starts_with(&[0xFE, 0xFF]) {
+        Some((Encoding::Utf16Be, 2))
+    } else {
+        None
+    }
+}
+
+/// The encoding a document says it has: a byte order mark wins, then the
+/// charset from the `Content-Type` header, then a `<meta charset>` near the start.
+pub fn declared(bytes: &[u8], hint: Option<&str>) -> Option<Encoding> {
+    if let Some((encoding, _)) = sniff_bom(bytes) {
+        return Some(encoding);
+    }
+    if let Some(encoding) = hint.and_then(Encoding::from_label) {
+        return Some(encoding);
+    }
+    let prescan = &bytes[..bytes.len().min(PRESCAN_LENGTH)];
+    match Encoding::from_label(&scan_head(prescan).charset?)? {
+        // a meta tag can't really declare UTF-16, the tag itself would be unreadable
+        Encoding::Utf16Le | Encoding::Utf16Be => Some(Encoding::Utf8),
+        encoding => Some(encoding),
+    }
+}
+
+/// Picks the encoding of a whole document: the [`declared`] one, or else valid
+/// UTF-8 is UTF-8 and anything else is Windows-1252.
+pub fn detect(bytes: &[u8], hint: Option<&str>) -> Encoding {
+    if let Some(encoding) = declared(bytes, hint) {
+        return encoding;
+    }
+    match core::str::from_utf8(bytes) {
+        Ok(_) => Encoding::Utf8,
+        // cut off in the middle of a character, which is fine for a prefix
+        Err(err) if err.error_len().is_none() => Encoding::Utf8,
+        Err(_) => Encoding::Windows1252,
+    }
+}
+
+/// Converts a document to UTF-8. Valid UTF-8 input is borrowed, not copied.
+/// Malformed sequences become U+FFFD.
+pub fn decode(bytes: &[u8], encoding: Encoding) -> Cow<'_, [u8]> {
+    if encoding == Encoding::Utf8 {
+        let text = match sniff_bom(bytes) {
+            Some((Encoding::Utf8, len)) => &bytes[len..],
+            _ => bytes,
+        };
+        if core::str::from_utf8(text).is_ok() {
+            return Cow::Borrowed(text);
+        }
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut decoder = Decoder::new(encoding);
+    decoder.decode(bytes, &mut out);
+    decoder.finish(&mut out);
+    Cow::Owned(out)
+}
+
+/// Incremental version of [`decode`] for bodies that arrive in chunks. Bytes that
+/// end partway through a character are held back until the next call.
+pub struct Decoder {
+    encoding: Encoding,
+    carry: Vec<u8>,
+    started: bool,
+    /// still choosing between UTF-8 and Windows-1252
+    sniffing: bool,
+}
+
+impl Decoder {
+    pub fn new(encoding: Encoding) -> Decoder {
+        Decoder {
+            encoding,
+            carry: Vec::new(),
+            started: false,
+            sniffing: false,
+        }
+    }
+    /// A decoder for a document that doesn't declare its encoding. ASCII reads
+    /// the same either way, so the choice waits for the first other byte: UTF-8
+    /// if it starts a valid sequence, Windows-1252 if not.
+    pub fn sniffing() -> Decoder {
+        Decoder {
+            sniffing: true,
+            ..Decoder::new(Encoding::Utf8)
+        }
+    }
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+    /// Appends the UTF-8 form of `bytes` to `out`.
+    pub fn decode(&mut self, bytes: &[u8], out: &mut Vec<u8>) {
+        let input: Cow<[u8]> = if self.carry.is_empty() {
+            Cow::Borrowed(bytes)
+        } else {
+            let mut joined = core::mem::take(&mut self.carry);
+            joined.extend_from_slice(bytes);
+            Cow::Owned(joined)
+        };
+        let mut input: &[u8] = &input;
+        if !self.started {
+            // wait until there are enough bytes to tell if a byte order mark is there
+            if input.len() < 3 {
+                self.carry.extend_from_slice(input);
+                return;
+            }
+            self.started = true;
+            if let Some((bom, len)) = sniff_bom(input) {
+                if bom == self.encoding {
+                    input = &input[len..];
+                }
+            }
+        }
+        if self.sniffing {
+            let ascii = input.iter().position(|b| !b.is_ascii()).unwrap_or(input.len());
+            out.extend_from_slice(&input[..ascii]);
+            input = &input[ascii..];
+            match core::str::from_utf8(input) {
+                _ if input.is_empty() => return,
+                Err(err) if err.valid_up_to() == 0 && err.error_len().is_some() => {
+                    self.encoding = Encoding::Windows1252;
+                }
+                Err(err) if err.valid_up_to() == 0 => {
+                    // the first character isn't all here yet
+                    self.carry.extend_from_slice(input);
+                    return;
+                }
+                _ => {}
+            }
+            self.sniffing = false;
+        }
+        match self.encoding {
+            Encoding::Utf8 => loop {
+                match core::str::from_utf8(input) {
+                    Ok(text) => {
+                        out.extend_from_slice(text.as_bytes());
+                        break;
+                    }
+                    Err(err) => {
+                        let valid = err.valid_up_to();
+                        out.extend_from_slice(&input[..valid]);
+                        match err.error_len() {
+                            Some(len) => {
+                                push_char(out, char::REPLACEMENT_CHARACTER);
+                                input = &input[valid + len..];
+                            }
+                            None => {
+                                self.carry.extend_from_slice(&input[valid..]);
+                                break;
+                            }
+                        }
+                    }
+                }
+            },
+            Encoding::Windows1252 => {
+                for b in input {
+                    push_char(out, windows_1252_char(*b));
+                }
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut end = input.len() - input.len() % 2;
+                // hold back a high surrogate until we see its partner
+                if end >= 2 {
+                    let last = self.unit([input[end - 2], input[end - 1]]);
+                    if (0xD800..0xDC00).contains(&last) {
+                        end -= 2;
+                    }
+                }
+                let units = input[..end]
+                    .chunks_exact(2)
+                    .map(|pair| self.unit([pair[0], pair[1]]));
+                for ch in char::decode_utf16(units) {
+                    push_char(out, ch.unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                self.carry.extend_from_slice(&input[end..]);
+            }
+        }
+    }
+    /// Flushes anything still held back. A truncated character becomes U+FFFD,
+    /// unless it was the first one of a document still being sniffed, which
+    /// makes it Windows-1252.
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        if !self.started {
+            // too short to hold a byte order mark, decode it as is
+            self.started = true;
+            let carry = core::mem::take(&mut self.carry);
+            self.decode(&carry, out);
+        }
+        if self.sniffing && !self.carry.is_empty() {
+            self.sniffing = false;
+            self.encoding = Encoding::Windows1252;
+            let carry = core::mem::take(&mut self.carry);
+            self.decode(&carry, out);
+        }
+        if !self.carry.is_empty() {
+            self.carry.clear();
+            push_char(out, char::REPLACEMENT_CHARACTER);
+        }
+    }
+    fn unit(&self, pair: [u8; 2]) -> u16 {
+        match self.encoding {
+            Encoding::Utf16Be => u16::from_be_bytes(pair),
+            _ => u16::from_le_bytes(pair),
+        }
+    }
+}
+
+fn push_char(out: &mut Vec<u8>, ch: char) {
+    let mut buf = [0u8; 4];
+    out.extend_from_slice(ch.encode_utf8(&mut buf).as_bytes());
+}
+
+fn windows_1252_char(b: u8) -> char {
+    match b {
+        0x80..=0x9F => {
+            char::from_u32(WINDOWS_1252_HIGH[(b - 0x80) as usize] as u32).unwrap_or('\u{FFFD}')
+        }
+        _ => b as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec;
+
+    /// Decodes `bytes` handed over in pieces of `size`.
+    fn decode_in_chunks(mut decoder: Decoder, bytes: &[u8], size: usize) -> String {
+        let mut out = vec![];
+        for chunk in bytes.chunks(size) {
+            decoder.decode(chunk, &mut out);
+        }
+        decoder.finish(&mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn byte_order_marks() {
+        let utf8 = b"\xEF\xBB\xBFcaf\xC3\xA9";
+        let utf16le = b"\xFF\xFEh\x00\xE9\x00";
+        let utf16be = b"\xFE\xFF\x00h\x00\xE9";
+        // a byte order mark beats the header
+        assert_eq!(detect(utf8, Some("windows-1252")), Encoding::Utf8);
+        assert_eq!(detect(utf16le, Some("utf-8")), Encoding::Utf16Le);
+        assert_eq!(detect(utf16be, None), Encoding::Utf16Be);
+        assert_eq!(&*decode(utf8, Encoding::Utf8), "café".as_bytes());
+        assert_eq!(&*decode(utf16le, Encoding::Utf16Le), "hé".as_bytes());
+        assert_eq!(&*decode(utf16be, Encoding::Utf16Be), "hé".as_bytes());
+    }
+
+    #[test]
+    fn header_beats_meta() {
+        let html = b"<html><head><meta charset=\"utf-8\"></head><body>\xE9</body></html>";
+        assert_eq!(detect(html, Some("ISO-8859-1")), Encoding::Windows1252);
+        assert_eq!(detect(html, None), Encoding::Utf8);
+        // an unknown label in the header falls back to the meta tag
+        assert_eq!(detect(html, Some("x-unknown")), Encoding::Utf8);
+        let html = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1252\">";
+        assert_eq!(declared(html, None), Some(Encoding::Windows1252));
+    }
+
+    #[test]
+    fn meta_cannot_declare_utf16() {
+        let html = b"<meta charset=\"utf-16\"><p>plain</p>";
+        assert_eq!(declared(html, None), Some(Encoding::Utf8));
+        assert_eq!(declared(b"<p>plain</p>", None), None);
+    }
+
+    #[test]
+    fn windows_1252_high_bytes() {
+        let bytes = b"\x80 \x85 \x8D \x93quoted\x94 \x9F \xA0\xE9\xFF";
+        assert_eq!(detect(bytes, None), Encoding::Windows1252);
+        let text = String::from_utf8(decode(bytes, Encoding::Windows1252).into_owned()).unwrap();
+        assert_eq!(text, "€ … \u{8D} “quoted” Ÿ \u{A0}éÿ");
+    }
+
+    #[test]
+    fn characters_split_across_chunks() {
+        let text = "héllo wörld ✓ 😀 done";
+        for size in 1..8 {
+            assert_eq!(decode_in_chunks(Decoder::new(Encoding::Utf8), text.as_bytes(), size), text);
+            let le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            assert_eq!(decode_in_chunks(Decoder::new(Encoding::Utf16Le), &le, size), text);
+            let be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+            assert_eq!(decode_in_chunks(Decoder::new(Encoding::Utf16Be), &be, size), text);
+        }
+        // a character cut off at the end
+        assert_eq!(decode_in_chunks(Decoder::new(Encoding::Utf8), b"ok\xE2\x9C", 2), "ok\u{FFFD}");
+    }
+
+    #[test]
+    fn undeclared_documents_wait_for_a_non_ascii_byte() {
+        let mut latin1 = vec![b'a'; 2 * PRESCAN_LENGTH];
+        latin1.extend_from_slice(b"caf\xE9 cr\xE8me");
+        let mut utf8 = vec![b'a'; 2 * PRESCAN_LENGTH];
+        utf8.extend_from_slice("café crème".as_bytes());
+        for size in [1, 3, 100, PRESCAN_LENGTH] {
+            let text = decode_in_chunks(Decoder::sniffing(), &latin1, size);
+            assert!(text.ends_with("café crème"), "{}", &text[text.len() - 12..]);
+            let text = decode_in_chunks(Decoder::sniffing(), &utf8, size);
+            assert!(text.ends_with("café crème"));
+        }
+        // a lone lead byte at the very end can't be UTF-8
+        assert_eq!(decode_in_chunks(Decoder::sniffing(), b"abc\xC3", 2), "abcÃ");
+        let mut decoder = Decoder::sniffing();
+        let mut out = vec![];
+        decoder.decode(b"plain", &mut out);
+        decoder.finish(&mut out);
+        assert_eq!(decoder.encoding(), Encoding::Utf8);
+    }
+}
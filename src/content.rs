@@ -0,0 +1,125 @@
+use crate::page::{Page, PageImage};
+use crate::url::Url;
+use alloc::format;
+use alloc::string::{String, ToString};
+use nostd_html_parser::blocks::{Block, BlockType};
+
+/// The kinds of document we can turn into a [`Page`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentKind {
+    Html,
+    PlainText,
+    Markdown,
+    Gemtext,
+    /// an image format we can decode, shown on a page of its own
+    Image,
+    /// anything else, like PDFs or binary downloads
+    Unsupported,
+}
+
+impl ContentKind {
+    /// Picks a converter from the `Content-Type` header, falling back to the file
+    /// extension of the URL. Markdown is often served as `text/plain`, so the
+    /// extension wins there. Without either the document is taken to be HTML.
+    /// Other text types are shown as plain text.
+    pub fn detect(content_type: Option<&str>, url: &str) -> ContentKind {
+        let mime = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|mime| mime.trim().to_ascii_lowercase());
+        let extension = ContentKind::from_extension(url);
+        match mime.as_deref() {
+            Some("text/html") | Some("application/xhtml+xml") => ContentKind::Html,
+            Some("text/markdown") | Some("text/x-markdown") => ContentKind::Markdown,
+            Some("text/gemini") => ContentKind::Gemtext,
+            Some("text/plain") => match extension {
+                Some(ContentKind::Markdown) => ContentKind::Markdown,
+                _ => ContentKind::PlainText,
+            },
+            Some("image/png") | Some("image/jpeg") | Some("image/gif") | Some("image/bmp")
+            | Some("image/x-ms-bmp") => ContentKind::Image,
+            Some(mime) if mime.starts_with("text/") => ContentKind::PlainText,
+            Some(_) => ContentKind::Unsupported,
+            None => extension.unwrap_or(ContentKind::Html),
+        }
+    }
+    /// False for kinds whose body isn't turned into the page, so there is no
+    /// need to download it.
+    pub fn is_document(self) -> bool {
+        !matches!(self, ContentKind::Image | ContentKind::Unsupported)
+    }
+    fn from_extension(url: &str) -> Option<ContentKind> {
+        let path = match Url::parse(url) {
+            Some(url) => url.path,
+            None => url.split(['?', '#']).next().unwrap_or("").to_string(),
+        };
+        let name = path.rsplit('/').next().unwrap_or("");
+        let (_, extension) = name.rsplit_once('.')?;
+        let extension = extension.to_ascii_lowercase();
+        match extension.as_str() {
+            "html" | "htm" | "xhtml" => Some(ContentKind::Html),
+            "txt" | "text" => Some(ContentKind::PlainText),
+            "md" | "markdown" => Some(ContentKind::Markdown),
+            "gmi" | "gemini" => Some(ContentKind::Gemtext),
+            _ => None,
+        }
+    }
+}
+
+/// A page showing the image at `url`, which is fetched and decoded like the
+/// images of any other page.
+pub fn image_page(url: &str) -> Page {
+    let mut page = Page::new();
+    page.url = url.to_string();
+    page.blocks.push(Block::new_of_type(BlockType::Paragraph, "[image]"));
+    page.images.push(PageImage {
+        block: 0,
+        src: url.to_string(),
+        alt: String::new(),
+        bitmap: None,
+    });
+    page
+}
+
+/// Shown in place of a document of a type we can't display.
+pub fn unsupported_page(url: &str, mime: &str) -> Page {
+    let mut page = Page::new();
+    page.url = url.to_string();
+    page.title = Some("Can't display this page".to_string());
+    let text = format!("Can't display {}", mime);
+    page.blocks.push(Block::new_of_type(BlockType::Paragraph, &text));
+    page
+}
+
+/// Plain text, one paragraph per line so the line structure survives. Blank
+/// lines are kept as empty paragraphs.
+pub fn plain_text_to_page(text: &str, url: &str) -> Page {
+    let mut page = Page::new();
+    page.url = url.to_string();
+    for line in text.lines() {
+        page.blocks.push(Block::new_of_type(BlockType::Paragraph, line.trim_end()));
+    }
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_kinds() {
+        let detect = ContentKind::detect;
+        assert_eq!(detect(Some("text/html; charset=utf-8"), "http://a/"), ContentKind::Html);
+        assert_eq!(detect(Some("text/plain"), "http://a/readme.md"), ContentKind::Markdown);
+        assert_eq!(detect(Some("text/css"), "http://a/site.css"), ContentKind::PlainText);
+        assert_eq!(detect(Some("image/PNG"), "http://a/x"), ContentKind::Image);
+        assert_eq!(detect(None, "http://a/notes.txt"), ContentKind::PlainText);
+        assert_eq!(detect(None, "http://a/"), ContentKind::Html);
+    }
+
+    #[test]
+    fn binary_types_are_not_html() {
+        for mime in ["application/pdf", "application/json", "application/octet-stream", "image/webp"] {
+            assert_eq!(ContentKind::detect(Some(mime), "http://a/index.html"), ContentKind::Unsupported);
+        }
+    }
+}
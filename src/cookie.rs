@@ -0,0 +1,503 @@
+use crate::url::Url;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// The most cookies kept. The oldest go first when there are more.
+pub const MAX_COOKIES: usize = 64;
+/// Cookies bigger than this are ignored, as browsers do.
+pub const MAX_COOKIE_SIZE: usize = 4096;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Domains under which anyone can register a name, so a cookie for one of
+/// them would go to unrelated sites. Bare top level domains are left out, as
+/// any domain without a dot counts. A short list, not the whole Public
+/// Suffix List.
+const PUBLIC_SUFFIXES: &[&str] = &[
+    "co.uk", "org.uk", "ac.uk", "gov.uk", "me.uk", "ltd.uk", "plc.uk", "net.uk",
+    "com.au", "net.au", "org.au", "edu.au", "gov.au",
+    "co.nz", "org.nz", "co.jp", "ne.jp", "or.jp", "ac.jp",
+    "co.kr", "co.in", "co.za", "com.br", "com.cn", "com.mx", "com.tr", "com.tw",
+    "github.io", "gitlab.io", "netlify.app", "vercel.app", "pages.dev", "workers.dev",
+    "herokuapp.com", "appspot.com", "blogspot.com", "azurewebsites.net", "cloudfront.net",
+];
+
+/// What `Expires` dates are measured against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Clock {
+    /// the jar's times are seconds since the Unix epoch, like the dates
+    Wall,
+    /// the jar's times count from something else, like boot. `Expires` is
+    /// taken relative to this `Date` of the response that set the cookie.
+    /// Without one the cookie only lasts the session.
+    Relative(Option<u64>),
+}
+
+/// A cookie set by a server. Times are seconds on whatever clock the jar is
+/// given. `Max-Age` works on any clock, see [`Clock`] for `Expires`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// lower case, without a leading dot
+    pub domain: String,
+    /// only sent to `domain` itself, not to its subdomains
+    pub host_only: bool,
+    pub path: String,
+    /// `None` for a session cookie
+    pub expires: Option<u64>,
+    /// only sent over https
+    pub secure: bool,
+}
+
+impl Cookie {
+    /// Parses a `Set-Cookie` header received from `url`. Cookies the URL isn't
+    /// allowed to set, like ones for another domain or a public suffix, are
+    /// rejected.
+    pub fn parse(header: &str, url: &Url, now: u64, clock: Clock) -> Option<Cookie> {
+        if header.len() > MAX_COOKIE_SIZE {
+            return None;
+        }
+        let host = url.host.as_ref()?.to_ascii_lowercase();
+        let mut attributes = header.split(';');
+        let (name, value) = attributes.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(&url.path),
+            expires: None,
+            secure: false,
+        };
+        let mut max_age = None;
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let (key, value) = (key.trim(), value.trim());
+            if key.eq_ignore_ascii_case("domain") {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                if domain.is_empty() {
+                    continue;
+                }
+                if !domain_matches(&host, &domain) {
+                    return None;
+                }
+                // a public suffix would reach every site under it. The site
+                // at the suffix itself only gets a host only cookie.
+                if is_public_suffix(&domain) {
+                    if domain != host {
+                        return None;
+                    }
+                    continue;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            } else if key.eq_ignore_ascii_case("path") {
+                if value.starts_with('/') {
+                    cookie.path = value.to_string();
+                }
+            } else if key.eq_ignore_ascii_case("max-age") {
+                max_age = value.parse::<i64>().ok().or(max_age);
+            } else if key.eq_ignore_ascii_case("expires") {
+                if let Some(date) = parse_http_date(value) {
+                    cookie.expires = match clock {
+                        Clock::Wall => Some(date),
+                        // a date at or before the response's is already expired
+                        Clock::Relative(Some(sent)) => Some(now.saturating_add(date.saturating_sub(sent))),
+                        Clock::Relative(None) => None,
+                    };
+                }
+            } else if key.eq_ignore_ascii_case("secure") {
+                cookie.secure = true;
+            }
+        }
+        // Max-Age wins over Expires, and zero or less deletes the cookie
+        if let Some(age) = max_age {
+            cookie.expires = Some(if age <= 0 { 0 } else { now.saturating_add(age as u64) });
+        }
+        if cookie.secure && url.scheme != "https" {
+            return None;
+        }
+        Some(cookie)
+    }
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+    /// Whether the cookie goes with a request to `url`.
+    pub fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host.as_ref() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        let scheme_ok = match url.scheme.as_str() {
+            "https" => true,
+            "http" => !self.secure,
+            _ => false,
+        };
+        domain_ok && scheme_ok && path_matches(&url.path, &self.path)
+    }
+}
+
+/// The cookies of every site visited.
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+    /// whether times are seconds since the Unix epoch
+    wall_clock: bool,
+}
+
+impl CookieJar {
+    pub const fn new() -> CookieJar {
+        CookieJar {
+            cookies: Vec::new(),
+            wall_clock: true,
+        }
+    }
+    /// A jar for times on a clock that isn't the Unix epoch, like seconds
+    /// since boot on a device without a real time clock.
+    pub const fn without_wall_clock() -> CookieJar {
+        CookieJar {
+            cookies: Vec::new(),
+            wall_clock: false,
+        }
+    }
+    pub fn cookies(&self) -> &[Cookie] {
+        &self.cookies
+    }
+    /// Stores the cookie from a `Set-Cookie` header sent by `url`. `date` is
+    /// the `Date` header of the response, used when there is no wall clock.
+    pub fn set_cookie(&mut self, header: &str, url: &str, date: Option<&str>, now: u64) {
+        let clock = if self.wall_clock {
+            Clock::Wall
+        } else {
+            Clock::Relative(date.and_then(parse_http_date))
+        };
+        if let Some(cookie) = Url::parse(url).and_then(|url| Cookie::parse(header, &url, now, clock)) {
+            self.insert(cookie, now);
+        }
+    }
+    /// Adds `cookie`, replacing one with the same name, domain and path. A
+    /// cookie that has already expired only removes the one it replaces.
+    pub fn insert(&mut self, cookie: Cookie, now: u64) {
+        self.cookies.retain(|old| {
+            !(old.name == cookie.name && old.domain == cookie.domain && old.path == cookie.path)
+        });
+        if cookie.is_expired(now) {
+            return;
+        }
+        self.cookies.push(cookie);
+        if self.cookies.len() > MAX_COOKIES {
+            self.remove_expired(now);
+        }
+        if self.cookies.len() > MAX_COOKIES {
+            self.cookies.remove(0);
+        }
+    }
+    pub fn remove_expired(&mut self, now: u64) {
+        self.cookies.retain(|cookie| !cookie.is_expired(now));
+    }
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+    /// The value of the `Cookie` header for a request to `url`, if any
+    /// cookies go with it. Longer paths come first.
+    pub fn cookie_header(&self, url: &str, now: u64) -> Option<String> {
+        let url = Url::parse(url)?;
+        let mut cookies: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired(now) && cookie.matches(&url))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        cookies.sort_by_key(|cookie| core::cmp::Reverse(cookie.path.len()));
+        let pairs: Vec<String> = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+    /// The cookies that outlive the session, in the Netscape `cookies.txt`
+    /// format curl and wget read.
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in &self.cookies {
+            let Some(expires) = cookie.expires else {
+                continue;
+            };
+            let dot = if cookie.host_only { "" } else { "." };
+            text.push_str(&format!(
+                "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                dot,
+                cookie.domain,
+                flag(!cookie.host_only),
+                cookie.path,
+                flag(cookie.secure),
+                expires,
+                cookie.name,
+                cookie.value
+            ));
+        }
+        text
+    }
+    /// Adds the cookies from a `cookies.txt` file. Lines that don't parse are
+    /// skipped.
+    pub fn load_text(&mut self, text: &str, now: u64) {
+        for line in text.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [domain, subdomains, path, secure, expires, name, value] = fields[..] else {
+                continue;
+            };
+            let Ok(expires) = expires.parse::<u64>() else {
+                continue;
+            };
+            let cookie = Cookie {
+                name: name.to_string(),
+                value: value.to_string(),
+                domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+                host_only: subdomains != "TRUE",
+                path: path.to_string(),
+                // zero marks a session cookie in the format
+                expires: (expires != 0).then_some(expires),
+                secure: secure == "TRUE",
+            };
+            self.insert(cookie, now);
+        }
+    }
+}
+
+impl Default for CookieJar {
+    fn default() -> CookieJar {
+        CookieJar::new()
+    }
+}
+
+fn flag(value: bool) -> &'static str {
+    if value { "TRUE" } else { "FALSE" }
+}
+
+fn is_public_suffix(domain: &str) -> bool {
+    !domain.contains('.') || PUBLIC_SUFFIXES.contains(&domain)
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|rest| rest.ends_with('.'))
+}
+
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    let path = if path.is_empty() { "/" } else { path };
+    path == cookie_path
+        || path.strip_prefix(cookie_path).is_some_and(|rest| {
+            cookie_path.ends_with('/') || rest.starts_with('/')
+        })
+}
+
+/// The directory of the request path, which is where a cookie without a
+/// `Path` applies.
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(n) => path[..n].to_string(),
+    }
+}
+
+/// Parses an `Expires` date like `Wed, 21 Oct 2015 07:28:00 GMT` into seconds
+/// since the Unix epoch. Dashes between the day, month and year are allowed
+/// too, as old servers send them. Dates before 1970 become zero. Years are
+/// clamped to 1601..=9999, so the arithmetic can't overflow.
+pub(crate) fn parse_http_date(text: &str) -> Option<u64> {
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    let mut time = None;
+    let tokens = text
+        .split([' ', ',', '-'])
+        .filter(|token| !token.is_empty());
+    for token in tokens {
+        if time.is_none() && token.contains(':') {
+            let mut parts = token.split(':').map(|part| part.parse::<u64>().ok());
+            let hours = parts.next()??;
+            let minutes = parts.next()??;
+            let seconds = parts.next().unwrap_or(Some(0))?;
+            if hours > 23 || minutes > 59 || seconds > 60 {
+                return None;
+            }
+            time = Some(hours * 3600 + minutes * 60 + seconds);
+        } else if token.bytes().all(|b| b.is_ascii_digit()) {
+            // too many digits for an i64 is still a year far in the future
+            let n: i64 = token.parse().unwrap_or(i64::MAX);
+            if day.is_none() && token.len() <= 2 {
+                day = Some(n);
+            } else if year.is_none() {
+                let year_n = match (token.len(), n) {
+                    (2, 70..) => 1900 + n,
+                    (2, _) => 2000 + n,
+                    _ => n,
+                };
+                year = Some(year_n.clamp(1601, 9999));
+            }
+        } else if month.is_none() {
+            let name = token.get(..3).unwrap_or("").to_ascii_lowercase();
+            month = MONTHS.iter().position(|m| *m == name).map(|n| n as i64 + 1);
+        }
+    }
+    let (day, month, year) = (day?, month?, year?);
+    if !(1..=31).contains(&day) {
+        return None;
+    }
+    let seconds = days_from_civil(year, month, day)
+        .checked_mul(86400)?
+        .checked_add(time.unwrap_or(0) as i64)?;
+    Some(seconds.max(0) as u64)
+}
+
+/// Days since 1970-01-01 for a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    // the year is counted from March so the leap day comes last
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        // the three formats of RFC 9110, and dashes between the date parts
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784111777));
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(784111777));
+        assert_eq!(parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT"), Some(1445412480));
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_http_date("29 Feb 2024"), Some(1709164800));
+        // before 1970 is zero
+        assert_eq!(parse_http_date("Fri, 01 Jan 1960 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+        assert_eq!(parse_http_date("32 Nov 1994"), None);
+        assert_eq!(parse_http_date("Nov 1994"), None);
+        assert_eq!(parse_http_date("tomorrow"), None);
+    }
+
+    #[test]
+    fn huge_years_are_clamped() {
+        let end = parse_http_date("31 Dec 9999 23:59:59").unwrap();
+        assert_eq!(parse_http_date("1 Jan 99999999999999 00:00:00"), Some(end - 365 * 86400 + 1));
+        assert_eq!(parse_http_date("1 Jan 99999999999999999999999999 00:00:00"), parse_http_date("1 Jan 9999"));
+        assert_eq!(parse_http_date("1 Jan 0001 00:00:00"), Some(0));
+    }
+
+    #[test]
+    fn domains_must_cover_the_host() {
+        let now = 1000;
+        let set = |header: &str| Cookie::parse(header, &url("http://www.example.co.uk/a/b"), now, Clock::Wall);
+        let cookie = set("id=1").unwrap();
+        assert_eq!((cookie.domain.as_str(), cookie.host_only, cookie.path.as_str()), ("www.example.co.uk", true, "/a"));
+        let cookie = set("id=1; Domain=.Example.co.uk").unwrap();
+        assert_eq!((cookie.domain.as_str(), cookie.host_only), ("example.co.uk", false));
+        assert!(set("id=1; Domain=other.co.uk").is_none());
+        assert!(set("id=1; Domain=ample.co.uk").is_none());
+        // public suffixes
+        assert!(set("id=1; Domain=co.uk").is_none());
+        assert!(set("id=1; Domain=uk").is_none());
+        let pages = |header: &str| Cookie::parse(header, &url("https://me.github.io/"), now, Clock::Wall);
+        assert!(pages("id=1; Domain=github.io").is_none());
+        assert!(!pages("id=1; Domain=me.github.io").unwrap().host_only);
+        // the suffix itself gets a host only cookie
+        let cookie = Cookie::parse("id=1; Domain=github.io", &url("https://github.io/"), now, Clock::Wall).unwrap();
+        assert!(cookie.host_only);
+    }
+
+    #[test]
+    fn matches_domains_and_paths() {
+        let mut jar = CookieJar::new();
+        jar.set_cookie("a=1; Domain=example.com; Path=/docs", "http://www.example.com/", None, 0);
+        jar.set_cookie("b=2", "http://www.example.com/docs/page", None, 0);
+        jar.set_cookie("c=3; Secure", "https://www.example.com/", None, 0);
+        let header = |url: &str| jar.cookie_header(url, 0);
+        assert_eq!(header("http://www.example.com/docs/page").as_deref(), Some("a=1; b=2"));
+        assert_eq!(header("http://shop.example.com/docs").as_deref(), Some("a=1"));
+        assert_eq!(header("http://example.com/docsearch"), None);
+        assert_eq!(header("https://www.example.com/").as_deref(), Some("c=3"));
+        assert_eq!(header("http://notexample.com/docs"), None);
+        assert!(path_matches("/docs/", "/docs"));
+        assert!(path_matches("", "/"));
+        assert!(!path_matches("/doc", "/docs"));
+    }
+
+    #[test]
+    fn max_age_and_expires() {
+        let mut jar = CookieJar::new();
+        let now = 1_700_000_000;
+        jar.set_cookie("a=1; Max-Age=60; Expires=Thu, 01 Jan 1970 00:00:00 GMT", "http://a.com/", None, now);
+        jar.set_cookie("b=2; Expires=Wed, 01 Jan 2200 00:00:00 GMT", "http://a.com/", None, now);
+        assert_eq!(jar.cookie_header("http://a.com/", now).as_deref(), Some("a=1; b=2"));
+        assert_eq!(jar.cookie_header("http://a.com/", now + 61).as_deref(), Some("b=2"));
+        // deleting with a past date
+        jar.set_cookie("b=; Expires=Thu, 01 Jan 1970 00:00:00 GMT", "http://a.com/", None, now);
+        assert_eq!(jar.cookies().len(), 1);
+    }
+
+    #[test]
+    fn expires_without_a_wall_clock() {
+        let mut jar = CookieJar::without_wall_clock();
+        // seconds since boot
+        let now = 30;
+        let date = Some("Wed, 21 Oct 2015 07:28:00 GMT");
+        jar.set_cookie("a=1; Expires=Wed, 21 Oct 2015 08:28:00 GMT", "http://a.com/", date, now);
+        jar.set_cookie("b=2; Expires=Wed, 01 Jan 2014 00:00:00 GMT", "http://a.com/", date, now);
+        jar.set_cookie("c=3; Expires=Wed, 21 Oct 2015 08:28:00 GMT", "http://a.com/", None, now);
+        let names: Vec<(&str, Option<u64>)> = jar.cookies().iter().map(|c| (c.name.as_str(), c.expires)).collect();
+        assert_eq!(names, [("a", Some(now + 3600)), ("c", None)]);
+        // a logout that expires a cookie in the past removes it
+        jar.set_cookie("a=; Expires=Thu, 01 Jan 1970 00:00:00 GMT", "http://a.com/", date, now);
+        assert_eq!(jar.cookie_header("http://a.com/", now).as_deref(), Some("c=3"));
+    }
+
+    #[test]
+    fn cookies_txt_round_trip() {
+        let mut jar = CookieJar::new();
+        let now = 1_700_000_000;
+        jar.set_cookie("a=1; Domain=example.com; Path=/docs; Max-Age=3600", "https://www.example.com/", None, now);
+        jar.set_cookie("b=x y; Secure; Max-Age=60", "https://www.example.com/", None, now);
+        jar.set_cookie("session=1", "https://www.example.com/", None, now);
+        let text = jar.to_text();
+        assert!(text.contains(".example.com\tTRUE\t/docs\tFALSE\t1700003600\ta\t1\n"));
+        let mut loaded = CookieJar::new();
+        loaded.load_text(&text, now);
+        loaded.load_text("# comment\nbroken line\nexample.com\tFALSE\t/\tFALSE\tsoon\tc\t3\n", now);
+        // session cookies aren't saved
+        assert_eq!(loaded.cookies(), &jar.cookies()[..2]);
+        // expired ones aren't loaded
+        let mut later = CookieJar::new();
+        later.load_text(&text, now + 120);
+        assert_eq!(later.cookies().len(), 1);
+    }
+}
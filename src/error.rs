@@ -0,0 +1,174 @@
+use crate::page::Page;
+use crate::redirect::RedirectError;
+use crate::url::Url;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use nostd_html_parser::blocks::{Block, BlockType};
+use nostd_html_parser::lines::{RunStyle, TextRun};
+
+/// Why a page couldn't be loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    InvalidUrl,
+    /// the host name didn't resolve
+    Dns,
+    ConnectionRefused,
+    Tls,
+    /// the server answered with an error status
+    Status(u16),
+    Timeout,
+    /// the response didn't fit in memory
+    TooLarge,
+    Redirect(RedirectError),
+    /// anything else, with whatever the network stack said
+    Other(String),
+}
+
+impl LoadError {
+    /// Errors at or above 400 are failures. Anything else is a page to show.
+    pub fn from_status(status: u16) -> Option<LoadError> {
+        (status >= 400).then_some(LoadError::Status(status))
+    }
+    pub fn title(&self) -> &'static str {
+        match self {
+            LoadError::InvalidUrl => "Invalid address",
+            LoadError::Dns => "Server not found",
+            LoadError::ConnectionRefused => "Couldn't connect",
+            LoadError::Tls => "Secure connection failed",
+            LoadError::Status(_) => "Server error",
+            LoadError::Timeout => "Timed out",
+            LoadError::TooLarge => "Too large",
+            LoadError::Redirect(_) => "Redirect failed",
+            LoadError::Other(_) => "Can't load this page",
+        }
+    }
+    pub fn message(&self) -> String {
+        match self {
+            LoadError::InvalidUrl => "This isn't an address that can be loaded.".to_string(),
+            LoadError::Dns => "The server's name couldn't be looked up.".to_string(),
+            LoadError::ConnectionRefused => "The server refused the connection.".to_string(),
+            LoadError::Tls => "The secure connection to the server couldn't be set up.".to_string(),
+            LoadError::Status(status) => match status_text(*status) {
+                Some(text) => format!("The server answered {} {}.", status, text),
+                None => format!("The server answered {}.", status),
+            },
+            LoadError::Timeout => "The server took too long to answer.".to_string(),
+            LoadError::TooLarge => "The response is too large to load.".to_string(),
+            LoadError::Redirect(RedirectError::TooMany) => "The page redirected too many times.".to_string(),
+            LoadError::Redirect(RedirectError::Loop(url)) => format!("The page redirected back to {}.", url),
+            LoadError::Redirect(RedirectError::BadLocation) => {
+                "The page redirected without saying where to.".to_string()
+            }
+            LoadError::Other(text) => text.clone(),
+        }
+    }
+    /// A page explaining the error, with the address that failed and a link
+    /// to try it again.
+    pub fn to_page(&self, url: &str) -> Page {
+        // a retry link with a fragment would only scroll the error page
+        let retry = match Url::parse(url) {
+            Some(parsed) => parsed.without_fragment().to_string(),
+            None => url.to_string(),
+        };
+        let mut page = Page::new();
+        page.url = url.to_string();
+        page.title = Some(self.title().to_string());
+        page.blocks.push(Block::new_of_type(BlockType::Header, self.title()));
+        page.blocks.push(Block::new_of_type(BlockType::Paragraph, &self.message()));
+        page.blocks.push(Block::new_of_type(BlockType::Paragraph, url));
+        page.blocks.push(Block {
+            block_type: BlockType::Paragraph,
+            spans: vec![TextRun {
+                style: RunStyle::Link(retry),
+                text: "Try again".to_string(),
+            }],
+        });
+        page.index_links();
+        page
+    }
+}
+
+impl From<RedirectError> for LoadError {
+    fn from(err: RedirectError) -> LoadError {
+        LoadError::Redirect(err)
+    }
+}
+
+fn status_text(status: u16) -> Option<&'static str> {
+    let text = match status {
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        410 => "Gone",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => return None,
+    };
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn error_statuses() {
+        assert_eq!(LoadError::from_status(200), None);
+        assert_eq!(LoadError::from_status(304), None);
+        assert_eq!(LoadError::from_status(399), None);
+        assert_eq!(LoadError::from_status(400), Some(LoadError::Status(400)));
+        assert_eq!(LoadError::from_status(503), Some(LoadError::Status(503)));
+    }
+
+    #[test]
+    fn status_messages() {
+        assert_eq!(LoadError::Status(404).title(), "Server error");
+        assert_eq!(LoadError::Status(404).message(), "The server answered 404 Not Found.");
+        assert_eq!(LoadError::Status(418).message(), "The server answered 418.");
+        assert_eq!(
+            LoadError::Redirect(RedirectError::Loop("http://a.test/".to_string())).message(),
+            "The page redirected back to http://a.test/."
+        );
+    }
+
+    #[test]
+    fn error_page() {
+        let page = LoadError::Status(500).to_page("http://example.com/docs?v=2#intro");
+        assert_eq!(page.url, "http://example.com/docs?v=2#intro");
+        assert_eq!(page.title.as_deref(), Some("Server error"));
+        let texts: Vec<String> = page
+            .blocks
+            .iter()
+            .map(|block| block.spans.iter().map(|span| span.text.as_str()).collect())
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                "Server error",
+                "The server answered 500 Internal Server Error.",
+                "http://example.com/docs?v=2#intro",
+                "Try again"
+            ]
+        );
+        assert_eq!(page.blocks[0].block_type, BlockType::Header);
+        // retrying loads the page again rather than scrolling the error page
+        assert_eq!(page.links.len(), 1);
+        assert_eq!(page.links[0].url, "http://example.com/docs?v=2");
+    }
+
+    #[test]
+    fn error_page_for_an_unparsable_address() {
+        let page = LoadError::InvalidUrl.to_page("not a url");
+        assert_eq!(page.title.as_deref(), Some("Invalid address"));
+        assert_eq!(page.links[0].url, "not a url");
+    }
+}
@@ -0,0 +1,520 @@
+use crate::builder::{parse_page_part, PageBuilder};
+use crate::cache::{CachePolicy, PageCache};
+use crate::cookie::CookieJar;
+use crate::error::LoadError;
+use crate::image;
+use crate::image::{Bitmap, ImageBudget};
+use crate::page::Page;
+use crate::redirect;
+use crate::redirect::RedirectChain;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use log::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+/// An HTTP request for a [`Fetcher`] to send.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request<'a> {
+    pub method: Method,
+    /// without a fragment
+    pub url: &'a str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Option<&'a str>,
+}
+
+impl<'a> Request<'a> {
+    pub fn get(url: &'a str) -> Request<'a> {
+        Request {
+            method: Method::Get,
+            url,
+            headers: vec![],
+            body: None,
+        }
+    }
+    /// A POST of a URL encoded form body.
+    pub fn post(url: &'a str, body: &'a str) -> Request<'a> {
+        Request {
+            method: Method::Post,
+            url,
+            headers: vec![("Content-Type", "application/x-www-form-urlencoded".to_string())],
+            body: Some(body),
+        }
+    }
+    pub fn with_header(mut self, name: &'static str, value: &str) -> Request<'a> {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The status and headers of a response, which arrive before its body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseHead {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+impl ResponseHead {
+    /// The first header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    /// Every header called `name`, for the ones like `Set-Cookie` that repeat.
+    pub fn headers_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+    pub fn cache_policy(&self) -> CachePolicy {
+        let mut policy = CachePolicy::default();
+        for (name, value) in &self.headers {
+            policy.header(name, value);
+        }
+        policy
+    }
+}
+
+/// Takes in a response as it arrives.
+#[allow(async_fn_in_trait)]
+pub trait ResponseSink {
+    /// The status and headers. Returning false skips the body.
+    async fn head(&mut self, head: &ResponseHead) -> bool;
+    /// The next piece of the body. Returning false stops the download.
+    async fn chunk(&mut self, bytes: &[u8]) -> bool;
+}
+
+/// Sends HTTP requests. Redirects are not followed, that is up to the caller.
+///
+/// The response is pushed to a sink rather than returned as a status, headers
+/// and body stream. On the device the reqwless response borrows the connection
+/// and the header buffer of the fetcher, so a returned stream would tie its
+/// lifetime to the fetcher, and returning the whole body would put the page on
+/// the heap before it is parsed. With a sink the body is handed over a small
+/// stack buffer at a time, and the sink can stop the download early: when the
+/// status is an error, the page is cached or the line budget is used up.
+#[allow(async_fn_in_trait)]
+pub trait Fetcher {
+    /// Sends `request` and passes the response to `sink`. An error after the
+    /// head means the body was cut short.
+    async fn fetch(&mut self, request: &Request<'_>, sink: &mut impl ResponseSink) -> Result<(), LoadError>;
+}
+
+/// Where loaded pages go: previews while the body arrives, then the page.
+#[allow(async_fn_in_trait)]
+pub trait PageSink {
+    async fn show(&mut self, page: Page);
+}
+
+/// What loads share: the cookies and the recently loaded pages. Times are
+/// seconds on any clock, see [`CookieJar`] and [`PageCache`].
+pub struct Session {
+    pub cookies: CookieJar,
+    pub cache: PageCache,
+}
+
+impl Session {
+    pub const fn new(cache_bytes: usize) -> Session {
+        Session {
+            cookies: CookieJar::new(),
+            cache: PageCache::new(cache_bytes),
+        }
+    }
+    /// Loads a web page, following redirects, sending and keeping cookies
+    /// and going through the cache. A `form_body` is sent as a URL encoded
+    /// POST. Returns the URL the page ended up at and the images it wants.
+    pub async fn load(
+        &mut self,
+        fetcher: &mut impl Fetcher,
+        href: &str,
+        form_body: Option<&str>,
+        now: u64,
+        pages: &mut impl PageSink,
+    ) -> Result<(String, Vec<String>), LoadError> {
+        // the next part of a page that was too big to hold in one go
+        let (href, offset) = parse_page_part(href).unwrap_or((href, 0));
+        let mut redirects = RedirectChain::new(href);
+        let mut form_body = form_body;
+        loop {
+            let request_url = redirects.request_url();
+            // only whole pages fetched with a GET go through the cache
+            let use_cache = form_body.is_none() && offset == 0;
+            if use_cache {
+                if let Some(page) = self.cache.fresh_page(redirects.current(), now) {
+                    info!("{} is fresh in the cache", request_url);
+                    return Ok(show(pages, page).await);
+                }
+            }
+            let mut request = match form_body {
+                Some(body) => Request::post(&request_url, body),
+                None => Request::get(&request_url),
+            };
+            if let Some(cookies) = self.cookies.cookie_header(&request_url, now) {
+                request.headers.push(("Cookie", cookies));
+            }
+            if use_cache {
+                request.headers.extend(self.cache.conditional_headers(&request_url));
+            } else if form_body.is_some() {
+                // whatever was posted may have changed the page
+                self.cache.remove(&request_url);
+            }
+            let mut response = PageResponse {
+                url: redirects.current(),
+                offset,
+                head: None,
+                builder: None,
+                pages: &mut *pages,
+            };
+            let result = fetcher.fetch(&request, &mut response).await;
+            let PageResponse { head, builder, .. } = response;
+            let Some(head) = head else {
+                return Err(result.err().unwrap_or(LoadError::Other("No response".to_string())));
+            };
+            for value in head.headers_named("set-cookie") {
+                self.cookies.set_cookie(value, &request_url, head.header("date"), now);
+                redirects.cookies_changed();
+            }
+            let policy = head.cache_policy();
+            if head.status == 304 {
+                if use_cache {
+                    if let Some(page) = self.cache.revalidated(redirects.current(), &policy, now) {
+                        info!("{} hasn't changed", request_url);
+                        return Ok(show(pages, page).await);
+                    }
+                }
+                // there's no page to show, so ask again without the cache headers
+                let asked = request.header("if-none-match").is_some() || request.header("if-modified-since").is_some();
+                if !asked {
+                    return Err(LoadError::Status(304));
+                }
+                info!("{} couldn't be read from the cache, fetching it again", request_url);
+                self.cache.remove(&request_url);
+                continue;
+            }
+            if redirect::is_redirect(head.status) {
+                if !redirect::keeps_method(head.status) {
+                    form_body = None;
+                }
+                let next = redirects.follow(head.header("location"))?;
+                info!("redirected to {}", next);
+                continue;
+            }
+            if let Some(err) = LoadError::from_status(head.status) {
+                return Err(err);
+            }
+            let page = match builder {
+                Some(builder) => builder.finish(),
+                None => PageBuilder::new(redirects.current(), head.header("content-type")).finish(),
+            };
+            // keep whatever arrived before the connection broke, but not for later
+            match result {
+                Ok(()) if use_cache => self.cache.store(&page, &policy, now),
+                Ok(()) => {}
+                Err(err) => warn!("error reading the body {:?}", err),
+            }
+            return Ok(show(pages, page).await);
+        }
+    }
+}
+
+async fn show(pages: &mut impl PageSink, page: Page) -> (String, Vec<String>) {
+    let images = (page.url.clone(), page.image_sources());
+    pages.show(page).await;
+    images
+}
+
+/// Builds the page from a response body, showing previews as it goes.
+struct PageResponse<'a, P> {
+    url: &'a str,
+    offset: usize,
+    head: Option<ResponseHead>,
+    builder: Option<PageBuilder>,
+    pages: &'a mut P,
+}
+
+impl<P: PageSink> ResponseSink for PageResponse<'_, P> {
+    async fn head(&mut self, head: &ResponseHead) -> bool {
+        self.head = Some(head.clone());
+        let skip = redirect::is_redirect(head.status)
+            // a 304 means the cached page is still good, so there's no body
+            || head.status == 304
+            || LoadError::from_status(head.status).is_some();
+        if skip {
+            return false;
+        }
+        let builder = PageBuilder::new(self.url, head.header("content-type")).resume_at(self.offset);
+        let wants_body = builder.wants_body();
+        self.builder = Some(builder);
+        wants_body
+    }
+    async fn chunk(&mut self, bytes: &[u8]) -> bool {
+        let Some(builder) = self.builder.as_mut() else {
+            return false;
+        };
+        if builder.is_truncated() {
+            return false;
+        }
+        builder.push(bytes);
+        if let Some(preview) = builder.preview() {
+            self.pages.show(preview).await;
+        }
+        true
+    }
+}
+
+/// Fetches and decodes an image, following redirects. Images that fail to
+/// load or don't fit the budget come back as `None`.
+pub async fn fetch_image(fetcher: &mut impl Fetcher, src: &str, budget: &ImageBudget) -> Option<Bitmap> {
+    let mut redirects = RedirectChain::new(src);
+    loop {
+        let request_url = redirects.request_url();
+        let mut response = ImageResponse {
+            status: 0,
+            location: None,
+            bytes: vec![],
+            max_bytes: budget.max_encoded,
+            too_big: false,
+        };
+        fetcher.fetch(&Request::get(&request_url), &mut response).await.ok()?;
+        if redirect::is_redirect(response.status) {
+            redirects.follow(response.location.as_deref()).ok()?;
+            continue;
+        }
+        if !(200..300).contains(&response.status) || response.too_big {
+            return None;
+        }
+        return image::decode(&response.bytes, budget);
+    }
+}
+
+struct ImageResponse {
+    status: u16,
+    location: Option<String>,
+    bytes: Vec<u8>,
+    max_bytes: usize,
+    too_big: bool,
+}
+
+impl ResponseSink for ImageResponse {
+    async fn head(&mut self, head: &ResponseHead) -> bool {
+        self.status = head.status;
+        self.location = head.header("location").map(|location| location.to_string());
+        (200..300).contains(&head.status)
+    }
+    async fn chunk(&mut self, bytes: &[u8]) -> bool {
+        if self.bytes.len() + bytes.len() > self.max_bytes {
+            self.too_big = true;
+            return false;
+        }
+        self.bytes.extend_from_slice(bytes);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockFetcher;
+    use alloc::format;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    /// Runs a future to the end. The mock never waits, so there's no need to park.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut context = Context::from_waker(Waker::noop());
+        let mut future = pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Pages(Vec<Page>);
+
+    impl PageSink for Pages {
+        async fn show(&mut self, page: Page) {
+            self.0.push(page);
+        }
+    }
+
+    /// What a load returned and the pages it showed.
+    type Loaded = (Result<(String, Vec<String>), LoadError>, Vec<Page>);
+
+    fn load(session: &mut Session, fetcher: &mut MockFetcher, href: &str, form_body: Option<&str>, now: u64) -> Loaded {
+        let mut pages = Pages::default();
+        let result = block_on(session.load(fetcher, href, form_body, now, &mut pages));
+        (result, pages.0)
+    }
+
+    fn text(page: &Page) -> String {
+        page.blocks
+            .iter()
+            .flat_map(|block| block.spans.iter())
+            .map(|span| span.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn follows_redirects_and_keeps_the_fragment() {
+        let mut fetcher = MockFetcher::new()
+            .with_redirect("http://a.com/old", 301, "/new")
+            .with_page("http://a.com/new", "text/html", "<p>moved</p><img src='/i.png'>");
+        let mut session = Session::new(100_000);
+        let (result, pages) = load(&mut session, &mut fetcher, "http://a.com/old#top", None, 0);
+        let (url, images) = result.unwrap();
+        assert_eq!(url, "http://a.com/new#top");
+        assert_eq!(images, ["http://a.com/i.png"]);
+        assert!(text(pages.last().unwrap()).starts_with("moved"));
+        let urls: Vec<&str> = fetcher.requests.iter().map(|request| request.url.as_str()).collect();
+        assert_eq!(urls, ["http://a.com/old", "http://a.com/new"]);
+    }
+
+    #[test]
+    fn redirects_after_a_post() {
+        let mut fetcher = MockFetcher::new()
+            .with_redirect("http://a.com/form", 303, "/done")
+            .with_redirect("http://a.com/upload", 307, "/store")
+            .with_page("http://a.com/done", "text/plain", "thanks")
+            .with_page("http://a.com/store", "text/plain", "stored");
+        let mut session = Session::new(100_000);
+        load(&mut session, &mut fetcher, "http://a.com/form", Some("a=1"), 0).0.unwrap();
+        load(&mut session, &mut fetcher, "http://a.com/upload", Some("b=2"), 0).0.unwrap();
+        let sent: Vec<(Method, &str, Option<&str>)> = fetcher
+            .requests
+            .iter()
+            .map(|request| (request.method, request.url.as_str(), request.body.as_deref()))
+            .collect();
+        assert_eq!(
+            sent,
+            [
+                (Method::Post, "http://a.com/form", Some("a=1")),
+                (Method::Get, "http://a.com/done", None),
+                (Method::Post, "http://a.com/upload", Some("b=2")),
+                (Method::Post, "http://a.com/store", Some("b=2")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_loops_and_error_statuses() {
+        let mut fetcher = MockFetcher::new()
+            .with_redirect("http://a.com/a", 302, "/b")
+            .with_redirect("http://a.com/b", 302, "/a");
+        let mut session = Session::new(100_000);
+        let (result, pages) = load(&mut session, &mut fetcher, "http://a.com/a", None, 0);
+        assert!(matches!(result, Err(LoadError::Redirect(_))));
+        assert!(pages.is_empty());
+        let (result, _) = load(&mut session, &mut fetcher, "http://a.com/missing", None, 0);
+        assert_eq!(result, Err(LoadError::Status(404)));
+    }
+
+    #[test]
+    fn sends_cookies_set_on_the_way() {
+        let mut fetcher = MockFetcher::new()
+            .with_response(
+                "http://a.com/login",
+                302,
+                &[("Location", "/home"), ("Set-Cookie", "sid=1; Path=/")],
+                b"",
+            )
+            .with_page("http://a.com/home", "text/plain", "welcome")
+            .with_page("http://b.com/", "text/plain", "elsewhere");
+        let mut session = Session::new(100_000);
+        load(&mut session, &mut fetcher, "http://a.com/login", None, 0).0.unwrap();
+        load(&mut session, &mut fetcher, "http://b.com/", None, 0).0.unwrap();
+        let cookies: Vec<Option<&str>> = fetcher
+            .requests
+            .iter()
+            .map(|request| request.headers.iter().find(|(name, _)| *name == "Cookie").map(|(_, value)| value.as_str()))
+            .collect();
+        assert_eq!(cookies, [None, Some("sid=1"), None]);
+    }
+
+    #[test]
+    fn revalidates_stale_pages() {
+        let mut fetcher = MockFetcher::new().with_response(
+            "http://a.com/",
+            200,
+            &[("Content-Type", "text/plain"), ("Cache-Control", "max-age=60"), ("ETag", "\"v1\"")],
+            b"cached",
+        );
+        let mut session = Session::new(100_000);
+        load(&mut session, &mut fetcher, "http://a.com/", None, 0).0.unwrap();
+        // fresh, so not fetched again
+        let (_, pages) = load(&mut session, &mut fetcher, "http://a.com/", None, 10);
+        assert_eq!(fetcher.requests.len(), 1);
+        assert_eq!(text(&pages[0]), "cached");
+        // stale, so asked about and still good
+        fetcher.add("http://a.com/", 304, &[("Cache-Control", "max-age=60")], b"");
+        let (result, pages) = load(&mut session, &mut fetcher, "http://a.com/", None, 100);
+        assert_eq!(result.unwrap().0, "http://a.com/");
+        assert!(fetcher.requests[1].headers.contains(&("If-None-Match", "\"v1\"".to_string())));
+        assert_eq!(text(&pages[0]), "cached");
+        // and fresh again after that
+        load(&mut session, &mut fetcher, "http://a.com/", None, 150).0.unwrap();
+        assert_eq!(fetcher.requests.len(), 2);
+    }
+
+    #[test]
+    fn a_304_without_a_cached_page_is_an_error() {
+        let mut fetcher = MockFetcher::new().with_response(
+            "http://a.com/",
+            200,
+            &[("Content-Type", "text/plain"), ("ETag", "\"v1\"")],
+            b"first",
+        );
+        let mut session = Session::new(100_000);
+        load(&mut session, &mut fetcher, "http://a.com/", None, 0).0.unwrap();
+        fetcher.add("http://a.com/", 304, &[], b"");
+        session.cache.clear();
+        let (result, pages) = load(&mut session, &mut fetcher, "http://a.com/", None, 100);
+        assert_eq!(result, Err(LoadError::Status(304)));
+        assert!(pages.is_empty());
+        assert!(!fetcher.requests[1].headers.iter().any(|(name, _)| *name == "If-None-Match"));
+        // nor is a POST answered with one shown as an empty page
+        let (result, _) = load(&mut session, &mut fetcher, "http://a.com/", Some("a=1"), 100);
+        assert_eq!(result, Err(LoadError::Status(304)));
+    }
+
+    #[test]
+    fn shows_a_preview_before_the_page() {
+        let html: String = (0..200).map(|n| format!("<p>paragraph {}</p>", n)).collect();
+        let mut fetcher = MockFetcher::new().with_page("http://a.com/", "text/html", &html);
+        fetcher.chunk_size = 16;
+        let mut session = Session::new(100_000);
+        let (result, pages) = load(&mut session, &mut fetcher, "http://a.com/", None, 0);
+        result.unwrap();
+        assert_eq!(pages.len(), 2);
+        assert!(pages[0].loading);
+        assert!(pages[0].blocks.len() < 200);
+        assert!(!pages[1].loading);
+        assert_eq!(pages[1].blocks.len(), 200);
+    }
+
+    #[test]
+    fn images_over_budget_are_dropped() {
+        let budget = ImageBudget::default();
+        let mut fetcher = MockFetcher::new()
+            .with_redirect("http://a.com/r.png", 302, "/big.png")
+            .with_response("http://a.com/big.png", 200, &[], &vec![0; budget.max_encoded + 1]);
+        assert!(block_on(fetch_image(&mut fetcher, "http://a.com/r.png", &budget)).is_none());
+        assert_eq!(fetcher.requests.len(), 2);
+    }
+}
@@ -0,0 +1,348 @@
+use crate::html::{collapse_whitespace, decode_entities, RawTag, TagScanner};
+use crate::url::{form_encode, Url};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use nostd_html_parser::blocks::{Block, BlockType};
+use nostd_html_parser::lines::{RunStyle, TextRun};
+
+/// Control blocks are a single link to `form-control:<index>`.
+pub const CONTROL_HREF_PREFIX: &str = "form-control:";
+
+/// Text fields show this many characters, the end of the value if it is longer.
+const FIELD_WIDTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormMethod {
+    Get,
+    Post,
+}
+
+impl FormMethod {
+    /// The method named by a `method` attribute. Anything but POST is a GET.
+    pub fn parse(method: Option<&str>) -> FormMethod {
+        match method {
+            Some(method) if method.trim().eq_ignore_ascii_case("post") => FormMethod::Post,
+            _ => FormMethod::Get,
+        }
+    }
+}
+
+/// A `<form>`. `action` is resolved against the page.
+#[derive(Debug, Clone)]
+pub struct Form {
+    pub action: String,
+    pub method: FormMethod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControlKind {
+    Text,
+    Password,
+    Hidden,
+    Checkbox,
+    Radio,
+    Submit,
+    Select,
+    TextArea,
+}
+
+impl ControlKind {
+    /// The kind of an `<input>` from its `type`. Inputs we can't show, like
+    /// file pickers, are `None`.
+    pub fn from_input_type(kind: Option<&str>) -> Option<ControlKind> {
+        let kind = kind.map(|kind| kind.trim().to_ascii_lowercase()).unwrap_or_default();
+        match kind.as_str() {
+            "" | "text" | "search" | "email" | "url" | "tel" | "number" => Some(ControlKind::Text),
+            "password" => Some(ControlKind::Password),
+            "hidden" => Some(ControlKind::Hidden),
+            "checkbox" => Some(ControlKind::Checkbox),
+            "radio" => Some(ControlKind::Radio),
+            "submit" => Some(ControlKind::Submit),
+            _ => None,
+        }
+    }
+    /// Controls whose value is typed in.
+    pub fn is_editable(self) -> bool {
+        matches!(self, ControlKind::Text | ControlKind::Password | ControlKind::TextArea)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SelectOption {
+    pub value: String,
+    pub label: String,
+}
+
+/// An `<input>`, `<select>` or `<textarea>`.
+#[derive(Debug, Clone)]
+pub struct FormControl {
+    pub kind: ControlKind,
+    /// index into `Page::forms` of the form it belongs to
+    pub form: Option<usize>,
+    /// the block showing the control. Hidden controls, and ones left out of
+    /// reader mode, have none.
+    pub block: Option<usize>,
+    pub name: String,
+    /// the text typed in, or what a checkbox, radio button or submit button sends
+    pub value: String,
+    pub checked: bool,
+    pub options: Vec<SelectOption>,
+    /// the chosen option of a select
+    pub selected: usize,
+}
+
+impl FormControl {
+    pub fn new(kind: ControlKind, name: &str) -> FormControl {
+        FormControl {
+            kind,
+            form: None,
+            block: None,
+            name: name.to_string(),
+            value: String::new(),
+            checked: false,
+            options: vec![],
+            selected: 0,
+        }
+    }
+    /// What the control's block shows.
+    pub fn label(&self) -> String {
+        match self.kind {
+            ControlKind::Text | ControlKind::Password | ControlKind::TextArea | ControlKind::Hidden => {
+                let count = self.value.chars().count();
+                let shown: String = self
+                    .value
+                    .chars()
+                    .skip(count.saturating_sub(FIELD_WIDTH))
+                    .map(|ch| match (self.kind, ch) {
+                        (ControlKind::Password, _) => '*',
+                        (_, '\n') => ' ',
+                        (_, ch) => ch,
+                    })
+                    .collect();
+                let padding = "_".repeat(FIELD_WIDTH - shown.chars().count());
+                format!("[{}{}]", shown, padding)
+            }
+            ControlKind::Checkbox => if self.checked { "[x]" } else { "[ ]" }.to_string(),
+            ControlKind::Radio => if self.checked { "(*)" } else { "( )" }.to_string(),
+            ControlKind::Submit => {
+                let value = self.value.trim();
+                format!("< {} >", if value.is_empty() { "Submit" } else { value })
+            }
+            ControlKind::Select => {
+                let label = self.options.get(self.selected).map_or("", |option| option.label.as_str());
+                format!("[{} v]", label)
+            }
+        }
+    }
+    /// The block that shows the control: one link run, so it can be selected
+    /// like a link. `index` is the control's place in `Page::controls`.
+    pub fn to_block(&self, index: usize) -> Block {
+        Block {
+            block_type: BlockType::Paragraph,
+            spans: vec![TextRun {
+                style: RunStyle::Link(format!("{}{}", CONTROL_HREF_PREFIX, index)),
+                text: self.label(),
+            }],
+        }
+    }
+    /// The name and value this control adds to a submission, if any.
+    /// `submitter` is true for the submit button that was pressed.
+    fn entry(&self, submitter: bool) -> Option<(&str, &str)> {
+        if self.name.is_empty() {
+            return None;
+        }
+        match self.kind {
+            ControlKind::Text | ControlKind::Password | ControlKind::Hidden | ControlKind::TextArea => {
+                Some((&self.name, &self.value))
+            }
+            ControlKind::Checkbox | ControlKind::Radio if self.checked => {
+                Some((&self.name, if self.value.is_empty() { "on" } else { &self.value }))
+            }
+            ControlKind::Submit if submitter => Some((&self.name, &self.value)),
+            ControlKind::Select => self
+                .options
+                .get(self.selected)
+                .map(|option| (self.name.as_str(), option.value.as_str())),
+            _ => None,
+        }
+    }
+}
+
+/// A filled in form, ready to send. `body` is URL encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormSubmission {
+    pub method: FormMethod,
+    pub action: String,
+    pub body: String,
+}
+
+impl FormSubmission {
+    /// Collects the values of every control in `form`. `submitter` is the index
+    /// of the control that sent it.
+    pub fn new(form: &Form, controls: &[FormControl], form_index: usize, submitter: usize) -> FormSubmission {
+        let mut body = String::new();
+        for (index, control) in controls.iter().enumerate() {
+            if control.form != Some(form_index) {
+                continue;
+            }
+            if let Some((name, value)) = control.entry(index == submitter) {
+                if !body.is_empty() {
+                    body.push('&');
+                }
+                body.push_str(&form_encode(name));
+                body.push('=');
+                // line breaks in a textarea are always sent as CRLF
+                body.push_str(&form_encode(&value.replace("\r\n", "\n").replace('\n', "\r\n")));
+            }
+        }
+        FormSubmission {
+            method: form.method,
+            action: form.action.clone(),
+            body,
+        }
+    }
+    /// The URL to request. A GET sends the body as the query, in place of the
+    /// action's own.
+    pub fn url(&self) -> String {
+        let Some(mut url) = Url::parse(&self.action) else {
+            return self.action.clone();
+        };
+        url.fragment = None;
+        if self.method == FormMethod::Get {
+            url.query = Some(self.body.clone());
+        }
+        url.to_string()
+    }
+}
+
+/// The control for an `<input>`, if it is a kind we can show.
+pub(crate) fn input_control(tag: &RawTag) -> Option<FormControl> {
+    let kind = ControlKind::from_input_type(tag.attr("type").as_deref())?;
+    let mut control = FormControl::new(kind, &tag.attr("name").unwrap_or_default());
+    control.value = match (kind, tag.attr("value")) {
+        (_, Some(value)) => value,
+        (ControlKind::Checkbox | ControlKind::Radio, None) => "on".to_string(),
+        _ => String::new(),
+    };
+    control.checked = tag.attr("checked").is_some();
+    Some(control)
+}
+
+/// The control for a `<select>`, given everything up to its end tag.
+pub(crate) fn select_control(tag: &RawTag, content: &[u8]) -> FormControl {
+    let mut control = FormControl::new(ControlKind::Select, &tag.attr("name").unwrap_or_default());
+    // the value attribute and text of the option being read
+    let mut option: Option<(Option<String>, String)> = None;
+    let mut pos = 0;
+    for inner in TagScanner::new(content) {
+        if let Some((_, text)) = option.as_mut() {
+            text.push_str(&String::from_utf8_lossy(&content[pos..inner.start]));
+        }
+        pos = inner.end;
+        if inner.is("option") || inner.is("optgroup") {
+            if let Some((value, text)) = option.take() {
+                push_option(&mut control, value, &text);
+            }
+            if inner.is("option") && !inner.closing {
+                if inner.attr("selected").is_some() {
+                    control.selected = control.options.len();
+                }
+                option = Some((inner.attr("value"), String::new()));
+            }
+        }
+    }
+    if let Some((value, mut text)) = option.take() {
+        text.push_str(&String::from_utf8_lossy(&content[pos..]));
+        push_option(&mut control, value, &text);
+    }
+    control
+}
+
+fn push_option(control: &mut FormControl, value: Option<String>, text: &str) {
+    let label = collapse_whitespace(&decode_entities(text));
+    control.options.push(SelectOption {
+        value: value.unwrap_or_else(|| label.clone()),
+        label,
+    });
+}
+
+/// The control for a `<textarea>`, given the text up to its end tag.
+pub(crate) fn textarea_control(tag: &RawTag, content: &[u8]) -> FormControl {
+    let mut control = FormControl::new(ControlKind::TextArea, &tag.attr("name").unwrap_or_default());
+    let text = decode_entities(&String::from_utf8_lossy(content));
+    // a line break straight after the start tag isn't part of the value
+    let text = text.strip_prefix("\r\n").or_else(|| text.strip_prefix('\n')).unwrap_or(&text);
+    control.value = text.to_string();
+    control
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control(kind: ControlKind, name: &str, value: &str, checked: bool) -> FormControl {
+        let mut control = FormControl::new(kind, name);
+        control.form = Some(0);
+        control.value = value.to_string();
+        control.checked = checked;
+        control
+    }
+
+    fn form(method: FormMethod, action: &str) -> Form {
+        Form {
+            action: action.to_string(),
+            method,
+        }
+    }
+
+    #[test]
+    fn values_are_encoded() {
+        let controls = [
+            control(ControlKind::Text, "q", "fish & chips", false),
+            control(ControlKind::TextArea, "note", "a=1\nb", false),
+            control(ControlKind::Hidden, "", "no name", false),
+            control(ControlKind::Text, "café", "100%", false),
+        ];
+        let sent = FormSubmission::new(&form(FormMethod::Post, "http://a.com/send"), &controls, 0, usize::MAX);
+        assert_eq!(sent.body, "q=fish+%26+chips&note=a%3D1%0D%0Ab&caf%C3%A9=100%25");
+        assert_eq!(sent.url(), "http://a.com/send");
+    }
+
+    #[test]
+    fn only_checked_boxes_and_the_submitter_are_sent() {
+        let mut other_form = control(ControlKind::Text, "elsewhere", "x", false);
+        other_form.form = Some(1);
+        let mut size = FormControl::new(ControlKind::Select, "size");
+        size.form = Some(0);
+        for value in ["s", "m"] {
+            size.options.push(SelectOption {
+                value: value.to_string(),
+                label: value.to_string(),
+            });
+        }
+        size.selected = 1;
+        let controls = [
+            control(ControlKind::Checkbox, "news", "yes", false),
+            control(ControlKind::Checkbox, "terms", "", true),
+            control(ControlKind::Radio, "color", "red", false),
+            control(ControlKind::Radio, "color", "blue", true),
+            control(ControlKind::Submit, "action", "save", false),
+            control(ControlKind::Submit, "action", "delete", false),
+            other_form,
+            size,
+        ];
+        let sent = FormSubmission::new(&form(FormMethod::Post, "http://a.com/"), &controls, 0, 5);
+        assert_eq!(sent.body, "terms=on&color=blue&action=delete&size=m");
+    }
+
+    #[test]
+    fn get_replaces_the_query() {
+        let controls = [control(ControlKind::Text, "q", "two words", false)];
+        let sent = FormSubmission::new(&form(FormMethod::Get, "http://a.com/search?old=1#results"), &controls, 0, 0);
+        assert_eq!(sent.url(), "http://a.com/search?q=two+words");
+        assert_eq!(FormMethod::parse(Some(" POST ")), FormMethod::Post);
+        assert_eq!(FormMethod::parse(Some("dialog")), FormMethod::Get);
+    }
+}
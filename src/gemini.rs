@@ -0,0 +1,393 @@
+use crate::charset;
+use crate::charset::Encoding;
+use crate::html::charset_from_content_type;
+use crate::page::{Indent, Page};
+use crate::url::Url;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use nostd_html_parser::blocks::{Block, BlockType};
+use nostd_html_parser::lines::{RunStyle, TextRun};
+
+pub const DEFAULT_PORT: u16 = 1965;
+
+/// Responses end when the server closes the connection, and gemtext doesn't
+/// go through a page budget, so anything past this is dropped.
+pub const MAX_RESPONSE: usize = 32 * 1024;
+
+/// How many redirects to follow before giving up.
+pub const MAX_REDIRECTS: usize = 5;
+
+/// Links that accept a changed certificate look like `gemini-trust:<url>`.
+const TRUST_PREFIX: &str = "gemini-trust:";
+
+/// The first line of a Gemini response: a two digit status and a meta string.
+/// For successful responses the meta is the MIME type of the body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeminiHeader {
+    pub status: u8,
+    pub meta: String,
+}
+
+impl GeminiHeader {
+    pub fn parse(line: &[u8]) -> Option<GeminiHeader> {
+        let line = core::str::from_utf8(line).ok()?.trim_end_matches(['\r', '\n']);
+        let (status, meta) = match line.split_once([' ', '\t']) {
+            Some((status, meta)) => (status, meta.trim()),
+            None => (line, ""),
+        };
+        if status.len() != 2 {
+            return None;
+        }
+        Some(GeminiHeader {
+            status: status.parse().ok()?,
+            meta: meta.to_string(),
+        })
+    }
+    pub fn is_input(&self) -> bool {
+        (10..20).contains(&self.status)
+    }
+    pub fn is_success(&self) -> bool {
+        (20..30).contains(&self.status)
+    }
+    pub fn is_redirect(&self) -> bool {
+        (30..40).contains(&self.status)
+    }
+}
+
+/// Splits a response into its header and body. Returns `None` if the header
+/// line is incomplete or malformed.
+pub fn split_header(response: &[u8]) -> Option<(GeminiHeader, &[u8])> {
+    let end = response.iter().position(|b| *b == b'\n')?;
+    let header = GeminiHeader::parse(&response[..end])?;
+    Some((header, &response[end + 1..]))
+}
+
+/// The request a client sends: the absolute URL, without fragment, and CRLF.
+pub fn request_line(url: &Url) -> String {
+    format!("{}\r\n", url.without_fragment())
+}
+
+/// Turns a Gemini response into a page. Non-success statuses become a short
+/// page explaining what the server said.
+pub fn page_from_response(url: &str, header: &GeminiHeader, body: &[u8]) -> Page {
+    if header.is_input() {
+        return message_page(url, "Input requested", &header.meta);
+    }
+    if header.is_redirect() {
+        let mut page = message_page(url, "Redirect", "This capsule has moved to ");
+        let target = page.resolve(&header.meta);
+        page.blocks[1].spans.push(TextRun {
+            style: RunStyle::Link(header.meta.clone()),
+            text: target,
+        });
+        page.index_links();
+        return page;
+    }
+    if !header.is_success() {
+        let title = format!("Gemini error {}", header.status);
+        return message_page(url, &title, &header.meta);
+    }
+    let mime = header.meta.split(';').next().unwrap_or("").trim();
+    let mime = if mime.is_empty() { "text/gemini" } else { mime };
+    if !mime.starts_with("text/") {
+        return message_page(url, "Unsupported content", &format!("Can't show {}", mime));
+    }
+    if !mime.eq_ignore_ascii_case("text/gemini") {
+        return Page::from_response(body, url, Some(&header.meta));
+    }
+    // gemini bodies are UTF-8 unless the meta says otherwise
+    let encoding = charset_from_content_type(&header.meta)
+        .and_then(Encoding::from_label)
+        .unwrap_or(Encoding::Utf8);
+    let text = charset::decode(body, encoding);
+    let text = String::from_utf8_lossy(&text);
+    let mut page = gemtext_to_page(&text, url);
+    page.charset = Some(encoding.name().to_string());
+    page.lang = header.meta.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim().eq_ignore_ascii_case("lang").then(|| value.trim().to_string())
+    });
+    page
+}
+
+/// Converts gemtext to a page. Headings, list items and links map onto blocks
+/// directly; the first top level heading becomes the title.
+pub fn gemtext_to_page(text: &str, url: &str) -> Page {
+    let mut page = Page::new();
+    page.url = url.to_string();
+    let mut preformatted = false;
+    for line in text.lines() {
+        if line.starts_with("```") {
+            preformatted = !preformatted;
+            continue;
+        }
+        if preformatted {
+            let index = page.blocks.len();
+            page.blocks.push(Block::new_of_type(BlockType::Paragraph, line));
+            page.mark_preformatted(index..index + 1);
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("=>") {
+            let rest = rest.trim();
+            if rest.is_empty() {
+                continue;
+            }
+            let (href, label) = match rest.split_once([' ', '\t']) {
+                Some((href, label)) => (href, label.trim()),
+                None => (rest, rest),
+            };
+            page.blocks.push(Block {
+                block_type: BlockType::Paragraph,
+                spans: vec![TextRun {
+                    style: RunStyle::Link(href.to_string()),
+                    text: label.to_string(),
+                }],
+            });
+        } else if line.starts_with('#') {
+            let text = line.trim_start_matches('#').trim();
+            if page.title.is_none() && !line.starts_with("##") {
+                page.title = Some(text.to_string());
+            }
+            page.blocks.push(Block::new_of_type(BlockType::Header, text));
+        } else if let Some(item) = line.strip_prefix("* ") {
+            page.blocks.push(Block::new_of_type(BlockType::ListItem, item.trim()));
+        } else if let Some(quote) = line.strip_prefix('>') {
+            page.indents.push(Indent {
+                block: page.blocks.len(),
+                level: 1,
+                quotes: 1,
+                number: None,
+            });
+            page.blocks.push(Block::new_of_type(BlockType::Paragraph, quote.trim()));
+        } else if !line.trim().is_empty() {
+            page.blocks.push(Block::new_of_type(BlockType::Paragraph, line));
+        }
+    }
+    page.index_links();
+    page
+}
+
+/// The href of the link that trusts the new certificate of the capsule at
+/// `url` and loads it again.
+pub fn trust_href(url: &str) -> String {
+    format!("{}{}", TRUST_PREFIX, url)
+}
+
+/// The URL to load again from a `gemini-trust:` href.
+pub fn parse_trust(href: &str) -> Option<&str> {
+    href.strip_prefix(TRUST_PREFIX)
+}
+
+/// Shown instead of a capsule whose certificate isn't the one pinned for it,
+/// with a link to trust the new one.
+pub fn changed_certificate_page(url: &str, host: &str) -> Page {
+    let text = format!(
+        "The certificate {} presented is not the one it had before. It may have \
+         been renewed, or someone may be pretending to be it. ",
+        host
+    );
+    let mut page = message_page(url, "Certificate changed", &text);
+    page.blocks[1].spans.push(TextRun {
+        style: RunStyle::Link(trust_href(url)),
+        text: "Trust the new certificate".to_string(),
+    });
+    page.index_links();
+    page
+}
+
+fn message_page(url: &str, title: &str, text: &str) -> Page {
+    let mut page = Page::new();
+    page.url = url.to_string();
+    page.title = Some(title.to_string());
+    page.blocks.push(Block::new_of_type(BlockType::Header, title));
+    page.blocks.push(Block::new_of_type(BlockType::Paragraph, text));
+    page
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trust {
+    /// first visit, the certificate is now pinned
+    New,
+    Trusted,
+    /// the host presented a different certificate than last time
+    Changed,
+}
+
+struct KnownHost {
+    host: String,
+    port: u16,
+    certificate: Vec<u8>,
+}
+
+/// Trust on first use: the first certificate a capsule presents is remembered,
+/// and later connections must present the same one. Capsules mostly use self
+/// signed certificates, so there is no CA to check against.
+pub struct KnownHosts {
+    hosts: Vec<KnownHost>,
+}
+
+impl KnownHosts {
+    pub const fn new() -> KnownHosts {
+        KnownHosts { hosts: Vec::new() }
+    }
+    /// Checks `certificate` (DER) against the one pinned for the host, pinning
+    /// it if this is the first visit.
+    pub fn check(&mut self, host: &str, port: u16, certificate: &[u8]) -> Trust {
+        let known = self
+            .hosts
+            .iter()
+            .find(|known| known.port == port && known.host.eq_ignore_ascii_case(host));
+        match known {
+            Some(known) if known.certificate == certificate => Trust::Trusted,
+            Some(_) => Trust::Changed,
+            None => {
+                self.hosts.push(KnownHost {
+                    host: host.to_string(),
+                    port,
+                    certificate: certificate.to_vec(),
+                });
+                Trust::New
+            }
+        }
+    }
+    /// Forgets the pinned certificate, so the next one is accepted.
+    pub fn forget(&mut self, host: &str, port: u16) {
+        self.hosts
+            .retain(|known| !(known.port == port && known.host.eq_ignore_ascii_case(host)));
+    }
+    /// The pins as text, one `host port certificate` line per host with the
+    /// certificate in hex, to keep them between runs.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for known in &self.hosts {
+            text.push_str(&format!("{} {} ", known.host, known.port));
+            for byte in &known.certificate {
+                text.push_str(&format!("{:02x}", byte));
+            }
+            text.push('\n');
+        }
+        text
+    }
+    /// Adds the pins from [`to_text`](Self::to_text). Lines that don't parse
+    /// are skipped.
+    pub fn load_text(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut fields = line.split_whitespace();
+            let (Some(host), Some(port), Some(hex), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let (Ok(port), Some(certificate)) = (port.parse::<u16>(), decode_hex(hex)) else {
+                continue;
+            };
+            self.forget(host, port);
+            self.hosts.push(KnownHost {
+                host: host.to_string(),
+                port,
+                certificate,
+            });
+        }
+    }
+}
+
+impl Default for KnownHosts {
+    fn default() -> KnownHosts {
+        KnownHosts::new()
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|n| u8::from_str_radix(hex.get(n..n + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pins_and_detects_changes() {
+        let mut hosts = KnownHosts::new();
+        assert_eq!(hosts.check("capsule.org", 1965, b"first"), Trust::New);
+        assert_eq!(hosts.check("Capsule.org", 1965, b"first"), Trust::Trusted);
+        assert_eq!(hosts.check("capsule.org", 1965, b"second"), Trust::Changed);
+        assert_eq!(hosts.check("capsule.org", 1966, b"second"), Trust::New);
+        hosts.forget("capsule.org", 1965);
+        assert_eq!(hosts.check("capsule.org", 1965, b"second"), Trust::New);
+    }
+
+    #[test]
+    fn pins_survive_a_round_trip() {
+        let mut hosts = KnownHosts::new();
+        hosts.check("capsule.org", 1965, &[0, 1, 0xab, 0xff]);
+        hosts.check("other.net", 1966, b"cert");
+        let mut loaded = KnownHosts::new();
+        loaded.load_text(&hosts.to_text());
+        loaded.load_text("broken line\nhost notaport 00\nhost 1965 0g\nhost 1965 +1\n");
+        assert_eq!(loaded.check("capsule.org", 1965, &[0, 1, 0xab, 0xff]), Trust::Trusted);
+        assert_eq!(loaded.check("other.net", 1966, b"cert"), Trust::Trusted);
+        assert_eq!(loaded.check("host", 1965, b""), Trust::New);
+    }
+
+    #[test]
+    fn changed_certificate_links_to_trust() {
+        let page = changed_certificate_page("gemini://capsule.org/", "capsule.org");
+        let link = page.links.last().unwrap();
+        assert_eq!(parse_trust(&link.url), Some("gemini://capsule.org/"));
+    }
+
+    fn texts(page: &Page) -> Vec<String> {
+        page.blocks
+            .iter()
+            .map(|block| block.spans.iter().map(|span| span.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn gemtext_links() {
+        let page = gemtext_to_page(
+            "=> /docs/ Documentation\n=>page.gmi\n=>\tgemini://other.org/\tElsewhere\n=>\n",
+            "gemini://capsule.org/dir/index.gmi",
+        );
+        assert_eq!(texts(&page), ["Documentation", "page.gmi", "Elsewhere"]);
+        let urls: Vec<&str> = page.links.iter().map(|link| link.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            ["gemini://capsule.org/docs/", "gemini://capsule.org/dir/page.gmi", "gemini://other.org/"]
+        );
+    }
+
+    #[test]
+    fn gemtext_headings_quotes_and_items() {
+        let page = gemtext_to_page(
+            "## Sub first\n# Title\n### Small\n#Tight\n> quoted\n* item\n*not an item\n\nplain",
+            "gemini://capsule.org/",
+        );
+        assert_eq!(page.title.as_deref(), Some("Title"));
+        assert_eq!(texts(&page), ["Sub first", "Title", "Small", "Tight", "quoted", "item", "*not an item", "plain"]);
+        let types: Vec<BlockType> = page.blocks.iter().map(|block| block.block_type).collect();
+        assert_eq!(&types[..4], [BlockType::Header; 4]);
+        assert_eq!(types[5], BlockType::ListItem);
+        assert_eq!(types[6], BlockType::Paragraph);
+        assert_eq!(page.indents.len(), 1);
+        assert_eq!((page.indents[0].block, page.indents[0].quotes), (4, 1));
+    }
+
+    #[test]
+    fn gemtext_preformatted_toggles() {
+        let page = gemtext_to_page("```alt text\n# not a heading\n\n  => not a link\n```\nafter", "gemini://capsule.org/");
+        assert_eq!(texts(&page), ["# not a heading", "", "  => not a link", "after"]);
+        assert!((0..3).all(|block| page.is_preformatted(block)));
+        assert!(!page.is_preformatted(3));
+        assert!(page.links.is_empty());
+        assert_eq!(page.title, None);
+    }
+}
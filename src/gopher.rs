@@ -0,0 +1,222 @@
+use crate::charset;
+use crate::page::Page;
+use crate::url::{percent_decode, Url};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use nostd_html_parser::blocks::{Block, BlockType};
+use nostd_html_parser::lines::{RunStyle, TextRun};
+
+pub const DEFAULT_PORT: u16 = 70;
+
+/// Gopher has no length header, so responses are read until the server closes
+/// the connection. Anything past this is dropped.
+pub const MAX_RESPONSE: usize = 32 * 1024;
+
+/// What to send to which server for a `gopher://` URL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GopherRequest {
+    pub host: String,
+    pub port: u16,
+    pub item_type: char,
+    pub selector: String,
+}
+
+impl GopherRequest {
+    /// Splits `gopher://host:port/<type><selector>` as described in RFC 4266. A
+    /// missing type means a menu. The query of a search item is the search string.
+    pub fn from_url(url: &Url) -> Option<GopherRequest> {
+        if url.scheme != "gopher" {
+            return None;
+        }
+        let path = url.path.strip_prefix('/').unwrap_or(&url.path);
+        let mut chars = path.chars();
+        let item_type = chars.next().unwrap_or('1');
+        let mut selector = percent_decode(chars.as_str());
+        if let Some(query) = &url.query {
+            selector.push('\t');
+            selector.push_str(&percent_decode(query));
+        }
+        Some(GopherRequest {
+            host: url.host.clone()?,
+            port: url.port.unwrap_or(DEFAULT_PORT),
+            item_type,
+            selector,
+        })
+    }
+    pub fn request_line(&self) -> String {
+        format!("{}\r\n", self.selector)
+    }
+}
+
+/// The URL for a menu item. Characters that can't appear in a URL path are escaped.
+fn item_url(item_type: char, selector: &str, host: &str, port: u16) -> String {
+    let mut url = format!("gopher://{}", host);
+    if port != DEFAULT_PORT {
+        url.push_str(&format!(":{}", port));
+    }
+    url.push('/');
+    url.push(item_type);
+    push_escaped(&mut url, selector);
+    url
+}
+
+fn push_escaped(url: &mut String, text: &str) {
+    for b in text.bytes() {
+        match b {
+            b'%' | b'#' | b'?' | b' ' => url.push_str(&format!("%{:02X}", b)),
+            0x21..=0x7e => url.push(b as char),
+            _ => url.push_str(&format!("%{:02X}", b)),
+        }
+    }
+}
+
+/// True for a search item that has no search string yet, so the user has to
+/// be asked for one before it is requested.
+pub fn needs_query(href: &str) -> bool {
+    Url::parse(href)
+        .and_then(|url| GopherRequest::from_url(&url))
+        .is_some_and(|request| request.item_type == '7' && !request.selector.contains('\t'))
+}
+
+/// The URL of the search item at `href` searching for `query`: the selector
+/// followed by a tab and the query, as in RFC 4266.
+pub fn search_url(href: &str, query: &str) -> String {
+    let mut url = href.to_string();
+    url.push_str("%09");
+    push_escaped(&mut url, query);
+    url
+}
+
+fn link_block(href: String, text: &str) -> Block {
+    Block {
+        block_type: BlockType::ListItem,
+        spans: vec![TextRun {
+            style: RunStyle::Link(href),
+            text: text.to_string(),
+        }],
+    }
+}
+
+/// Lines of a response up to the `.` line that ends it. Lines starting with a
+/// dot have it doubled, so that is undone too.
+fn response_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .take_while(|line| *line != ".")
+        .map(|line| if line.starts_with("..") { &line[1..] } else { line })
+}
+
+/// Converts a gophermap into a page. Info lines become paragraphs and text,
+/// menu, search and `URL:` html items become links. Other item types are shown
+/// as plain text.
+pub fn menu_to_page(text: &str, url: &str) -> Page {
+    let mut page = Page::new();
+    page.url = url.to_string();
+    for line in response_lines(text) {
+        let mut chars = line.chars();
+        let Some(item_type) = chars.next() else {
+            continue;
+        };
+        let mut fields = chars.as_str().split('\t');
+        let display = fields.next().unwrap_or("");
+        let selector = fields.next().unwrap_or("");
+        let host = fields.next().unwrap_or("");
+        let port = fields
+            .next()
+            .and_then(|port| port.trim().parse().ok())
+            .unwrap_or(DEFAULT_PORT);
+        match item_type {
+            'i' => page.blocks.push(Block::new_of_type(BlockType::Paragraph, display)),
+            'h' if selector.starts_with("URL:") => {
+                page.blocks.push(link_block(selector[4..].to_string(), display));
+            }
+            '0' | '1' | '7' | 'h' => {
+                let text = if item_type == '7' {
+                    format!("{} (search)", display)
+                } else {
+                    display.to_string()
+                };
+                page.blocks
+                    .push(link_block(item_url(item_type, selector, host, port), &text));
+            }
+            _ => page.blocks.push(Block::new_of_type(BlockType::Paragraph, display)),
+        }
+    }
+    page.index_links();
+    page
+}
+
+/// A text item, one preformatted block per line, blank ones included, since
+/// gopher text is often laid out in columns or drawn with characters.
+pub fn text_to_page(text: &str, url: &str) -> Page {
+    let mut page = Page::new();
+    page.url = url.to_string();
+    for line in response_lines(text) {
+        page.blocks.push(Block::new_of_type(BlockType::Paragraph, line));
+    }
+    let len = page.blocks.len();
+    page.mark_preformatted(0..len);
+    page
+}
+
+/// Turns the response to `request` into a page according to its item type.
+pub fn page_from_response(url: &str, request: &GopherRequest, body: &[u8]) -> Page {
+    if request.item_type == 'h' {
+        return Page::from_bytes(body, url);
+    }
+    let encoding = charset::detect(body, None);
+    let text = charset::decode(body, encoding);
+    let text = String::from_utf8_lossy(&text);
+    let mut page = match request.item_type {
+        '1' | '7' => menu_to_page(&text, url),
+        '0' => text_to_page(&text, url),
+        other => {
+            let mut page = Page::new();
+            page.url = url.to_string();
+            let message = format!("Can't show gopher item type {}", other);
+            page.blocks.push(Block::new_of_type(BlockType::Paragraph, &message));
+            page
+        }
+    };
+    page.charset = Some(encoding.name().to_string());
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn search_items_ask_for_a_query() {
+        let page = menu_to_page("7Find things\t/find\texample.org\t70\r\n.\r\n", "gopher://example.org/");
+        let href = &page.links[0].url;
+        assert_eq!(href, "gopher://example.org/7/find");
+        assert!(needs_query(href));
+        let url = search_url(href, "tiny web");
+        assert!(!needs_query(&url));
+        let request = GopherRequest::from_url(&Url::parse(&url).unwrap()).unwrap();
+        assert_eq!(request.item_type, '7');
+        assert_eq!(request.request_line(), "/find\ttiny web\r\n");
+    }
+
+    #[test]
+    fn only_search_items_need_a_query() {
+        assert!(!needs_query("gopher://example.org/1/menu"));
+        assert!(!needs_query("gopher://example.org/7/find?old"));
+        assert!(!needs_query("https://example.org/7"));
+    }
+
+    #[test]
+    fn text_items_keep_their_layout() {
+        let text = "Name     Size\r\n\r\nfoo.txt    12\r\n..hidden\r\n.\r\nafter the end\r\n";
+        let page = text_to_page(text, "gopher://example.org/0/list");
+        let lines: Vec<String> = page
+            .blocks
+            .iter()
+            .map(|block| block.spans.iter().map(|span| span.text.as_str()).collect())
+            .collect();
+        assert_eq!(lines, ["Name     Size", "", "foo.txt    12", ".hidden"]);
+        assert!((0..4).all(|block| page.is_preformatted(block)));
+    }
+}
@@ -0,0 +1,490 @@
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use nostd_html_parser::lines::{RunStyle, TextRun};
+
+/// A single tag found by [`TagScanner`]. `start` and `end` are byte offsets
+/// of the `<` and just past the `>`.
+#[derive(Debug)]
+pub struct RawTag<'a> {
+    pub name: &'a [u8],
+    pub attrs: &'a [u8],
+    pub closing: bool,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl RawTag<'_> {
+    pub fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name.as_bytes())
+    }
+    /// Looks up an attribute by name, with character references decoded.
+    pub fn attr(&self, name: &str) -> Option<String> {
+        let mut attrs = Attributes {
+            bytes: self.attrs,
+            pos: 0,
+        };
+        while let Some((key, value)) = attrs.next() {
+            if key.eq_ignore_ascii_case(name.as_bytes()) {
+                return Some(decode_entities(&String::from_utf8_lossy(value)));
+            }
+        }
+        None
+    }
+}
+
+/// Elements that start or end a block. The document is split around these so
+/// each piece can be handed to the block parser on its own.
+const BLOCK_TAGS: [&str; 30] = [
+    "p", "div", "li", "ul", "ol", "dl", "dt", "dd", "h1", "h2", "h3", "h4", "h5", "h6",
+    "blockquote", "pre", "table", "tr", "td", "th", "section", "article", "header", "footer",
+    "nav", "main", "aside", "figure", "form", "hr",
+];
+
+impl RawTag<'_> {
+    pub fn is_block(&self) -> bool {
+        BLOCK_TAGS.iter().any(|name| self.is(name))
+    }
+    /// The anchor name this tag defines: its `id`, or the `name` of an `<a>`.
+    pub fn anchor(&self) -> Option<String> {
+        if self.closing {
+            return None;
+        }
+        match self.attr("id") {
+            Some(id) => Some(id),
+            None if self.is("a") => self.attr("name"),
+            None => None,
+        }
+    }
+}
+
+/// Walks the tags of a document without building a tree. Comments, doctypes and
+/// the contents of `<script>` and `<style>` are skipped.
+///
+/// This is not a second HTML parser: it only finds where tags are, and never
+/// decodes text or builds runs. `TagParser` from `nostd_html_parser` still
+/// does the real tokenizing, of each block segment. The builder needs what
+/// `TagParser` doesn't give it: the byte offset of every tag, to cut the
+/// document into segments and to know where a streamed chunk can be resumed,
+/// and the raw attributes of tags it never turns into blocks, like `id`,
+/// `<base>`, `<meta>` and form controls. It is a byte search for `<` with no
+/// allocation, so the extra pass costs far less than the block parsing.
+pub struct TagScanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> TagScanner<'a> {
+    pub fn new(bytes: &'a [u8]) -> TagScanner<'a> {
+        TagScanner { bytes, pos: 0 }
+    }
+    fn search(&self, from: usize, needle: &[u8]) -> Option<usize> {
+        self.bytes[from.min(self.bytes.len())..]
+            .windows(needle.len())
+            .position(|w| w.eq_ignore_ascii_case(needle))
+            .map(|n| n + from)
+    }
+    fn skip_raw_text(&mut self, name: &[u8]) {
+        let mut pos = self.pos;
+        while let Some(n) = self.search(pos, b"</") {
+            let after = &self.bytes[n + 2..];
+            if after.len() >= name.len() && after[..name.len()].eq_ignore_ascii_case(name) {
+                self.pos = n;
+                return;
+            }
+            pos = n + 2;
+        }
+        self.pos = self.bytes.len();
+    }
+}
+
+impl<'a> Iterator for TagScanner<'a> {
+    type Item = RawTag<'a>;
+
+    fn next(&mut self) -> Option<RawTag<'a>> {
+        loop {
+            let start = self.search(self.pos, b"<")?;
+            let rest = &self.bytes[start..];
+            if rest.starts_with(b"<!--") {
+                self.pos = match self.search(start + 4, b"-->") {
+                    Some(n) => n + 3,
+                    None => self.bytes.len(),
+                };
+                continue;
+            }
+            if rest.starts_with(b"<!") || rest.starts_with(b"<?") {
+                self.pos = self.search(start, b">").map(|n| n + 1).unwrap_or(self.bytes.len());
+                continue;
+            }
+            let closing = rest.get(1) == Some(&b'/');
+            let name_start = if closing { start + 2 } else { start + 1 };
+            if !self.bytes.get(name_start).is_some_and(|b| b.is_ascii_alphabetic()) {
+                self.pos = start + 1;
+                continue;
+            }
+            let mut name_end = name_start;
+            while name_end < self.bytes.len()
+                && !self.bytes[name_end].is_ascii_whitespace()
+                && self.bytes[name_end] != b'>'
+                && self.bytes[name_end] != b'/'
+            {
+                name_end += 1;
+            }
+            let end = tag_end(self.bytes, name_end)?;
+            self.pos = end;
+            let tag = RawTag {
+                name: &self.bytes[name_start..name_end],
+                attrs: &self.bytes[name_end..end - 1],
+                closing,
+                start,
+                end,
+            };
+            if !closing && (tag.is("script") || tag.is("style")) {
+                self.skip_raw_text(tag.name);
+            }
+            return Some(tag);
+        }
+    }
+}
+
+/// Finds the end of a tag, skipping over `>` inside quoted attribute values.
+fn tag_end(bytes: &[u8], from: usize) -> Option<usize> {
+    let mut quote: Option<u8> = None;
+    for (i, b) in bytes[from..].iter().enumerate() {
+        match quote {
+            Some(q) if *b == q => quote = None,
+            Some(_) => {}
+            None if *b == b'"' || *b == b'\'' => quote = Some(*b),
+            None if *b == b'>' => return Some(from + i + 1),
+            None => {}
+        }
+    }
+    None
+}
+
+struct Attributes<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Attributes<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len()
+            && (self.bytes[self.pos].is_ascii_whitespace() || self.bytes[self.pos] == b'/')
+        {
+            self.pos += 1;
+        }
+    }
+    fn next(&mut self) -> Option<(&'a [u8], &'a [u8])> {
+        self.skip_whitespace();
+        if self.pos >= self.bytes.len() {
+            return None;
+        }
+        let key_start = self.pos;
+        while self.pos < self.bytes.len()
+            && !self.bytes[self.pos].is_ascii_whitespace()
+            && self.bytes[self.pos] != b'='
+            && self.bytes[self.pos] != b'/'
+        {
+            self.pos += 1;
+        }
+        let key = &self.bytes[key_start..self.pos];
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        if self.bytes.get(self.pos) != Some(&b'=') {
+            return Some((key, &[]));
+        }
+        self.pos += 1;
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        let value = match self.bytes.get(self.pos) {
+            Some(q) if *q == b'"' || *q == b'\'' => {
+                let q = *q;
+                let start = self.pos + 1;
+                let len = self.bytes[start..]
+                    .iter()
+                    .position(|b| *b == q)
+                    .unwrap_or(self.bytes.len() - start);
+                self.pos = start + len + 1;
+                &self.bytes[start..start + len]
+            }
+            _ => {
+                let start = self.pos;
+                while self.pos < self.bytes.len() && !self.bytes[self.pos].is_ascii_whitespace() {
+                    self.pos += 1;
+                }
+                &self.bytes[start..self.pos]
+            }
+        };
+        Some((key, value))
+    }
+}
+
+/// Decodes the handful of character references that show up in attribute values
+/// and titles: the XML five, `&nbsp;` and numeric references.
+pub fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(n) = rest.find('&') {
+        out.push_str(&rest[..n]);
+        rest = &rest[n..];
+        let Some(semi) = rest.as_bytes()[..rest.len().min(12)]
+            .iter()
+            .position(|b| *b == b';')
+        else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let name = &rest[1..semi];
+        let ch = match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => {
+                if let Some(hex) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                    u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+                } else if let Some(dec) = name.strip_prefix('#') {
+                    dec.parse::<u32>().ok().and_then(char::from_u32)
+                } else {
+                    None
+                }
+            }
+        };
+        match ch {
+            Some(ch) => {
+                out.push(ch);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The text of a `<pre>` split into lines, with every space kept. Links are
+/// kept as link runs, other tags are dropped and tabs are expanded to the
+/// next multiple of 8 columns.
+pub fn preformatted_lines(bytes: &[u8]) -> Vec<Vec<TextRun>> {
+    let mut text = PreText {
+        lines: vec![vec![]],
+        column: 0,
+    };
+    let mut href: Option<String> = None;
+    let mut pos = 0;
+    for tag in TagScanner::new(bytes) {
+        text.push(&bytes[pos..tag.start], href.as_deref());
+        pos = tag.end;
+        if tag.is("a") {
+            href = if tag.closing { None } else { tag.attr("href") };
+        }
+    }
+    text.push(&bytes[pos..], href.as_deref());
+    let mut lines = text.lines;
+    // a line break right after <pre> or right before </pre> isn't content
+    if lines.len() > 1 && lines[0].is_empty() {
+        lines.remove(0);
+    }
+    if lines.len() > 1 && lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+struct PreText {
+    lines: Vec<Vec<TextRun>>,
+    column: usize,
+}
+
+impl PreText {
+    fn push(&mut self, bytes: &[u8], href: Option<&str>) {
+        if bytes.is_empty() {
+            return;
+        }
+        for ch in decode_entities(&String::from_utf8_lossy(bytes)).chars() {
+            match ch {
+                '\n' => {
+                    self.lines.push(vec![]);
+                    self.column = 0;
+                }
+                '\r' => {}
+                '\t' => {
+                    let spaces = 8 - self.column % 8;
+                    for _ in 0..spaces {
+                        self.push_char(' ', href);
+                    }
+                }
+                '\u{a0}' => self.push_char(' ', href),
+                ch => self.push_char(ch, href),
+            }
+        }
+    }
+    fn push_char(&mut self, ch: char, href: Option<&str>) {
+        self.column += 1;
+        let Some(line) = self.lines.last_mut() else {
+            return;
+        };
+        let same_style = match (line.last().map(|run| &run.style), href) {
+            (Some(RunStyle::Link(current)), Some(href)) => current == href,
+            (Some(RunStyle::Plain), None) => true,
+            _ => false,
+        };
+        match line.last_mut() {
+            Some(run) if same_style => run.text.push(ch),
+            _ => line.push(TextRun {
+                style: match href {
+                    Some(href) => RunStyle::Link(href.to_string()),
+                    None => RunStyle::Plain,
+                },
+                text: ch.to_string(),
+            }),
+        }
+    }
+}
+
+/// Document level information found in the `<head>`.
+#[derive(Debug, Default)]
+pub struct HeadInfo {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub lang: Option<String>,
+    pub charset: Option<String>,
+    pub base: Option<String>,
+}
+
+/// Pulls the title, description, language, charset and base href out of a
+/// document. Scanning stops at `<body>`.
+pub fn scan_head(bytes: &[u8]) -> HeadInfo {
+    let mut info = HeadInfo::default();
+    let mut title_start: Option<usize> = None;
+    for tag in TagScanner::new(bytes) {
+        if let Some(start) = title_start.take() {
+            let text = String::from_utf8_lossy(&bytes[start..tag.start]);
+            info.title = Some(collapse_whitespace(&decode_entities(&text)));
+        }
+        if tag.closing {
+            continue;
+        }
+        if tag.is("html") {
+            info.lang = tag.attr("lang");
+        } else if tag.is("title") && info.title.is_none() {
+            title_start = Some(tag.end);
+        } else if tag.is("base") && info.base.is_none() {
+            info.base = tag.attr("href");
+        } else if tag.is("meta") {
+            if let Some(charset) = tag.attr("charset") {
+                info.charset = Some(charset.trim().to_string());
+            }
+            let name = tag.attr("name").or_else(|| tag.attr("http-equiv"));
+            let content = tag.attr("content");
+            match (name, content) {
+                (Some(name), Some(content)) if name.eq_ignore_ascii_case("description") => {
+                    info.description = Some(collapse_whitespace(&content));
+                }
+                (Some(name), Some(content)) if name.eq_ignore_ascii_case("content-type") && info.charset.is_none() => {
+                    info.charset = charset_from_content_type(&content).map(|c| c.to_string());
+                }
+                _ => {}
+            }
+        } else if tag.is("body") {
+            break;
+        }
+    }
+    info
+}
+
+/// Extracts the `charset` parameter from a `Content-Type` value such as
+/// `text/html; charset=ISO-8859-1`.
+pub fn charset_from_content_type(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case("charset") {
+            Some(value.trim().trim_matches('"').trim_matches('\''))
+        } else {
+            None
+        }
+    })
+}
+
+pub(crate) fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for word in text.split_whitespace() {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(word);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::charset::PRESCAN_LENGTH;
+    use alloc::format;
+
+    #[test]
+    fn head_fields() {
+        let html = br#"<!DOCTYPE html><html lang="fr"><head>
+            <meta charset=" ISO-8859-1 ">
+            <title>  Caf&#233; &amp;
+              Bar </title>
+            <meta name="Description" content="A  small   place">
+            <base href="/docs/">
+            </head><body><title>Not this</title><meta charset="utf-8"></body></html>"#;
+        let info = scan_head(html);
+        assert_eq!(info.lang.as_deref(), Some("fr"));
+        assert_eq!(info.charset.as_deref(), Some("ISO-8859-1"));
+        assert_eq!(info.title.as_deref(), Some("Café & Bar"));
+        assert_eq!(info.description.as_deref(), Some("A small place"));
+        assert_eq!(info.base.as_deref(), Some("/docs/"));
+    }
+
+    #[test]
+    fn charset_from_http_equiv() {
+        let html = br#"<head><meta http-equiv="Content-Type" content="text/html; charset='windows-1252'"></head>"#;
+        assert_eq!(scan_head(html).charset.as_deref(), Some("windows-1252"));
+        // <meta charset> wins over an http-equiv after it
+        let html = br#"<meta charset="utf-8"><meta http-equiv="content-type" content="text/html; charset=koi8-r">"#;
+        assert_eq!(scan_head(html).charset.as_deref(), Some("utf-8"));
+        assert_eq!(charset_from_content_type("text/html"), None);
+        assert_eq!(charset_from_content_type("text/html; Charset=\"UTF-8\""), Some("UTF-8"));
+    }
+
+    #[test]
+    fn head_cut_short() {
+        // what the charset prescan sees of a long head
+        let html = format!("<html><head><title>Long</title>{}<meta charset=\"koi8-r\">", "<!-- padding -->".repeat(80));
+        let cut = &html.as_bytes()[..PRESCAN_LENGTH];
+        let info = scan_head(cut);
+        assert_eq!(info.title.as_deref(), Some("Long"));
+        assert_eq!(info.charset, None);
+        // cut inside the title or a tag
+        assert_eq!(scan_head(b"<title>Unfinished").title, None);
+        assert_eq!(scan_head(b"<meta charset=\"koi").charset, None);
+    }
+
+    fn line_texts(lines: &[Vec<TextRun>]) -> Vec<String> {
+        lines.iter().map(|line| line.iter().map(|run| run.text.as_str()).collect()).collect()
+    }
+
+    #[test]
+    fn preformatted_text_keeps_its_spacing() {
+        let lines = preformatted_lines(b"\nfn main() {\r\n\tlet x = 1;  \n\n  a\tb &lt;c&gt;\n");
+        assert_eq!(line_texts(&lines), ["fn main() {", "        let x = 1;  ", "", "  a     b <c>"]);
+        let lines = preformatted_lines(b"see <b>the</b> <a href=\"/docs\">docs\n page</a>.");
+        assert_eq!(line_texts(&lines), ["see the docs", " page."]);
+        assert_eq!(lines[0][1].style, RunStyle::Link("/docs".to_string()));
+        assert_eq!(lines[1][0].style, RunStyle::Link("/docs".to_string()));
+        assert_eq!(lines[1][1].style, RunStyle::Plain);
+    }
+}
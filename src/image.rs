@@ -0,0 +1,319 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::{Debug, Formatter};
+use core::mem::size_of;
+use embedded_graphics::pixelcolor::Rgb565;
+
+mod bmp;
+mod gif;
+mod jpeg;
+mod png;
+
+/// A decoded image, scaled down and dithered for the display. Pages hold it
+/// in an `Arc`, so the history and reader mode share one copy.
+#[derive(Clone)]
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    /// row by row, top to bottom
+    pub pixels: Vec<Rgb565>,
+}
+
+impl Bitmap {
+    /// The memory taken by the pixels.
+    pub fn byte_size(&self) -> usize {
+        self.pixels.len() * size_of::<Rgb565>()
+    }
+}
+
+impl Debug for Bitmap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Bitmap({}x{})", self.width, self.height)
+    }
+}
+
+/// Limits for decoding an image. Anything bigger is skipped and the page shows
+/// the alt text instead.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageBudget {
+    pub max_width: u32,
+    pub max_height: u32,
+    /// size of the downloaded file
+    pub max_encoded: usize,
+    /// memory for the full size image while it is decoded, and again for
+    /// the scaled down copy
+    pub max_decoded: usize,
+    /// memory for the finished bitmaps of one page, all together
+    pub max_total: usize,
+    /// how many images to fetch for one page
+    pub max_images: usize,
+}
+
+impl ImageBudget {
+    /// The budget for the next image of a page that already holds `used`
+    /// bytes of bitmaps.
+    pub fn remaining(&self, used: usize) -> ImageBudget {
+        ImageBudget {
+            max_total: self.max_total.saturating_sub(used),
+            ..*self
+        }
+    }
+}
+
+impl Default for ImageBudget {
+    fn default() -> Self {
+        ImageBudget {
+            max_width: 304,
+            max_height: 200,
+            max_encoded: 64 * 1024,
+            max_decoded: 192 * 1024,
+            max_total: 512 * 1024,
+            max_images: 8,
+        }
+    }
+}
+
+/// Full size RGB pixels, three bytes each, as they come out of a decoder.
+pub(crate) struct RgbImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbImage {
+    /// A white image, if it fits in `max_bytes`.
+    pub fn new(width: usize, height: usize, max_bytes: usize) -> Option<RgbImage> {
+        let size = width.checked_mul(height)?.checked_mul(3)?;
+        if width == 0 || height == 0 || size > max_bytes {
+            return None;
+        }
+        let mut pixels = Vec::new();
+        pixels.try_reserve_exact(size).ok()?;
+        pixels.resize(size, 0xff);
+        Some(RgbImage {
+            width,
+            height,
+            pixels,
+        })
+    }
+    pub fn set(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let i = (y * self.width + x) * 3;
+        self.pixels[i..i + 3].copy_from_slice(&rgb);
+    }
+}
+
+/// Blends a pixel with partial alpha onto the white page.
+pub(crate) fn over_white(rgb: [u8; 3], alpha: u8) -> [u8; 3] {
+    let blend = |c: u8| ((c as u16 * alpha as u16 + 255 * (255 - alpha as u16)) / 255) as u8;
+    [blend(rgb[0]), blend(rgb[1]), blend(rgb[2])]
+}
+
+/// Whether the bytes start like one of the image formats we can decode.
+pub fn is_image(bytes: &[u8]) -> bool {
+    png::is_png(bytes) || jpeg::is_jpeg(bytes) || gif::is_gif(bytes) || bmp::is_bmp(bytes)
+}
+
+/// Decodes a PNG, JPEG, GIF or BMP file into a bitmap that fits the budget.
+/// Only the first frame of an animated GIF is used.
+pub fn decode(bytes: &[u8], budget: &ImageBudget) -> Option<Bitmap> {
+    if bytes.len() > budget.max_encoded {
+        return None;
+    }
+    let image = if png::is_png(bytes) {
+        png::decode(bytes, budget.max_decoded)
+    } else if jpeg::is_jpeg(bytes) {
+        jpeg::decode(bytes, budget.max_decoded)
+    } else if gif::is_gif(bytes) {
+        gif::decode(bytes, budget.max_decoded)
+    } else if bmp::is_bmp(bytes) {
+        bmp::decode(bytes, budget.max_decoded)
+    } else {
+        None
+    }?;
+    let image = scale_to_fit(
+        image,
+        budget.max_width as usize,
+        budget.max_height as usize,
+        budget.max_decoded,
+    )?;
+    dither(&image, budget.max_total)
+}
+
+/// Shrinks the image to fit, averaging the source pixels under each new one.
+/// Images that already fit are returned as is. The scaled copy has to fit in
+/// `max_bytes`.
+fn scale_to_fit(image: RgbImage, max_width: usize, max_height: usize, max_bytes: usize) -> Option<RgbImage> {
+    if image.width <= max_width && image.height <= max_height {
+        return Some(image);
+    }
+    // scale by whichever side is further over, keeping the aspect ratio
+    let (width, height) = if image.width * max_height > image.height * max_width {
+        (max_width, (image.height * max_width / image.width).max(1))
+    } else {
+        ((image.width * max_height / image.height).max(1), max_height)
+    };
+    let mut out = RgbImage::new(width, height, max_bytes)?;
+    for y in 0..height {
+        let y0 = y * image.height / height;
+        let y1 = ((y + 1) * image.height / height).max(y0 + 1);
+        for x in 0..width {
+            let x0 = x * image.width / width;
+            let x1 = ((x + 1) * image.width / width).max(x0 + 1);
+            let mut sum = [0u32; 3];
+            for sy in y0..y1 {
+                let row = &image.pixels[sy * image.width * 3..];
+                for sx in x0..x1 {
+                    for c in 0..3 {
+                        sum[c] += row[sx * 3 + c] as u32;
+                    }
+                }
+            }
+            let count = ((y1 - y0) * (x1 - x0)) as u32;
+            out.set(x, y, sum.map(|s| (s / count) as u8));
+        }
+    }
+    Some(out)
+}
+
+/// Reduces 8 bit channels to Rgb565 with Floyd-Steinberg error diffusion, so
+/// gradients and photos don't band. `None` if the bitmap would take more than
+/// `max_bytes`.
+fn dither(image: &RgbImage, max_bytes: usize) -> Option<Bitmap> {
+    let width = image.width;
+    let count = width * image.height;
+    if count.checked_mul(size_of::<Rgb565>())? > max_bytes {
+        return None;
+    }
+    let mut pixels = Vec::new();
+    pixels.try_reserve_exact(count).ok()?;
+    // error for this row and the next, with a pixel of padding on each side
+    let mut current = vec![0i16; (width + 2) * 3];
+    let mut next = vec![0i16; (width + 2) * 3];
+    for y in 0..image.height {
+        for x in 0..width {
+            let mut channels = [0u8; 3];
+            for c in 0..3 {
+                let bits = if c == 1 { 6 } else { 5 };
+                let value = image.pixels[(y * width + x) * 3 + c] as i16 + current[(x + 1) * 3 + c] / 16;
+                let value = value.clamp(0, 255);
+                let level = (value >> (8 - bits)) as u8;
+                // the 8 bit value this level is displayed as
+                let shown = ((level as i16) << (8 - bits)) | ((level as i16) >> (2 * bits - 8));
+                let error = value - shown;
+                current[(x + 2) * 3 + c] += error * 7;
+                next[x * 3 + c] += error * 3;
+                next[(x + 1) * 3 + c] += error * 5;
+                next[(x + 2) * 3 + c] += error;
+                channels[c] = level;
+            }
+            pixels.push(Rgb565::new(channels[0], channels[1], channels[2]));
+        }
+        core::mem::swap(&mut current, &mut next);
+        next.iter_mut().for_each(|e| *e = 0);
+    }
+    Some(Bitmap {
+        width: width as u32,
+        height: image.height as u32,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::prelude::RgbColor;
+
+    /// 4x3, red on the left, blue on the right, stored without compression
+    pub(crate) const PNG: &[u8] = &[
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d,
+        0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x03,
+        0x08, 0x02, 0x00, 0x00, 0x00, 0x3b, 0x96, 0x39, 0x91, 0x00, 0x00, 0x00,
+        0x32, 0x49, 0x44, 0x41, 0x54, 0x78, 0x01, 0x01, 0x27, 0x00, 0xd8, 0xff,
+        0x00, 0xff, 0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0x00,
+        0x00, 0x01, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x01, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0xa6, 0xa7, 0x05, 0xfd, 0xbb, 0xd2, 0xed, 0xf7, 0x00,
+        0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+    ];
+    /// 2x2, 24 bit: white, black on top, green below
+    pub(crate) const BMP: &[u8] = &[
+        0x42, 0x4d, 0x46, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x36, 0x00,
+        0x00, 0x00, 0x28, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00,
+        0x00, 0x00, 0x01, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0x00,
+        0x00, 0x00, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    /// 2x2, red and blue on the diagonals
+    pub(crate) const GIF: &[u8] = &[
+        0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x02, 0x00, 0x02, 0x00, 0x80, 0x00,
+        0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0x2c, 0x00, 0x00, 0x00, 0x00,
+        0x02, 0x00, 0x02, 0x00, 0x00, 0x02, 0x03, 0x44, 0x02, 0x05, 0x00, 0x3b,
+    ];
+
+    #[test]
+    fn decodes_png() {
+        let bitmap = decode(PNG, &ImageBudget::default()).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (4, 3));
+        assert_eq!(bitmap.pixels[0], Rgb565::RED);
+        assert_eq!(bitmap.pixels[4], Rgb565::BLUE);
+        assert_eq!(bitmap.pixels[11], Rgb565::BLUE);
+    }
+
+    #[test]
+    fn decodes_bmp() {
+        let bitmap = decode(BMP, &ImageBudget::default()).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (2, 2));
+        assert_eq!(&bitmap.pixels[..3], [Rgb565::WHITE, Rgb565::BLACK, Rgb565::GREEN]);
+    }
+
+    #[test]
+    fn decodes_gif() {
+        let bitmap = decode(GIF, &ImageBudget::default()).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (2, 2));
+        assert_eq!(bitmap.pixels, [Rgb565::RED, Rgb565::BLUE, Rgb565::BLUE, Rgb565::RED]);
+    }
+
+    #[test]
+    fn scales_big_images_down() {
+        let budget = ImageBudget {
+            max_width: 2,
+            max_height: 2,
+            ..ImageBudget::default()
+        };
+        let bitmap = decode(PNG, &budget).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (2, 1));
+        // the scaled copy has to fit the budget too
+        let image = RgbImage::new(4, 4, 48).unwrap();
+        assert!(scale_to_fit(image, 2, 2, 12).is_some());
+        let image = RgbImage::new(4, 4, 48).unwrap();
+        assert!(scale_to_fit(image, 2, 2, 11).is_none());
+    }
+
+    #[test]
+    fn bitmaps_share_the_total() {
+        let budget = ImageBudget::default();
+        let bitmap = decode(BMP, &budget).unwrap();
+        assert_eq!(bitmap.byte_size(), 8);
+        let budget = ImageBudget {
+            max_total: 12,
+            ..budget
+        };
+        assert!(decode(BMP, &budget.remaining(4)).is_some());
+        assert!(decode(BMP, &budget.remaining(bitmap.byte_size())).is_none());
+    }
+
+    #[test]
+    fn truncated_images_are_rejected() {
+        let budget = ImageBudget::default();
+        for image in [PNG, BMP, GIF] {
+            for len in 0..image.len() - 1 {
+                // GIF frames that end early are padded, the rest fail
+                let _ = decode(&image[..len], &budget);
+            }
+        }
+        assert!(decode(&PNG[..40], &budget).is_none());
+        assert!(decode(&BMP[..60], &budget).is_none());
+    }
+}
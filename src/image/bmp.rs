@@ -0,0 +1,97 @@
+use super::RgbImage;
+#[cfg(test)]
+use alloc::vec::Vec;
+
+pub fn is_bmp(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"BM")
+}
+
+fn le16(bytes: &[u8], at: usize) -> Option<usize> {
+    let b = bytes.get(at..at + 2)?;
+    Some(u16::from_le_bytes([b[0], b[1]]) as usize)
+}
+
+fn le32(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Decodes uncompressed 1, 4, 8, 24 and 32 bit BMPs. Sizes and offsets come
+/// straight from the file, so the arithmetic on them is checked.
+pub fn decode(bytes: &[u8], max_decoded: usize) -> Option<RgbImage> {
+    let data_offset = le32(bytes, 10)? as usize;
+    let header_size = le32(bytes, 14)? as usize;
+    let width = le32(bytes, 18)? as i32;
+    let height = le32(bytes, 22)? as i32;
+    let bits = le16(bytes, 28)?;
+    let compression = le32(bytes, 30)?;
+    // 3 is bitfields, which for 32 bit images is almost always plain BGRA
+    if width <= 0 || !matches!(bits, 1 | 4 | 8 | 24 | 32) {
+        return None;
+    }
+    if !(compression == 0 || (compression == 3 && bits == 32)) {
+        return None;
+    }
+    let width = width as usize;
+    // positive heights are stored bottom up
+    let bottom_up = height > 0;
+    let height = height.unsigned_abs() as usize;
+    let palette = bytes.get(header_size.checked_add(14)?..data_offset).unwrap_or(&[]);
+    let stride = width.checked_mul(bits)?.div_ceil(32).checked_mul(4)?;
+    let mut image = RgbImage::new(width, height, max_decoded)?;
+    for row in 0..height {
+        let start = row.checked_mul(stride)?.checked_add(data_offset)?;
+        let data = bytes.get(start..start.checked_add(stride)?)?;
+        let y = if bottom_up { height - 1 - row } else { row };
+        for x in 0..width {
+            let bgr = match bits {
+                24 | 32 => {
+                    let i = x * bits / 8;
+                    [data[i], data[i + 1], data[i + 2]]
+                }
+                1 | 4 | 8 => {
+                    let bit = x * bits;
+                    let shift = 8 - bits - bit % 8;
+                    let index = ((data[bit / 8] >> shift) & ((1 << bits) - 1) as u8) as usize;
+                    match palette.get(index * 4..index * 4 + 3) {
+                        Some(entry) => [entry[0], entry[1], entry[2]],
+                        None => [0, 0, 0],
+                    }
+                }
+                _ => return None,
+            };
+            image.set(x, y, [bgr[2], bgr[1], bgr[0]]);
+        }
+    }
+    Some(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::BMP;
+
+    fn with(at: usize, value: u32) -> Vec<u8> {
+        let mut bytes = BMP.to_vec();
+        bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn huge_header_fields_are_rejected() {
+        // width, header size and data offset
+        assert!(decode(&with(18, 0x7fff_ffff), usize::MAX).is_none());
+        assert!(decode(&with(14, u32::MAX), 1024).is_some());
+        assert!(decode(&with(10, u32::MAX), 1024).is_none());
+        // bit depths that aren't supported
+        let mut bytes = BMP.to_vec();
+        bytes[28] = 2;
+        assert!(decode(&bytes, 1024).is_none());
+    }
+
+    #[test]
+    fn budget_is_checked_before_allocating() {
+        assert!(decode(BMP, 12).is_some());
+        assert!(decode(BMP, 11).is_none());
+    }
+}
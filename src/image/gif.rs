@@ -0,0 +1,253 @@
+use super::RgbImage;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub fn is_gif(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a")
+}
+
+fn le16(bytes: &[u8]) -> usize {
+    u16::from_le_bytes([bytes[0], bytes[1]]) as usize
+}
+
+struct Input<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let slice = self.bytes.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(slice)
+    }
+    fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+    /// Joins a run of data sub-blocks, each prefixed with its length.
+    fn sub_blocks(&mut self, out: &mut Vec<u8>) -> Option<()> {
+        loop {
+            let len = self.byte()? as usize;
+            if len == 0 {
+                return Some(());
+            }
+            out.extend_from_slice(self.take(len)?);
+        }
+    }
+}
+
+/// Decodes the first frame of a GIF onto a white canvas of the full screen size.
+pub fn decode(bytes: &[u8], max_decoded: usize) -> Option<RgbImage> {
+    let mut input = Input { bytes, pos: 6 };
+    let screen = input.take(7)?;
+    let width = le16(screen);
+    let height = le16(&screen[2..]);
+    let flags = screen[4];
+    let mut global: &[u8] = &[];
+    if flags & 0x80 != 0 {
+        global = input.take(3 << ((flags & 7) + 1))?;
+    }
+    let mut transparent: Option<u8> = None;
+    loop {
+        match input.byte()? {
+            // extension
+            0x21 => {
+                let label = input.byte()?;
+                let mut data = vec![];
+                input.sub_blocks(&mut data)?;
+                // graphic control extension
+                if label == 0xF9 && data.len() >= 4 && data[0] & 1 != 0 {
+                    transparent = Some(data[3]);
+                }
+            }
+            // image descriptor
+            0x2C => {
+                let descriptor = input.take(9)?;
+                let left = le16(descriptor);
+                let top = le16(&descriptor[2..]);
+                let frame_width = le16(&descriptor[4..]);
+                let frame_height = le16(&descriptor[6..]);
+                let flags = descriptor[8];
+                let mut palette = global;
+                if flags & 0x80 != 0 {
+                    palette = input.take(3 << ((flags & 7) + 1))?;
+                }
+                let interlaced = flags & 0x40 != 0;
+                let min_code_size = input.byte()?;
+                let mut data = vec![];
+                input.sub_blocks(&mut data)?;
+                let pixel_count = frame_width.checked_mul(frame_height)?;
+                if pixel_count > max_decoded / 4 {
+                    return None;
+                }
+                let indexes = lzw_decode(&data, min_code_size, pixel_count)?;
+                drop(data);
+                let mut image = RgbImage::new(width, height, max_decoded - pixel_count)?;
+                let rows = frame_rows(frame_height, interlaced);
+                for (i, index) in indexes.iter().enumerate() {
+                    if Some(*index) == transparent {
+                        continue;
+                    }
+                    let x = left + i % frame_width;
+                    let y = top + rows[i / frame_width];
+                    let offset = *index as usize * 3;
+                    if x < width && y < height {
+                        if let Some(rgb) = palette.get(offset..offset + 3) {
+                            image.set(x, y, [rgb[0], rgb[1], rgb[2]]);
+                        }
+                    }
+                }
+                return Some(image);
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// The row each stored row of a frame goes to. Interlaced frames store every
+/// 8th row first, then the 4th, then the odd halves.
+fn frame_rows(height: usize, interlaced: bool) -> Vec<usize> {
+    if !interlaced {
+        return (0..height).collect();
+    }
+    let mut rows = Vec::with_capacity(height);
+    for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+        rows.extend((start..height).step_by(step));
+    }
+    rows
+}
+
+/// Variable width LZW as used by GIF. Stops once `count` pixels are out.
+fn lzw_decode(data: &[u8], min_code_size: u8, count: usize) -> Option<Vec<u8>> {
+    if !(2..=8).contains(&min_code_size) {
+        return None;
+    }
+    let clear = 1usize << min_code_size;
+    let end = clear + 1;
+    // each code is a previous code plus one more byte. The tables are too
+    // big for the stack of a task, so they go on the heap.
+    let mut prefix: Vec<u16> = table()?;
+    let mut suffix: Vec<u8> = table()?;
+    let mut first: Vec<u8> = table()?;
+    for code in 0..clear {
+        suffix[code] = code as u8;
+        first[code] = code as u8;
+    }
+    let mut out: Vec<u8> = Vec::new();
+    out.try_reserve_exact(count).ok()?;
+    let mut stack: Vec<u8> = vec![];
+    let mut size = min_code_size as usize + 1;
+    let mut next = end + 1;
+    let mut prev: Option<usize> = None;
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    let mut bytes = data.iter();
+    while out.len() < count {
+        while bit_count < size {
+            let Some(b) = bytes.next() else {
+                return Some(pad(out, count));
+            };
+            bits |= (*b as u32) << bit_count;
+            bit_count += 8;
+        }
+        let code = (bits & ((1 << size) - 1)) as usize;
+        bits >>= size;
+        bit_count -= size;
+        if code == clear {
+            size = min_code_size as usize + 1;
+            next = end + 1;
+            prev = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+        let Some(p) = prev else {
+            if code >= clear {
+                return None;
+            }
+            out.push(code as u8);
+            prev = Some(code);
+            continue;
+        };
+        // a code not in the table yet is the previous string plus its own first byte
+        let (start, extra) = if code < next {
+            (code, None)
+        } else if code == next {
+            (p, Some(first[p]))
+        } else {
+            return None;
+        };
+        stack.clear();
+        let mut c = start;
+        loop {
+            stack.push(suffix[c]);
+            if c < clear {
+                break;
+            }
+            c = prefix[c] as usize;
+        }
+        out.extend(stack.iter().rev());
+        if let Some(extra) = extra {
+            out.push(extra);
+        }
+        if next < 4096 {
+            prefix[next] = p as u16;
+            suffix[next] = first[start];
+            first[next] = first[p];
+            next += 1;
+            if next == 1 << size && size < 12 {
+                size += 1;
+            }
+        }
+        prev = Some(code);
+    }
+    Some(pad(out, count))
+}
+
+/// A zeroed table with room for every 12 bit code.
+fn table<T: Copy + Default>() -> Option<Vec<T>> {
+    let mut table = Vec::new();
+    table.try_reserve_exact(4096).ok()?;
+    table.resize(4096, T::default());
+    Some(table)
+}
+
+/// Truncated image data leaves the rest of the frame at color 0.
+fn pad(mut out: Vec<u8>, count: usize) -> Vec<u8> {
+    out.resize(count, 0);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::GIF;
+
+    #[test]
+    fn decodes_lzw() {
+        // the frame of the test image: clear, 0, 1, 1, 0, end
+        assert_eq!(lzw_decode(&[0x44, 0x02, 0x05], 2, 4), Some(vec![0, 1, 1, 0]));
+        // a code past the next free one is an error
+        assert_eq!(lzw_decode(&[0x3c], 2, 4), None);
+        // codes that run out leave the rest at color 0
+        assert_eq!(lzw_decode(&[], 2, 3), Some(vec![0, 0, 0]));
+        assert_eq!(lzw_decode(&[0], 12, 1), None);
+    }
+
+    #[test]
+    fn frames_over_budget_are_rejected() {
+        let mut bytes = GIF.to_vec();
+        // frame size in the image descriptor
+        bytes[24..28].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        assert!(decode(&bytes, 48 * 1024).is_none());
+        assert!(decode(GIF, 4 * 4).is_some());
+        assert!(decode(GIF, 4 * 4 - 1).is_none());
+    }
+
+    #[test]
+    fn interlaced_rows() {
+        assert_eq!(frame_rows(5, false), [0, 1, 2, 3, 4]);
+        assert_eq!(frame_rows(10, true), [0, 8, 4, 2, 6, 1, 3, 5, 7, 9]);
+    }
+}
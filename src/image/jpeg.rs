@@ -0,0 +1,29 @@
+use super::RgbImage;
+use zune_jpeg::zune_core::colorspace::ColorSpace;
+use zune_jpeg::zune_core::options::DecoderOptions;
+use zune_jpeg::JpegDecoder;
+
+pub fn is_jpeg(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0xFF, 0xD8, 0xFF])
+}
+
+/// Decodes baseline and progressive JPEGs. The size is read from the headers
+/// first so oversized images are skipped before any pixels are allocated.
+pub fn decode(bytes: &[u8], max_decoded: usize) -> Option<RgbImage> {
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGB);
+    let mut decoder = JpegDecoder::new_with_options(bytes, options);
+    decoder.decode_headers().ok()?;
+    let (width, height) = decoder.dimensions()?;
+    if width.checked_mul(height)?.checked_mul(3)? > max_decoded {
+        return None;
+    }
+    let pixels = decoder.decode().ok()?;
+    if pixels.len() < width * height * 3 {
+        return None;
+    }
+    Some(RgbImage {
+        width,
+        height,
+        pixels,
+    })
+}
@@ -0,0 +1,248 @@
+use super::{over_white, RgbImage};
+use alloc::vec;
+use alloc::vec::Vec;
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+pub fn is_png(bytes: &[u8]) -> bool {
+    bytes.starts_with(SIGNATURE)
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    depth: u8,
+    color: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.color {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.depth as usize
+    }
+    /// Bytes in a row, without the filter byte. `None` for widths too big
+    /// to count.
+    fn stride(&self) -> Option<usize> {
+        Some(self.width.checked_mul(self.bits_per_pixel())?.div_ceil(8))
+    }
+}
+
+fn be32(bytes: &[u8]) -> usize {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+}
+
+/// Decodes a non-interlaced PNG of any color type and bit depth. Transparent
+/// pixels are blended onto white. Lengths and sizes come straight from the
+/// file, so the arithmetic on them is checked.
+pub fn decode(bytes: &[u8], max_decoded: usize) -> Option<RgbImage> {
+    let mut header: Option<Header> = None;
+    let mut palette: &[u8] = &[];
+    let mut palette_alpha: &[u8] = &[];
+    let mut data: Vec<u8> = vec![];
+    let mut pos = SIGNATURE.len();
+    while pos + 8 <= bytes.len() {
+        let len = be32(&bytes[pos..]);
+        let kind = &bytes[pos + 4..pos + 8];
+        let end = (pos + 8).checked_add(len)?;
+        let body = bytes.get(pos + 8..end)?;
+        match kind {
+            b"IHDR" if body.len() >= 13 => {
+                header = Some(Header {
+                    width: be32(body),
+                    height: be32(&body[4..]),
+                    depth: body[8],
+                    color: body[9],
+                    interlaced: body[12] != 0,
+                })
+            }
+            b"PLTE" => palette = body,
+            b"tRNS" => palette_alpha = body,
+            b"IDAT" => {
+                data.try_reserve(body.len()).ok()?;
+                data.extend_from_slice(body);
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+        // skip the body and the CRC
+        pos = end.checked_add(4)?;
+    }
+    let header = header?;
+    if header.interlaced || !matches!(header.depth, 1 | 2 | 4 | 8 | 16) {
+        return None;
+    }
+    let stride = header.stride()?;
+    let raw_size = header.height.checked_mul(stride.checked_add(1)?)?;
+    if raw_size > max_decoded {
+        return None;
+    }
+    let mut raw = decompress_to_vec_zlib_with_limit(&data, raw_size).ok()?;
+    drop(data);
+    if raw.len() < raw_size {
+        return None;
+    }
+    let mut image = RgbImage::new(header.width, header.height, max_decoded.saturating_sub(raw_size))?;
+    let bpp = header.bits_per_pixel().div_ceil(8);
+    let mut prev: Vec<u8> = vec![0; stride];
+    for y in 0..header.height {
+        let start = y * (stride + 1);
+        let filter = raw[start];
+        let row = &mut raw[start + 1..start + 1 + stride];
+        unfilter(filter, row, &prev, bpp)?;
+        for x in 0..header.width {
+            let rgb = pixel(&header, row, x, palette, palette_alpha);
+            image.set(x, y, rgb);
+        }
+        prev.copy_from_slice(row);
+    }
+    Some(image)
+}
+
+/// Undoes the per row filter, see the PNG spec section 9.
+fn unfilter(filter: u8, row: &mut [u8], prev: &[u8], bpp: usize) -> Option<()> {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predicted = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => return None,
+        };
+        row[i] = row[i].wrapping_add(predicted);
+    }
+    Some(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Channel `n` of pixel `x` scaled to 8 bits. Palette indexes are returned as is.
+fn sample(header: &Header, row: &[u8], x: usize, n: usize) -> u8 {
+    let index = x * header.channels() + n;
+    match header.depth {
+        8 => row[index],
+        // keep the high byte
+        16 => row[index * 2],
+        depth => {
+            let bit = index * depth as usize;
+            let byte = row[bit / 8];
+            let shift = 8 - depth as usize - bit % 8;
+            let value = (byte >> shift) & ((1 << depth) - 1);
+            if header.color == 3 {
+                value
+            } else {
+                // spread 1, 2 and 4 bit grays over the full range
+                (value as u16 * 255 / ((1 << depth) - 1) as u16) as u8
+            }
+        }
+    }
+}
+
+fn pixel(header: &Header, row: &[u8], x: usize, palette: &[u8], palette_alpha: &[u8]) -> [u8; 3] {
+    match header.color {
+        0 => {
+            let v = sample(header, row, x, 0);
+            [v, v, v]
+        }
+        2 => [
+            sample(header, row, x, 0),
+            sample(header, row, x, 1),
+            sample(header, row, x, 2),
+        ],
+        3 => {
+            let index = sample(header, row, x, 0) as usize;
+            let rgb = match palette.get(index * 3..index * 3 + 3) {
+                Some(rgb) => [rgb[0], rgb[1], rgb[2]],
+                None => [0, 0, 0],
+            };
+            over_white(rgb, palette_alpha.get(index).copied().unwrap_or(255))
+        }
+        4 => {
+            let v = sample(header, row, x, 0);
+            over_white([v, v, v], sample(header, row, x, 1))
+        }
+        _ => over_white(
+            [
+                sample(header, row, x, 0),
+                sample(header, row, x, 1),
+                sample(header, row, x, 2),
+            ],
+            sample(header, row, x, 3),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::PNG;
+
+    #[test]
+    fn huge_chunk_lengths_are_rejected() {
+        let mut bytes = PNG.to_vec();
+        bytes[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decode(&bytes, usize::MAX).is_none());
+    }
+
+    #[test]
+    fn huge_sizes_are_rejected() {
+        for (width, height) in [(u32::MAX, 1), (1, u32::MAX), (u32::MAX, u32::MAX)] {
+            let mut bytes = PNG.to_vec();
+            bytes[16..20].copy_from_slice(&width.to_be_bytes());
+            bytes[20..24].copy_from_slice(&height.to_be_bytes());
+            // 16 bits of RGBA, the most bits per pixel
+            bytes[24] = 16;
+            bytes[25] = 6;
+            assert!(decode(&bytes, usize::MAX / 2).is_none());
+        }
+    }
+
+    #[test]
+    fn stride_overflow_is_caught() {
+        let header = Header {
+            width: usize::MAX / 8,
+            height: 1,
+            depth: 16,
+            color: 6,
+            interlaced: false,
+        };
+        assert_eq!(header.stride(), None);
+    }
+
+    #[test]
+    fn unfilters_rows() {
+        let prev = [10, 20, 30, 40];
+        let mut row = [1, 2, 3, 4];
+        unfilter(1, &mut row, &prev, 2).unwrap();
+        assert_eq!(row, [1, 2, 4, 6]);
+        let mut row = [1, 2, 3, 4];
+        unfilter(2, &mut row, &prev, 2).unwrap();
+        assert_eq!(row, [11, 22, 33, 44]);
+        assert!(unfilter(5, &mut row, &prev, 2).is_none());
+        assert_eq!(paeth(10, 20, 15), 15);
+    }
+}
@@ -1,7 +1,31 @@
 #![no_std]
 extern crate alloc;
+#[cfg(any(test, feature = "std"))]
+extern crate std;
 
+pub mod archive;
 pub mod browser;
+pub mod builder;
+pub mod cache;
+pub mod charset;
 pub mod comps;
+pub mod content;
+pub mod cookie;
+pub mod error;
+pub mod fetch;
+pub mod form;
+pub mod gemini;
+pub mod gopher;
+pub mod html;
+pub mod image;
+pub mod markdown;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod page;
 pub mod pageview;
+pub mod reader;
+pub mod redirect;
+#[cfg(feature = "std")]
+pub mod reqwest_fetcher;
+pub mod search;
+pub mod url;
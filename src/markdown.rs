@@ -0,0 +1,366 @@
+use crate::page::{Indent, Page, MAX_INDENT};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use nostd_html_parser::blocks::{Block, BlockType};
+use nostd_html_parser::lines::{RunStyle, TextRun};
+
+/// Converts Markdown to a page: ATX and setext headings, bullet and numbered
+/// lists, block quotes, code blocks, and inline links, bold and code spans.
+/// The first top level heading becomes the title.
+pub fn markdown_to_page(text: &str, url: &str) -> Page {
+    let mut page = Page::new();
+    page.url = url.to_string();
+    let mut paragraph = String::new();
+    let mut fence: Option<&str> = None;
+    // the indents of the list items open around the current line
+    let mut list_indents: Vec<usize> = vec![];
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            } else {
+                push_code(&mut page, line);
+            }
+            continue;
+        }
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            flush_paragraph(&mut page, &mut paragraph);
+            list_indents.clear();
+            fence = Some(&trimmed[..3]);
+            continue;
+        }
+        if trimmed.is_empty() {
+            flush_paragraph(&mut page, &mut paragraph);
+            continue;
+        }
+        // inside a list, an indented item or quote is nested rather than code
+        let nested = !list_indents.is_empty() && (list_item(trimmed).is_some() || trimmed.starts_with('>'));
+        // an indented line that doesn't continue a paragraph is code
+        if paragraph.is_empty() && !nested && (line.starts_with("    ") || line.starts_with('\t')) {
+            list_indents.clear();
+            let code = line.strip_prefix("    ").or_else(|| line.strip_prefix('\t')).unwrap_or(line);
+            push_code(&mut page, code);
+            continue;
+        }
+        if !paragraph.is_empty() && is_setext_underline(trimmed) {
+            let heading = core::mem::take(&mut paragraph);
+            if trimmed.starts_with('=') && page.title.is_none() {
+                page.title = Some(heading.clone());
+            }
+            page.blocks.push(block_of(BlockType::Header, &heading));
+            continue;
+        }
+        if is_rule(trimmed) {
+            flush_paragraph(&mut page, &mut paragraph);
+            list_indents.clear();
+            continue;
+        }
+        if let Some((level, heading)) = atx_heading(trimmed) {
+            flush_paragraph(&mut page, &mut paragraph);
+            list_indents.clear();
+            if level == 1 && page.title.is_none() {
+                page.title = Some(heading.to_string());
+            }
+            page.blocks.push(block_of(BlockType::Header, heading));
+            continue;
+        }
+        if let Some((number, item)) = list_item(trimmed) {
+            flush_paragraph(&mut page, &mut paragraph);
+            // an item indented more than the one before is nested in it
+            let spaces = line.len() - line.trim_start().len();
+            while list_indents.last().is_some_and(|&indent| indent >= spaces) {
+                list_indents.pop();
+            }
+            list_indents.push(spaces);
+            let level = list_indents.len().min(MAX_INDENT) as u8;
+            push_indented(&mut page, block_of(BlockType::ListItem, item), level, 0, number);
+            continue;
+        }
+        if trimmed.starts_with('>') {
+            flush_paragraph(&mut page, &mut paragraph);
+            let mut quote = trimmed;
+            let mut level = 0;
+            while let Some(rest) = quote.strip_prefix('>') {
+                quote = rest.trim_start();
+                level += 1;
+            }
+            let level = level.min(MAX_INDENT) as u8;
+            let quotes = ((1u16 << level) - 1) as u8;
+            push_indented(&mut page, block_of(BlockType::Paragraph, quote), level, quotes, None);
+            continue;
+        }
+        if !paragraph.is_empty() {
+            paragraph.push(' ');
+        }
+        paragraph.push_str(trimmed);
+        list_indents.clear();
+    }
+    flush_paragraph(&mut page, &mut paragraph);
+    page.index_links();
+    page
+}
+
+fn push_indented(page: &mut Page, block: Block, level: u8, quotes: u8, number: Option<u32>) {
+    let index = page.blocks.len();
+    page.blocks.push(block);
+    page.indents.push(Indent {
+        block: index,
+        level,
+        quotes,
+        number,
+    });
+}
+
+/// A line of a code block, kept as it is.
+fn push_code(page: &mut Page, line: &str) {
+    let index = page.blocks.len();
+    page.blocks.push(Block::new_of_type(BlockType::Paragraph, line));
+    page.mark_preformatted(index..index + 1);
+}
+
+fn flush_paragraph(page: &mut Page, paragraph: &mut String) {
+    if !paragraph.is_empty() {
+        page.blocks.push(block_of(BlockType::Paragraph, paragraph));
+        paragraph.clear();
+    }
+}
+
+fn block_of(block_type: BlockType, text: &str) -> Block {
+    Block {
+        block_type,
+        spans: inline_spans(text),
+    }
+}
+
+fn is_setext_underline(line: &str) -> bool {
+    (line.starts_with('=') && line.chars().all(|ch| ch == '='))
+        || (line.starts_with('-') && line.chars().all(|ch| ch == '-'))
+}
+
+fn is_rule(line: &str) -> bool {
+    let marks: String = line.chars().filter(|ch| !ch.is_whitespace()).collect();
+    marks.len() >= 3 && ['-', '*', '_'].iter().any(|mark| marks.chars().all(|ch| ch == *mark))
+}
+
+/// `## Heading ##` gives level 2 and `Heading`.
+fn atx_heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|ch| *ch == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+/// The number, for a numbered item, and the text of a list item.
+fn list_item(line: &str) -> Option<(Option<u32>, &str)> {
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(item) = line.strip_prefix(bullet) {
+            return Some((None, item.trim()));
+        }
+    }
+    let digits = line.chars().take_while(|ch| ch.is_ascii_digit()).count();
+    let rest = &line[digits..];
+    if digits > 0 && (rest.starts_with(". ") || rest.starts_with(") ")) {
+        return Some((line[..digits].parse().ok(), rest[2..].trim()));
+    }
+    None
+}
+
+/// Splits inline Markdown into runs. Emphasis markers are dropped, images show
+/// their alt text.
+fn inline_spans(text: &str) -> Vec<TextRun> {
+    let mut spans: Vec<TextRun> = vec![];
+    let mut plain = String::new();
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        let mut special: Option<(TextRun, usize)> = None;
+        if ch == '\\' {
+            if let Some(escaped) = rest[1..].chars().next() {
+                plain.push(escaped);
+                rest = &rest[1 + escaped.len_utf8()..];
+                continue;
+            }
+        } else if ch == '[' {
+            special = link(rest).map(|(text, href, len)| {
+                (
+                    TextRun {
+                        style: RunStyle::Link(href.to_string()),
+                        text: text.to_string(),
+                    },
+                    len,
+                )
+            });
+        } else if ch == '!' && rest[1..].starts_with('[') {
+            if let Some((alt, _, len)) = link(&rest[1..]) {
+                plain.push_str(alt);
+                rest = &rest[1 + len..];
+                continue;
+            }
+        } else if ch == '<' {
+            special = autolink(rest).map(|(href, len)| {
+                (
+                    TextRun {
+                        style: RunStyle::Link(href.to_string()),
+                        text: href.to_string(),
+                    },
+                    len,
+                )
+            });
+        } else if rest.starts_with("**") || rest.starts_with("__") {
+            special = delimited(rest, &rest[..2]).map(|(inner, len)| {
+                (
+                    TextRun {
+                        style: RunStyle::Bold,
+                        text: inner.to_string(),
+                    },
+                    len,
+                )
+            });
+        } else if ch == '`' {
+            if let Some((code, len)) = delimited(rest, "`") {
+                plain.push_str(code);
+                rest = &rest[len..];
+                continue;
+            }
+        } else if ch == '*' {
+            if let Some((inner, len)) = delimited(rest, "*") {
+                plain.push_str(inner);
+                rest = &rest[len..];
+                continue;
+            }
+        }
+        match special {
+            Some((span, len)) => {
+                if !plain.is_empty() {
+                    spans.push(TextRun {
+                        style: RunStyle::Plain,
+                        text: core::mem::take(&mut plain),
+                    });
+                }
+                spans.push(span);
+                rest = &rest[len..];
+            }
+            None => {
+                plain.push(ch);
+                rest = &rest[ch.len_utf8()..];
+            }
+        }
+    }
+    if !plain.is_empty() || spans.is_empty() {
+        spans.push(TextRun {
+            style: RunStyle::Plain,
+            text: plain,
+        });
+    }
+    spans
+}
+
+/// `[text](href "title")` gives the text, the href and the length consumed.
+fn link(input: &str) -> Option<(&str, &str, usize)> {
+    let close = input.find(']')?;
+    let text = &input[1..close];
+    let after = input[close + 1..].strip_prefix('(')?;
+    let end = after.find(')')?;
+    let target = after[..end].trim();
+    let href = target.split_whitespace().next().unwrap_or("");
+    let href = href.trim_start_matches('<').trim_end_matches('>');
+    Some((text, href, close + 2 + end + 1))
+}
+
+/// `<https://example.com>` gives the URL and the length consumed.
+fn autolink(input: &str) -> Option<(&str, usize)> {
+    let end = input.find('>')?;
+    let href = &input[1..end];
+    if href.contains(' ') || !(href.contains("://") || href.starts_with("mailto:")) {
+        return None;
+    }
+    Some((href, end + 1))
+}
+
+/// Text between a pair of `marker`s, and the length consumed.
+fn delimited<'a>(input: &'a str, marker: &str) -> Option<(&'a str, usize)> {
+    let inner = &input[marker.len()..];
+    let end = inner.find(marker)?;
+    if end == 0 {
+        return None;
+    }
+    Some((&inner[..end], marker.len() + end + marker.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(page: &Page) -> Vec<String> {
+        page.blocks
+            .iter()
+            .map(|block| block.spans.iter().map(|span| span.text.as_str()).collect())
+            .collect()
+    }
+
+    fn levels(page: &Page) -> Vec<(usize, u8, Option<u32>)> {
+        page.indents.iter().map(|indent| (indent.block, indent.level, indent.number)).collect()
+    }
+
+    #[test]
+    fn headings() {
+        let page = markdown_to_page("## Second\n# First #\n#not\n\nSetext\n===\nSmaller\n---\n", "http://a/");
+        assert_eq!(page.title.as_deref(), Some("First"));
+        assert_eq!(texts(&page), ["Second", "First", "#not", "Setext", "Smaller"]);
+        let headers = page.blocks.iter().filter(|block| block.block_type == BlockType::Header).count();
+        assert_eq!(headers, 4);
+    }
+
+    #[test]
+    fn nested_lists() {
+        let text = "- one\n    - child\n        - grandchild\n  - two space child\n- two\n\n1. first\n2) second\n   > quoted\n";
+        let page = markdown_to_page(text, "http://a/");
+        assert_eq!(
+            texts(&page),
+            ["one", "child", "grandchild", "two space child", "two", "first", "second", "quoted"]
+        );
+        assert!(page.preformatted.is_empty());
+        assert_eq!(
+            &levels(&page)[..7],
+            [(0, 1, None), (1, 2, None), (2, 3, None), (3, 2, None), (4, 1, None), (5, 1, Some(1)), (6, 1, Some(2))]
+        );
+        assert_eq!(page.indents[7].quotes, 1);
+    }
+
+    #[test]
+    fn code_blocks() {
+        let text = "Text\n    still text\n\n    indented code\n\tx = 1\n```rust\n# not a heading\n\n```\n";
+        let page = markdown_to_page(text, "http://a/");
+        assert_eq!(
+            texts(&page),
+            ["Text still text", "indented code", "x = 1", "# not a heading", ""]
+        );
+        assert!((1..5).all(|block| page.is_preformatted(block)));
+        assert!(!page.is_preformatted(0));
+        assert_eq!(page.title, None);
+    }
+
+    #[test]
+    fn links_and_emphasis() {
+        let page = markdown_to_page(
+            "See [the docs](/docs \"Docs\") or <https://example.com/x>, **bold**, *em*, `code` and ![alt](i.png) \\*not em\\*.",
+            "http://a/b/",
+        );
+        let spans: Vec<(&RunStyle, &str)> = page.blocks[0].spans.iter().map(|span| (&span.style, span.text.as_str())).collect();
+        assert_eq!(spans[1], (&RunStyle::Link("/docs".to_string()), "the docs"));
+        assert_eq!(spans[3], (&RunStyle::Link("https://example.com/x".to_string()), "https://example.com/x"));
+        assert_eq!(spans[5], (&RunStyle::Bold, "bold"));
+        assert_eq!(spans[6].1, ", em, code and alt *not em*.");
+        let urls: Vec<&str> = page.links.iter().map(|link| link.url.as_str()).collect();
+        assert_eq!(urls, ["http://a/docs", "https://example.com/x"]);
+        // not links
+        let page = markdown_to_page("[no target] and <not a link> and <b>", "http://a/");
+        assert!(page.links.is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+use crate::error::LoadError;
+use crate::fetch::{Fetcher, Method, Request, ResponseHead, ResponseSink};
+use crate::url::Url;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A canned response for [`MockFetcher`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixture {
+    pub url: String,
+    pub head: ResponseHead,
+    pub body: Vec<u8>,
+}
+
+/// A request as [`MockFetcher`] received it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Option<String>,
+}
+
+/// Serves fixtures from memory instead of the network, for tests. URLs
+/// without a fixture answer 404. Every request is recorded.
+pub struct MockFetcher {
+    fixtures: Vec<Fixture>,
+    pub requests: Vec<SentRequest>,
+    /// bodies are handed over in pieces this big, like off a slow connection
+    pub chunk_size: usize,
+}
+
+impl MockFetcher {
+    pub fn new() -> MockFetcher {
+        MockFetcher {
+            fixtures: vec![],
+            requests: vec![],
+            chunk_size: 64,
+        }
+    }
+    /// Serves `body` for `url`, replacing any fixture it had.
+    pub fn with_response(mut self, url: &str, status: u16, headers: &[(&str, &str)], body: &[u8]) -> MockFetcher {
+        self.add(url, status, headers, body);
+        self
+    }
+    pub fn with_page(self, url: &str, content_type: &str, body: &str) -> MockFetcher {
+        self.with_response(url, 200, &[("Content-Type", content_type)], body.as_bytes())
+    }
+    pub fn with_redirect(self, url: &str, status: u16, location: &str) -> MockFetcher {
+        self.with_response(url, status, &[("Location", location)], &[])
+    }
+    /// Like [`with_response`](Self::with_response), for changing what a URL
+    /// serves between loads.
+    pub fn add(&mut self, url: &str, status: u16, headers: &[(&str, &str)], body: &[u8]) {
+        let url = without_fragment(url);
+        self.fixtures.retain(|fixture| fixture.url != url);
+        self.fixtures.push(Fixture {
+            url,
+            head: ResponseHead {
+                status,
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+            },
+            body: body.to_vec(),
+        });
+    }
+}
+
+impl Default for MockFetcher {
+    fn default() -> MockFetcher {
+        MockFetcher::new()
+    }
+}
+
+impl Fetcher for MockFetcher {
+    async fn fetch(&mut self, request: &Request<'_>, sink: &mut impl ResponseSink) -> Result<(), LoadError> {
+        self.requests.push(SentRequest {
+            method: request.method,
+            url: request.url.to_string(),
+            headers: request.headers.clone(),
+            body: request.body.map(|body| body.to_string()),
+        });
+        let url = without_fragment(request.url);
+        let not_found = Fixture {
+            url: url.clone(),
+            head: ResponseHead {
+                status: 404,
+                headers: vec![],
+            },
+            body: vec![],
+        };
+        let fixture = self
+            .fixtures
+            .iter()
+            .find(|fixture| fixture.url == url)
+            .unwrap_or(&not_found);
+        if !sink.head(&fixture.head).await {
+            return Ok(());
+        }
+        for chunk in fixture.body.chunks(self.chunk_size.max(1)) {
+            if !sink.chunk(chunk).await {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn without_fragment(url: &str) -> String {
+    match Url::parse(url) {
+        Some(url) => url.without_fragment().to_string(),
+        None => url.to_string(),
+    }
+}
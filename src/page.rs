@@ -1,15 +1,113 @@
+use crate::builder::{page_part_href, PageBuilder};
+use crate::form::{ControlKind, Form, FormControl, FormSubmission};
+use crate::image::Bitmap;
+use crate::url::{percent_decode, Url};
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
-use nostd_html_parser::blocks::{Block, BlockParser};
-use nostd_html_parser::tags::TagParser;
+use core::ops::Range;
+use nostd_html_parser::blocks::{Block, BlockType};
+use nostd_html_parser::lines::{RunStyle, TextRun};
 
-#[derive(Debug)]
+/// A link found in the page's blocks. `url` is `href` resolved against the page.
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub href: String,
+    pub text: String,
+    pub block: usize,
+    pub url: String,
+}
+
+/// An `id` or `<a name>` target and the block it is in.
+#[derive(Debug, Clone)]
+pub struct Anchor {
+    pub name: String,
+    pub block: usize,
+}
+
+/// An `<img>` on the page. It gets a block of its own holding the alt text,
+/// which is shown until the image has been fetched and decoded.
+#[derive(Debug, Clone)]
+pub struct PageImage {
+    pub block: usize,
+    /// the resolved URL of the image
+    pub src: String,
+    pub alt: String,
+    pub bitmap: Option<Arc<Bitmap>>,
+}
+
+/// Deeper nesting is drawn at this level.
+pub const MAX_INDENT: usize = 8;
+
+/// How deep a block sits in lists and block quotes. Blocks outside of them
+/// have no entry.
+#[derive(Debug, Clone, Copy)]
+pub struct Indent {
+    pub block: usize,
+    /// how many lists and quotes are around the block
+    pub level: u8,
+    /// bit n is set when level n + 1 is a quote
+    pub quotes: u8,
+    /// the number of an ordered list item, on its first block
+    pub number: Option<u32>,
+}
+
+/// A `<table>`. Every cell is parsed into blocks of its own, so the cells are
+/// runs of consecutive blocks in row order. Empty cells have an empty range.
+#[derive(Debug, Clone)]
+pub struct PageTable {
+    pub rows: Vec<Vec<TableCell>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableCell {
+    pub blocks: Range<usize>,
+    /// a `<th>`
+    pub header: bool,
+}
+
+impl PageTable {
+    /// All the blocks of the table.
+    pub fn blocks(&self) -> Range<usize> {
+        let mut cells = self.rows.iter().flatten();
+        let start = cells.next().map_or(0, |cell| cell.blocks.start);
+        let end = self.rows.iter().flatten().map(|cell| cell.blocks.end).max().unwrap_or(start);
+        start..end
+    }
+    pub fn column_count(&self) -> usize {
+        self.rows.iter().map(|row| row.len()).max().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Page {
     pub url: String,
-    pub links: Vec<u32>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub lang: Option<String>,
+    pub charset: Option<String>,
+    pub base: Option<String>,
+    pub links: Vec<Link>,
+    pub anchors: Vec<Anchor>,
+    pub images: Vec<PageImage>,
+    pub tables: Vec<PageTable>,
+    /// Runs of blocks from `<pre>` and code blocks, one block per line, shown
+    /// with their spacing kept instead of being wrapped.
+    pub preformatted: Vec<Range<usize>>,
+    /// in block order, so they can be looked up by block
+    pub indents: Vec<Indent>,
+    pub forms: Vec<Form>,
+    pub controls: Vec<FormControl>,
     pub selection: i32,
     pub blocks: Vec<Block>,
+    /// The blocks inside the page's `<article>`, or its `<main>` if it has no article.
+    pub main_content: Option<Range<usize>>,
+    /// Set on the early copies a [`PageBuilder`] hands out before the body is complete.
+    pub loading: bool,
+    /// Where some of the blocks start in the document, as (block, offset)
+    /// pairs, so a page that is cut short later can offer the rest from there.
+    pub part_offsets: Vec<(usize, usize)>,
 }
 
 impl Page {
@@ -18,21 +116,397 @@ impl Page {
             selection: 0,
             blocks: vec![],
             links: vec![],
+            anchors: vec![],
+            images: vec![],
+            tables: vec![],
+            preformatted: vec![],
+            indents: vec![],
+            forms: vec![],
+            controls: vec![],
             url: "".to_string(),
+            title: None,
+            description: None,
+            lang: None,
+            charset: None,
+            base: None,
+            main_content: None,
+            loading: false,
+            part_offsets: vec![],
         }
     }
 }
 
 impl Page {
-    pub fn from_bytes(bytes: &[u8], url: &str) -> Page {
-        let tags = TagParser::new(bytes);
-        let block_parser = BlockParser::new(tags);
-        let blocks = block_parser.collect();
+    /// A copy of the first `count` blocks and what goes with them. Tables
+    /// that don't fit whole are left out.
+    pub fn first_blocks(&self, count: usize) -> Page {
+        let count = count.min(self.blocks.len());
         Page {
-            url: url.to_string(),
-            links: vec![],
-            selection: 0,
-            blocks,
+            url: self.url.clone(),
+            title: self.title.clone(),
+            description: self.description.clone(),
+            lang: self.lang.clone(),
+            charset: self.charset.clone(),
+            base: self.base.clone(),
+            links: self.links.iter().filter(|link| link.block < count).cloned().collect(),
+            anchors: self.anchors.iter().filter(|anchor| anchor.block < count).cloned().collect(),
+            images: self.images.iter().filter(|image| image.block < count).cloned().collect(),
+            tables: self
+                .tables
+                .iter()
+                .filter(|table| table.blocks().end <= count)
+                .cloned()
+                .collect(),
+            preformatted: self
+                .preformatted
+                .iter()
+                .filter(|range| range.start < count)
+                .map(|range| range.start..range.end.min(count))
+                .collect(),
+            indents: self.indents.iter().filter(|indent| indent.block < count).copied().collect(),
+            forms: self.forms.clone(),
+            controls: self
+                .controls
+                .iter()
+                .filter(|control| control.block.is_none_or(|block| block < count))
+                .cloned()
+                .collect(),
+            selection: self.selection,
+            blocks: self.blocks[..count].to_vec(),
+            main_content: None,
+            loading: self.loading,
+            part_offsets: self
+                .part_offsets
+                .iter()
+                .filter(|(block, _)| *block < count)
+                .copied()
+                .collect(),
         }
     }
+    /// Drops the blocks from `count` on, and the links, images and the rest
+    /// that belong to them.
+    pub fn truncate_blocks(&mut self, count: usize) {
+        self.blocks.truncate(count);
+        self.links.retain(|link| link.block < count);
+        self.anchors.retain(|anchor| anchor.block < count);
+        self.images.retain(|image| image.block < count);
+        self.tables.retain(|table| table.blocks().end <= count);
+        self.preformatted.retain(|range| range.start < count);
+        for range in self.preformatted.iter_mut() {
+            range.end = range.end.min(count);
+        }
+        self.indents.retain(|indent| indent.block < count);
+        self.controls.retain(|control| control.block.is_none_or(|block| block < count));
+        self.part_offsets.retain(|(block, _)| *block < count);
+        if let Some(main) = self.main_content.as_mut() {
+            main.end = main.end.min(count);
+        }
+    }
+    /// Ends the page with a notice that it was cut short, and a link that
+    /// loads the rest starting at `offset` in the document.
+    pub fn push_next_part(&mut self, offset: usize) {
+        let mut notice = Block::new_of_type(BlockType::Paragraph, "Page truncated to fit in memory. ");
+        notice.spans.push(TextRun {
+            style: RunStyle::Link(page_part_href(&self.url, offset)),
+            text: "Load the next part".to_string(),
+        });
+        let index = self.blocks.len();
+        self.blocks.push(notice);
+        self.add_links(index);
+    }
+    /// The latest place before `block` that the rest of the page can be
+    /// loaded from, other than the start of this part.
+    pub fn part_offset_before(&self, block: usize) -> Option<(usize, usize)> {
+        self.part_offsets
+            .iter()
+            .rev()
+            .find(|(start, _)| *start > 0 && *start <= block)
+            .copied()
+    }
+}
+
+impl Page {
+    pub fn from_bytes(bytes: &[u8], url: &str) -> Page {
+        Page::from_response(bytes, url, None)
+    }
+    /// Builds a page from a response body, transcoding it to UTF-8 first.
+    /// `content_type` is the `Content-Type` header, if the server sent one.
+    pub fn from_response(bytes: &[u8], url: &str, content_type: Option<&str>) -> Page {
+        let mut builder = PageBuilder::new(url, content_type);
+        builder.push(bytes);
+        builder.finish()
+    }
+    /// Rebuilds `links` from the link spans of `blocks`. Adjacent spans with the
+    /// same href are treated as a single link.
+    pub fn index_links(&mut self) {
+        self.links.clear();
+        self.add_links(0);
+    }
+    /// Appends the links of `blocks[first..]` to `links`.
+    pub(crate) fn add_links(&mut self, first: usize) {
+        let base = self.base_url();
+        let mut links: Vec<Link> = vec![];
+        for (index, block) in self.blocks.iter().enumerate().skip(first) {
+            let mut in_link = false;
+            for span in &block.spans {
+                match &span.style {
+                    RunStyle::Link(href) => {
+                        if let Some(link) = links.last_mut() {
+                            if in_link && link.href == *href {
+                                link.text.push_str(&span.text);
+                                continue;
+                            }
+                        }
+                        let url = match base.as_ref().and_then(|base| base.join(href)) {
+                            Some(url) => url.to_string(),
+                            None => href.clone(),
+                        };
+                        links.push(Link {
+                            href: href.clone(),
+                            text: span.text.clone(),
+                            block: index,
+                            url,
+                        });
+                        in_link = true;
+                    }
+                    _ => in_link = false,
+                }
+            }
+        }
+        for link in links.iter_mut() {
+            link.text = link.text.trim().to_string();
+        }
+        self.links.append(&mut links);
+    }
+    /// Finds the block an `#fragment` points at.
+    pub fn find_anchor(&self, fragment: &str) -> Option<usize> {
+        let decoded = percent_decode(fragment);
+        self.anchors
+            .iter()
+            .find(|anchor| anchor.name == fragment || anchor.name == decoded)
+            .map(|anchor| anchor.block)
+    }
+    /// If following `link` stays on this page, returns the fragment it points at,
+    /// so the caller can scroll instead of fetching.
+    pub fn same_page_fragment(&self, link: &Link) -> Option<String> {
+        if let Some(fragment) = link.href.strip_prefix('#') {
+            return Some(fragment.to_string());
+        }
+        let target = Url::parse(&link.url)?;
+        let current = Url::parse(&self.url)?;
+        let fragment = target.fragment.clone()?;
+        if target.without_fragment() == current.without_fragment() {
+            Some(fragment)
+        } else {
+            None
+        }
+    }
+    pub fn indent_for_block(&self, block: usize) -> Option<&Indent> {
+        let index = self.indents.binary_search_by_key(&block, |indent| indent.block).ok()?;
+        self.indents.get(index)
+    }
+    pub fn is_preformatted(&self, block: usize) -> bool {
+        self.preformatted.iter().any(|range| range.contains(&block))
+    }
+    /// Marks blocks as preformatted, joining them onto the run before if they follow it.
+    pub(crate) fn mark_preformatted(&mut self, blocks: Range<usize>) {
+        if blocks.is_empty() {
+            return;
+        }
+        match self.preformatted.last_mut() {
+            Some(last) if last.end == blocks.start => last.end = blocks.end,
+            _ => self.preformatted.push(blocks),
+        }
+    }
+    /// The table whose first block is `block`.
+    pub fn table_at(&self, block: usize) -> Option<&PageTable> {
+        self.tables.iter().find(|table| table.blocks().start == block)
+    }
+    /// The image shown in place of a block, if that block is an image.
+    pub fn image_for_block(&self, block: usize) -> Option<&PageImage> {
+        self.images.iter().find(|image| image.block == block)
+    }
+    /// The distinct image URLs on the page, in the order they appear.
+    pub fn image_sources(&self) -> Vec<String> {
+        let mut sources: Vec<String> = vec![];
+        for image in &self.images {
+            if !sources.contains(&image.src) {
+                sources.push(image.src.clone());
+            }
+        }
+        sources
+    }
+    /// Stores a decoded image for every `<img>` with the given source.
+    pub fn set_image(&mut self, src: &str, bitmap: &Arc<Bitmap>) -> bool {
+        let mut found = false;
+        for image in self.images.iter_mut().filter(|image| image.src == src) {
+            image.bitmap = Some(bitmap.clone());
+            found = true;
+        }
+        found
+    }
+    /// The form control shown by a block.
+    pub fn control_for_block(&self, block: usize) -> Option<usize> {
+        self.controls.iter().position(|control| control.block == Some(block))
+    }
+    /// Shows the current value of a control in its block.
+    pub fn refresh_control(&mut self, index: usize) {
+        let Some(control) = self.controls.get(index) else {
+            return;
+        };
+        if let Some(block) = control.block.filter(|block| *block < self.blocks.len()) {
+            self.blocks[block] = control.to_block(index);
+        }
+    }
+    /// The control and, for a radio button, the others in its group.
+    pub fn control_group(&self, index: usize) -> Vec<usize> {
+        let Some(control) = self.controls.get(index) else {
+            return vec![];
+        };
+        if control.kind != ControlKind::Radio {
+            return vec![index];
+        }
+        (0..self.controls.len())
+            .filter(|other| {
+                let other = &self.controls[*other];
+                other.kind == ControlKind::Radio && other.form == control.form && other.name == control.name
+            })
+            .collect()
+    }
+    /// Takes the value of a control from another version of this page, like
+    /// its reader mode version, which keeps every control at the same index.
+    pub fn copy_control(&mut self, from: &Page, index: usize) {
+        let (Some(control), Some(from)) = (self.controls.get_mut(index), from.controls.get(index)) else {
+            return;
+        };
+        control.value.clone_from(&from.value);
+        control.checked = from.checked;
+        control.selected = from.selected;
+        self.refresh_control(index);
+    }
+    /// Checks a radio button and unchecks the others in its group.
+    pub fn check_radio(&mut self, index: usize) {
+        let (form, name) = match self.controls.get(index) {
+            Some(control) => (control.form, control.name.clone()),
+            None => return,
+        };
+        for other in 0..self.controls.len() {
+            let control = &mut self.controls[other];
+            if control.kind == ControlKind::Radio && control.form == form && control.name == name {
+                control.checked = other == index;
+                self.refresh_control(other);
+            }
+        }
+    }
+    /// The values of the form a control belongs to, sent as if `submitter` was pressed.
+    pub fn submit_form(&self, submitter: usize) -> Option<FormSubmission> {
+        let form_index = self.controls.get(submitter)?.form?;
+        let form = self.forms.get(form_index)?;
+        Some(FormSubmission::new(form, &self.controls, form_index, submitter))
+    }
+    /// The page title, or the URL for pages without one.
+    pub fn display_title(&self) -> &str {
+        match &self.title {
+            Some(title) if !title.is_empty() => title,
+            _ => &self.url,
+        }
+    }
+    /// The URL relative links are resolved against: the `<base href>` if the
+    /// page has one, otherwise the page's own URL.
+    pub fn base_url(&self) -> Option<Url> {
+        let page_url = Url::parse(&self.url);
+        match (&self.base, page_url) {
+            (Some(base), Some(page_url)) => page_url.join(base),
+            (Some(base), None) => Url::parse(base),
+            (None, page_url) => page_url,
+        }
+    }
+    /// Turns an href found on this page into an absolute URL. Hrefs that can't be
+    /// resolved are returned unchanged.
+    pub fn resolve(&self, href: &str) -> String {
+        match self.base_url().and_then(|base| base.join(href)) {
+            Some(url) => url.to_string(),
+            None => href.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(style: RunStyle, text: &str) -> TextRun {
+        TextRun {
+            style,
+            text: text.to_string(),
+        }
+    }
+
+    fn link(href: &str, text: &str) -> TextRun {
+        run(RunStyle::Link(href.to_string()), text)
+    }
+
+    #[test]
+    fn links_are_indexed_in_order() {
+        let mut page = Page::new();
+        page.url = "http://example.com/dir/page.html".to_string();
+        page.blocks.push(Block {
+            block_type: BlockType::Paragraph,
+            spans: vec![
+                link("/a", " Read "),
+                link("/a", "more "),
+                run(RunStyle::Plain, "or"),
+                link("b.html", "next"),
+            ],
+        });
+        page.blocks.push(Block {
+            block_type: BlockType::Paragraph,
+            spans: vec![link("/a", "again"), link("gemini://capsule.test/", "capsule")],
+        });
+        page.index_links();
+        let links: Vec<(&str, &str, usize)> = page
+            .links
+            .iter()
+            .map(|link| (link.text.as_str(), link.url.as_str(), link.block))
+            .collect();
+        assert_eq!(
+            links,
+            [
+                ("Read more", "http://example.com/a", 0),
+                ("next", "http://example.com/dir/b.html", 0),
+                // the same href in another block is a link of its own
+                ("again", "http://example.com/a", 1),
+                ("capsule", "gemini://capsule.test/", 1),
+            ]
+        );
+        assert_eq!(page.links[1].href, "b.html");
+    }
+
+    #[test]
+    fn links_resolve_against_the_base() {
+        let mut page = Page::new();
+        page.url = "http://example.com/dir/page.html".to_string();
+        page.base = Some("/docs/".to_string());
+        page.blocks.push(Block {
+            block_type: BlockType::Paragraph,
+            spans: vec![link("intro", "Intro")],
+        });
+        page.index_links();
+        assert_eq!(page.links[0].url, "http://example.com/docs/intro");
+        assert_eq!(page.resolve("#top"), "http://example.com/docs/#top");
+    }
+
+    fn block_text(page: &Page, block: usize) -> String {
+        page.blocks[block].spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    #[test]
+    fn anchors_point_at_their_blocks() {
+        let html = "<html><body><h1 id=\"top\">Title</h1><p>one</p><div id=\"café\"><p>two</p></div><a name=\"end\"></a></body></html>";
+        let page = Page::from_bytes(html.as_bytes(), "http://example.com/");
+        assert_eq!(page.find_anchor("top").map(|block| block_text(&page, block)).as_deref(), Some("Title"));
+        assert_eq!(page.find_anchor("caf%C3%A9").map(|block| block_text(&page, block)).as_deref(), Some("two"));
+        assert_eq!(page.find_anchor("end"), Some(page.blocks.len() - 1));
+        assert_eq!(page.find_anchor("missing"), None);
+    }
 }
@@ -1,16 +1,24 @@
-use crate::browser::PAGE_VIEW;
-use crate::page::Page;
+use crate::browser::{show_find_status, PAGE_VIEW};
+use crate::builder::PageBudget;
+use crate::form::{ControlKind, FormSubmission};
+use crate::image::Bitmap;
+use crate::page::{Link, Page, PageTable, TableCell, MAX_INDENT};
+use crate::reader::reader_page;
+use crate::url::Url;
 use alloc::boxed::Box;
-use alloc::string::ToString;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use alloc::{format, vec};
+use alloc::vec;
 use core::cmp::max;
+use core::ops::Range;
 use embedded_graphics::geometry::Point;
 use embedded_graphics::mono_font::ascii::FONT_9X15_BOLD;
-use iris_ui::{DrawEvent, GuiEvent};
+use iris_ui::{DrawEvent, GuiEvent, Theme};
 use log::{info, warn};
-use nostd_html_parser::blocks::BlockType;
-use nostd_html_parser::lines::{break_lines, RunStyle, TextLine};
+use nostd_html_parser::blocks::{Block, BlockType};
+use nostd_html_parser::lines::{break_lines, RunStyle, TextLine, TextRun};
 use iris_ui::geom::Bounds;
 use iris_ui::gfx::TextStyle;
 use iris_ui::input::{InputEvent, OutputAction, TextAction};
@@ -18,33 +26,430 @@ use iris_ui::view::{Align, View};
 
 pub struct RenderedPage {
     pub link_count: i32,
-    pub lines: Vec<TextLine>,
+    /// None for preformatted lines, which are drawn straight from their block.
+    pub lines: Vec<Option<TextLine>>,
+    pub line_info: Vec<LineInfo>,
     pub page: Page,
+    /// The full page when `page` is its reader mode version.
+    pub original: Option<Page>,
     pub scroll_index: i32,
+    /// how many characters preformatted lines are scrolled to the left
+    pub scroll_x: usize,
+    /// characters in the longest preformatted line
+    pub preformatted_width: usize,
 }
+
+/// Text found by a search, `len` characters from column `start` of a line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextMatch {
+    pub line: usize,
+    pub start: usize,
+    pub len: usize,
+}
+
+/// A search of the current page and the match shown.
+#[derive(Debug, Clone)]
+pub struct FindState {
+    pub query: String,
+    pub matches: Vec<TextMatch>,
+    pub current: usize,
+}
+
+/// Searches stop here, so a one letter query can't fill the heap.
+const MAX_MATCHES: usize = 500;
+
+/// Where a rendered line came from. `first_link` is the index into `Page::links`
+/// of the first link run on the line, or of the next link if the line has none.
+/// An `image` line draws the decoded image of its block instead of the text.
+/// Table cells start `column` characters in, and all but the first cell of a
+/// row are `same_row` so they are drawn beside the one before.
+#[derive(Debug, Clone, Copy)]
+pub struct LineInfo {
+    pub block: usize,
+    pub first_link: usize,
+    pub image: bool,
+    pub column: u32,
+    pub same_row: bool,
+    pub preformatted: bool,
+}
+
+/// How far the left and right keys scroll preformatted lines, in characters.
+const SCROLL_X_STEP: usize = 8;
+
+const LINE_HEIGHT: u32 = FONT_9X15_BOLD.character_size.height + 2;
+
+/// Tables are drawn as a grid if every column can get this many characters.
+const MIN_COLUMN: usize = 6;
+
+/// Each level of list or quote nesting moves a block this many characters in.
+const INDENT_COLUMNS: u32 = 3;
+
 impl RenderedPage {
+    /// Breaks the lines of a block again after its text changed. False when
+    /// that can't be done in place, for blocks in tables or ones that now
+    /// take a different number of lines, so the page has to be rendered again.
+    fn relayout_block(&mut self, block: usize, columns: u32) -> bool {
+        if self.page.tables.iter().any(|table| table.blocks().contains(&block)) {
+            return false;
+        }
+        let Some(first) = self.line_info.iter().position(|info| info.block == block) else {
+            return true;
+        };
+        let count = self.line_info[first..].iter().take_while(|info| info.block == block).count();
+        let info = self.line_info[first];
+        if info.image || info.preformatted {
+            return false;
+        }
+        let mut next_link = info.first_link;
+        let lines = break_block(&self.page.blocks[block], block, columns.saturating_sub(info.column), &mut next_link);
+        if lines.len() != count {
+            return false;
+        }
+        for (n, (line, mut new_info)) in lines.into_iter().enumerate() {
+            new_info.column = info.column;
+            self.lines[first + n] = line;
+            self.line_info[first + n] = new_info;
+        }
+        true
+    }
     pub fn find_href_by_index(&self, index: i32) -> Option<&str> {
-        // info!("find_href_by_index: {}", index);
-        // // blocks of spans -> text lines of text runs
-        let mut count = 0;
-        for line in &self.lines {
-            for run in &line.runs {
-                match &run.style {
-                    RunStyle::Link(href) => {
-                        // info!("link is {}", href);
-                        if count == index {
-                            // info!("found the right link");
-                            return Some(&href);
-                        }
-                        count += 1;
+        self.find_link_by_index(index).map(|link| link.href.as_str())
+    }
+    pub fn find_link_by_index(&self, index: i32) -> Option<&Link> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| self.page.links.get(index))
+    }
+    /// The runs of a line. Preformatted lines come straight from their block.
+    fn line_runs(&self, index: usize) -> &[TextRun] {
+        match (self.lines.get(index), self.line_info.get(index)) {
+            (Some(Some(line)), _) => &line.runs,
+            (Some(None), Some(info)) => self.page.blocks.get(info.block).map_or(&[], |block| &block.spans),
+            _ => &[],
+        }
+    }
+    /// Finds `query` in the text of every line, ignoring case. Lines showing a
+    /// decoded image have no text to find.
+    pub fn find_matches(&self, query: &str) -> Vec<TextMatch> {
+        let query: Vec<char> = query.chars().map(fold_case).collect();
+        let mut matches = vec![];
+        if query.is_empty() {
+            return matches;
+        }
+        for line in 0..self.lines.len() {
+            if self.line_info.get(line).is_some_and(|info| info.image) {
+                continue;
+            }
+            let text: Vec<char> = self
+                .line_runs(line)
+                .iter()
+                .flat_map(|run| run.text.chars())
+                .map(fold_case)
+                .collect();
+            let mut start = 0;
+            while start + query.len() <= text.len() {
+                if text[start..start + query.len()] == query[..] {
+                    if matches.len() >= MAX_MATCHES || matches.try_reserve(1).is_err() {
+                        return matches;
                     }
-                    _ => {}
+                    matches.push(TextMatch {
+                        line,
+                        start,
+                        len: query.len(),
+                    });
+                    start += query.len();
+                } else {
+                    start += 1;
                 }
             }
         }
-        None
+        matches
+    }
+    /// Characters `range` of a line's text.
+    fn line_text(&self, line: usize, range: Range<usize>) -> String {
+        self.line_runs(line)
+            .iter()
+            .flat_map(|run| run.text.chars())
+            .skip(range.start)
+            .take(range.len())
+            .collect()
+    }
+    /// Scrolls so a match is on screen, sideways too for preformatted lines.
+    fn scroll_to_match(&mut self, found: &TextMatch, columns: usize) {
+        self.scroll_index = found.line as i32;
+        if self.lines.get(found.line).is_some_and(|line| line.is_none()) {
+            let shown = columns.saturating_sub(self.line_info[found.line].column as usize).max(1);
+            if found.start < self.scroll_x || found.start + found.len > self.scroll_x + shown {
+                let max_x = self.preformatted_width.saturating_sub(columns);
+                self.scroll_x = found.start.saturating_sub(SCROLL_X_STEP).min(max_x);
+            }
+        }
+    }
+    /// The first line of the given block, or of the next block that has lines.
+    pub fn line_for_block(&self, block: usize) -> Option<usize> {
+        self.line_info.iter().position(|info| info.block >= block)
     }
+    /// Scrolls so the block named by an `#fragment` is at the top. An empty
+    /// fragment or `#top` goes to the start of the page.
+    pub fn scroll_to_fragment(&mut self, fragment: &str) -> bool {
+        if fragment.is_empty() || fragment.eq_ignore_ascii_case("top") {
+            self.scroll_index = 0;
+            return true;
+        }
+        match self.page.find_anchor(fragment).and_then(|block| self.line_for_block(block)) {
+            Some(line) => {
+                self.scroll_index = line as i32;
+                true
+            }
+            None => {
+                warn!("no anchor named {}", fragment);
+                false
+            }
+        }
+    }
+}
+
+/// The characters of each match on `line` that are within `visible`, and
+/// whether it is the current match.
+fn line_marks(find: &FindState, line: usize, visible: Range<usize>) -> Vec<(Range<usize>, bool)> {
+    find.matches
+        .iter()
+        .enumerate()
+        .filter(|(_, found)| found.line == line)
+        .map(|(n, found)| {
+            let from = found.start.max(visible.start);
+            let to = (found.start + found.len).min(visible.end);
+            (from..to, n == find.current)
+        })
+        .filter(|(range, _)| !range.is_empty())
+        .collect()
+}
+
+/// The column each run of a line starts at. Runs advance by characters, as
+/// find matches and the monospace font do, not by bytes.
+fn run_columns(runs: &[TextRun]) -> Vec<usize> {
+    runs.iter()
+        .scan(0, |column, run| {
+            let start = *column;
+            *column += run.text.chars().count();
+            Some(start)
+        })
+        .collect()
+}
+
+/// Lowercases a character for comparing, keeping it a single character.
+fn fold_case(ch: char) -> char {
+    ch.to_lowercase().next().unwrap_or(ch)
+}
+
+/// Works out which entry of `Page::links` each run of a line belongs to. A link run
+/// that directly follows a run with the same href is part of the same link.
+fn run_link_ids(runs: &[TextRun], first_link: usize) -> Vec<Option<usize>> {
+    let mut ids = vec![];
+    let mut current: Option<usize> = None;
+    let mut prev_href: Option<&str> = None;
+    for run in runs {
+        match &run.style {
+            RunStyle::Link(href) => {
+                current = match (current, prev_href) {
+                    (None, _) => Some(first_link),
+                    (Some(id), Some(prev)) if prev == href => Some(id),
+                    (Some(id), _) => Some(id + 1),
+                };
+                prev_href = Some(href);
+                ids.push(current);
+            }
+            _ => {
+                prev_href = None;
+                ids.push(None);
+            }
+        }
+    }
+    ids
 }
+
+/// Breaks a block into lines `width` characters wide. `next_link` is the index
+/// of the block's first link, and is moved past its last one.
+fn break_block(block: &Block, index: usize, width: u32, next_link: &mut usize) -> Vec<(Option<TextLine>, LineInfo)> {
+    let mut out = vec![];
+    let mut prev_href: Option<String> = None;
+    for line in break_lines(block, width) {
+        // a link wrapped from the previous line keeps its index
+        let first_link = match (line.runs.first().map(|run| &run.style), &prev_href) {
+            (Some(RunStyle::Link(href)), Some(prev)) if href == prev => *next_link - 1,
+            _ => *next_link,
+        };
+        if let Some(last) = run_link_ids(&line.runs, first_link).into_iter().flatten().last() {
+            *next_link = last + 1;
+        }
+        prev_href = match line.runs.last().map(|run| &run.style) {
+            Some(RunStyle::Link(href)) => Some(href.clone()),
+            _ => None,
+        };
+        let info = LineInfo {
+            block: index,
+            first_link,
+            image: false,
+            column: 0,
+            same_row: false,
+            preformatted: false,
+        };
+        out.push((Some(line), info));
+    }
+    out
+}
+
+fn cell_text(page: &Page, cell: &TableCell) -> String {
+    let mut text = String::new();
+    for block in &page.blocks[cell.blocks.clone()] {
+        for word in block.spans.iter().flat_map(|span| span.text.split_whitespace()) {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(word);
+        }
+    }
+    text
+}
+
+/// Widths for the columns of a table, with a space between them, or None if
+/// they can't all fit in `columns`. Columns narrower than they'd like get the
+/// room that is left a character at a time.
+pub(crate) fn column_widths(page: &Page, table: &PageTable, columns: usize) -> Option<Vec<usize>> {
+    let count = table.column_count();
+    let available = columns.checked_sub(count.checked_sub(1)?)?;
+    let mut natural = vec![1; count];
+    for row in &table.rows {
+        for (cell, natural) in row.iter().zip(natural.iter_mut()) {
+            for block in &page.blocks[cell.blocks.clone()] {
+                let len: usize = block.spans.iter().map(|span| span.text.chars().count()).sum();
+                *natural = (*natural).max(len);
+            }
+        }
+    }
+    if natural.iter().sum::<usize>() <= available {
+        return Some(natural);
+    }
+    let mut widths: Vec<usize> = natural.iter().map(|natural| (*natural).min(MIN_COLUMN)).collect();
+    let mut left = available.checked_sub(widths.iter().sum())?;
+    while left > 0 {
+        let mut grew = false;
+        for (width, natural) in widths.iter_mut().zip(&natural) {
+            if left > 0 && *width < *natural {
+                *width += 1;
+                left -= 1;
+                grew = true;
+            }
+        }
+        if !grew {
+            break;
+        }
+    }
+    Some(widths)
+}
+
+/// Each cell wrapped to its column. The first lines of the cells of a row go
+/// side by side, then the second lines, and so on.
+fn grid_lines(page: &Page, table: &PageTable, widths: &[usize], next_link: &mut usize) -> Vec<(Option<TextLine>, LineInfo)> {
+    let mut out = vec![];
+    for row in &table.rows {
+        let mut cells = vec![];
+        let mut column = 0;
+        for (cell, width) in row.iter().zip(widths) {
+            let mut lines = vec![];
+            for index in cell.blocks.clone() {
+                for (line, mut info) in break_block(&page.blocks[index], index, *width as u32, next_link) {
+                    info.column = column as u32;
+                    lines.push((line, info));
+                }
+            }
+            cells.push(lines.into_iter());
+            column += width + 1;
+        }
+        let height = cells.iter().map(|lines| lines.len()).max().unwrap_or(0);
+        for _ in 0..height {
+            let mut same_row = false;
+            for lines in cells.iter_mut() {
+                if let Some((line, mut info)) = lines.next() {
+                    info.same_row = same_row;
+                    same_row = true;
+                    out.push((line, info));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Each row as a bulleted list of its cells, labelled with the column heading
+/// or, without a heading row, with the heading cell at the start of the row.
+fn stacked_lines(page: &Page, table: &PageTable, columns: u32, next_link: &mut usize) -> Vec<(Option<TextLine>, LineInfo)> {
+    let mut out = vec![];
+    // links in the cells used as labels aren't shown
+    let skip_links = |cell: &TableCell, next_link: &mut usize| {
+        *next_link += page.links.iter().filter(|link| cell.blocks.contains(&link.block)).count();
+    };
+    let headings = match table.rows.first() {
+        Some(row) if table.rows.len() > 1 && row.iter().all(|cell| cell.header) => Some(row),
+        _ => None,
+    };
+    if let Some(headings) = headings {
+        for cell in headings {
+            skip_links(cell, next_link);
+        }
+    }
+    for row in &table.rows[headings.is_some() as usize..] {
+        let row_label = match row.first() {
+            Some(cell) if headings.is_none() && cell.header && row.len() > 1 => Some(cell),
+            _ => None,
+        };
+        if let Some(cell) = row_label {
+            skip_links(cell, next_link);
+        }
+        let mut first = true;
+        for (column, cell) in row.iter().enumerate().skip(row_label.is_some() as usize) {
+            let label = headings
+                .and_then(|headings| headings.get(column))
+                .or(row_label)
+                .map(|cell| cell_text(page, cell))
+                .filter(|label| !label.is_empty());
+            for index in cell.blocks.clone() {
+                let mut block = page.blocks[index].clone();
+                if index == cell.blocks.start {
+                    if let Some(label) = &label {
+                        block.spans.insert(
+                            0,
+                            TextRun {
+                                style: RunStyle::Bold,
+                                text: format!("{}: ", label),
+                            },
+                        );
+                    }
+                    if first {
+                        block.block_type = BlockType::ListItem;
+                        first = false;
+                    }
+                }
+                out.extend(break_block(&block, index, columns, next_link));
+            }
+        }
+    }
+    out
+}
+
+/// The columns to add to every indent so the widest item number, like
+/// `100.`, fits in the indent of its list along with a space.
+fn marker_room(page: &Page) -> u32 {
+    page.indents
+        .iter()
+        .filter_map(|indent| {
+            let width = indent.number?.checked_ilog10().unwrap_or(0) + 3;
+            Some(width.saturating_sub(indent.level as u32 * INDENT_COLUMNS))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
 pub struct PageView {
     pub dirty: bool,
     pub history: Vec<RenderedPage>,
@@ -52,19 +457,43 @@ pub struct PageView {
     pub visible: bool,
     pub bounds: Bounds,
     pub columns: u32,
+    pub budget: PageBudget,
+    pub reader_mode: bool,
+    /// typing goes into the selected form control
+    pub editing: bool,
+    /// a form that was submitted, waiting to be sent
+    pub submission: Option<FormSubmission>,
+    /// the search of the current page, while its matches are being stepped through
+    pub find: Option<FindState>,
 }
 
+/// The command the page view sends to open the find panel.
+pub const FIND_COMMAND: &str = "find-in-page";
+
+/// The command the page view sends when a form is submitted. The submission
+/// itself is picked up with [`PageView::take_submission`].
+pub const SUBMIT_FORM_COMMAND: &str = "submit-form";
+
 impl PageView {
     pub fn new(bounds: Bounds, page: Page) -> View {
         let pv = PageView {
             dirty: true,
             visible: true,
             columns: 20,
+            budget: PageBudget::default(),
+            reader_mode: false,
+            editing: false,
+            submission: None,
+            find: None,
             history: vec![RenderedPage {
                 lines: vec![],
+                line_info: vec![],
                 scroll_index: 0,
                 page,
+                original: None,
                 link_count: 0,
+                scroll_x: 0,
+                preformatted_width: 0,
             }],
             history_index: 0,
             bounds,
@@ -88,43 +517,413 @@ impl PageView {
             .. Default::default()
         }
     }
-    pub fn load_page(&mut self, page: Page) {
-        let mut lines: Vec<TextLine> = vec![];
-        let mut link_count = 0;
-        for block in &page.blocks {
-            let mut some_lines = break_lines(&block, self.columns);
-            for line in &some_lines {
-                for run in &line.runs {
-                    // info!("Run: {:?}", run);
-                    match &run.style {
-                        RunStyle::Link(href) => {
-                            info!("found a link: {:?}", href);
-                            link_count += 1;
+    pub fn load_page(&mut self, mut page: Page) {
+        // the rest of a page we are already showing a preview of, which keeps
+        // what was typed into it and the search
+        let current = self.get_imutable_page();
+        let update = current.page.loading && current.page.url == page.url;
+        if update {
+            let preview = current.original.as_ref().unwrap_or(&current.page);
+            for (index, control) in preview.controls.iter().enumerate() {
+                let same = page.controls.get(index).map(|other| (&other.name, other.block));
+                if same == Some((&control.name, control.block)) {
+                    page.copy_control(preview, index);
+                }
+            }
+        } else {
+            self.editing = false;
+            self.find = None;
+        }
+        let mut pg = if self.reader_mode {
+            self.render(reader_page(&page), Some(page))
+        } else {
+            self.render(page, None)
+        };
+        if !update {
+            self.history.push(pg);
+            self.history_index = self.history.len() - 1;
+            return;
+        }
+        let current = self.get_current_rendered_page();
+        if current.scroll_index != 0 {
+            pg.scroll_index = current.scroll_index;
+        }
+        pg.page.selection = current.page.selection;
+        *current = pg;
+        if let Some(find) = self.find.as_mut() {
+            find.matches = self.history[self.history_index].find_matches(&find.query);
+            find.current = find.current.min(find.matches.len().saturating_sub(1));
+        }
+    }
+    /// Switches between the full page and the reader mode version of it.
+    pub fn set_reader_mode(&mut self, reader_mode: bool) {
+        self.editing = false;
+        self.find = None;
+        self.reader_mode = reader_mode;
+        self.apply_reader_mode();
+    }
+    /// Re-renders the current page if it doesn't match `reader_mode`.
+    fn apply_reader_mode(&mut self) {
+        let reader_mode = self.reader_mode;
+        let current = self.get_current_rendered_page();
+        if current.original.is_some() == reader_mode {
+            return;
+        }
+        let page = core::mem::replace(&mut current.page, Page::new());
+        let pg = match current.original.take() {
+            Some(original) => self.render(original, None),
+            None => self.render(reader_page(&page), Some(page)),
+        };
+        *self.get_current_rendered_page() = pg;
+    }
+    fn render(&self, mut page: Page, original: Option<Page>) -> RenderedPage {
+        let mut lines: Vec<Option<TextLine>> = vec![];
+        let mut line_info: Vec<LineInfo> = vec![];
+        let mut next_link = 0;
+        // links on the lines kept so far, for when the page gets cut short
+        let mut shown_links = 0;
+        let mut preformatted_width = 0;
+        let mut truncated = false;
+        let marker_room = marker_room(&page);
+        let mut index = 0;
+        'blocks: while index < page.blocks.len() {
+            // nested blocks keep at least MIN_COLUMN characters to break into
+            let indent = page.indent_for_block(index).map_or(0, |indent| {
+                (indent.level as u32 * INDENT_COLUMNS + marker_room)
+                    .min(self.columns.saturating_sub(MIN_COLUMN as u32))
+            });
+            let (block_lines, next) = match page.table_at(index) {
+                Some(table) => (self.table_lines(&page, table, &mut next_link), table.blocks().end),
+                None if page.is_preformatted(index) => {
+                    let info = LineInfo {
+                        block: index,
+                        first_link: next_link,
+                        image: false,
+                        column: indent,
+                        same_row: false,
+                        preformatted: true,
+                    };
+                    let spans = &page.blocks[index].spans;
+                    if let Some(last) = run_link_ids(spans, next_link).into_iter().flatten().last() {
+                        next_link = last + 1;
+                    }
+                    let width: usize = spans.iter().map(|span| span.text.chars().count()).sum();
+                    preformatted_width = max(preformatted_width, width + indent as usize);
+                    (vec![(None, info)], index + 1)
+                }
+                None => {
+                    let mut block_lines =
+                        break_block(&page.blocks[index], index, self.columns - indent, &mut next_link);
+                    // a decoded image takes the place of its alt text
+                    if page.image_for_block(index).is_some_and(|image| image.bitmap.is_some()) {
+                        block_lines.truncate(1);
+                        if let Some((_, info)) = block_lines.first_mut() {
+                            info.image = true;
+                        }
+                    } else {
+                        for (_, info) in block_lines.iter_mut() {
+                            info.column = indent;
                         }
-                        _ => {}
                     }
+                    (block_lines, index + 1)
                 }
+            };
+            for (line, info) in block_lines {
+                if lines.len() >= self.budget.max_lines
+                    || lines.try_reserve(1).is_err()
+                    || line_info.try_reserve(1).is_err()
+                {
+                    truncated = true;
+                    break 'blocks;
+                }
+                let runs = match &line {
+                    Some(line) => &line.runs,
+                    None => &page.blocks[info.block].spans,
+                };
+                if let Some(last) = run_link_ids(runs, info.first_link).into_iter().flatten().last() {
+                    shown_links = shown_links.max(last + 1);
+                }
+                line_info.push(info);
+                lines.push(line);
             }
-            lines.append(&mut some_lines);
+            index = next;
         }
-        let pg: RenderedPage = RenderedPage {
-            link_count,
+        if let Some((block, offset)) = truncated.then(|| page.part_offset_before(index)).flatten() {
+            warn!("stopped breaking lines at block {} of {}", index, page.url);
+            // end the page where the next part will pick up, with a link to it
+            while line_info.last().is_some_and(|info| info.block >= block) {
+                line_info.pop();
+                lines.pop();
+            }
+            page.truncate_blocks(block);
+            let mut notice_link = page.links.len();
+            page.push_next_part(offset);
+            let notice = break_block(&page.blocks[block], block, self.columns, &mut notice_link);
+            for (line, info) in notice {
+                line_info.push(info);
+                lines.push(line);
+            }
+            shown_links = page.links.len();
+        } else if truncated {
+            warn!("stopped breaking lines at {} for {}", lines.len(), page.url);
+            let notice = Block::new_of_type(BlockType::Paragraph, "Page truncated to fit in memory.");
+            for line in break_lines(&notice, self.columns) {
+                line_info.push(LineInfo {
+                    block: page.blocks.len(),
+                    first_link: shown_links,
+                    image: false,
+                    column: 0,
+                    same_row: false,
+                    preformatted: false,
+                });
+                lines.push(Some(line));
+            }
+        } else if next_link != page.links.len() {
+            warn!("rendered {} links but the page has {}", next_link, page.links.len());
+        }
+        let link_count = if truncated { shown_links } else { page.links.len() };
+        let fragment = Url::parse(&page.url).and_then(|url| url.fragment);
+        let mut pg: RenderedPage = RenderedPage {
+            link_count: link_count as i32,
             lines,
+            line_info,
             page,
+            original,
             scroll_index: 0,
+            scroll_x: 0,
+            preformatted_width,
+        };
+        if let Some(fragment) = fragment {
+            pg.scroll_to_fragment(&fragment);
+        }
+        pg
+    }
+    /// Fills in a decoded image on every history entry for `page_url`. Pages
+    /// that use it are re-rendered in place, keeping the scroll position and
+    /// the selected link. They all share the one bitmap.
+    pub fn set_image(&mut self, page_url: &str, src: &str, bitmap: &Arc<Bitmap>) -> bool {
+        let mut changed = false;
+        for index in 0..self.history.len() {
+            let rp = &mut self.history[index];
+            if rp.page.url != page_url {
+                continue;
+            }
+            let mut used = rp.page.set_image(src, bitmap);
+            if let Some(original) = rp.original.as_mut() {
+                used |= original.set_image(src, bitmap);
+            }
+            if !used {
+                continue;
+            }
+            self.rerender(index);
+            changed = true;
+        }
+        changed
+    }
+    /// Renders a history entry again after its page changed, keeping the
+    /// scroll position and the selected link.
+    fn rerender(&mut self, index: usize) {
+        let rp = &mut self.history[index];
+        let page = core::mem::replace(&mut rp.page, Page::new());
+        let original = rp.original.take();
+        let scroll_index = rp.scroll_index;
+        let scroll_x = rp.scroll_x;
+        let selection = page.selection;
+        let mut pg = self.render(page, original);
+        pg.scroll_index = scroll_index;
+        pg.scroll_x = scroll_x.min(pg.preformatted_width);
+        pg.page.selection = selection;
+        self.history[index] = pg;
+        // the lines may have moved under the matches
+        if index == self.history_index {
+            if let Some(find) = self.find.as_mut() {
+                find.matches = self.history[index].find_matches(&find.query);
+                find.current = find.current.min(find.matches.len().saturating_sub(1));
+            }
+        }
+    }
+    /// Searches the current page and scrolls to the first match at or after
+    /// the top of the screen. Returns how many were found. A search with no
+    /// matches is dropped.
+    pub fn find(&mut self, query: &str) -> usize {
+        let columns = self.columns as usize;
+        let rp = self.get_current_rendered_page();
+        let matches = rp.find_matches(query);
+        if matches.is_empty() {
+            self.find = None;
+            return 0;
+        }
+        let top = max(rp.scroll_index, 0) as usize;
+        let current = matches.iter().position(|found| found.line >= top).unwrap_or(0);
+        rp.scroll_to_match(&matches[current], columns);
+        let count = matches.len();
+        self.find = Some(FindState {
+            query: String::from(query),
+            matches,
+            current,
+        });
+        count
+    }
+    /// Moves to the next match, or the previous one, wrapping around.
+    pub fn find_next(&mut self, forward: bool) {
+        let columns = self.columns as usize;
+        let Some(find) = self.find.as_mut() else {
+            return;
+        };
+        let count = find.matches.len();
+        if count == 0 {
+            return;
+        }
+        find.current = if forward {
+            (find.current + 1) % count
+        } else {
+            (find.current + count - 1) % count
+        };
+        let found = find.matches[find.current];
+        self.get_current_rendered_page().scroll_to_match(&found, columns);
+    }
+    /// The current match, counting from one, and how many there are.
+    pub fn find_status(&self) -> Option<(usize, usize)> {
+        self.find.as_ref().map(|find| (find.current + 1, find.matches.len()))
+    }
+    /// The form control the selected link shows, if it is one.
+    pub fn selected_control(&self) -> Option<usize> {
+        let rp = self.get_imutable_page();
+        let link = rp.find_link_by_index(rp.page.selection)?;
+        rp.page.control_for_block(link.block)
+    }
+    /// Enter on a form control. Checkboxes toggle, radio buttons are picked,
+    /// selects step to their next option and text fields start or stop taking
+    /// typing. Submit buttons, and Enter at the end of a one line field, submit
+    /// the form.
+    fn activate_control(&mut self, index: usize) -> Option<OutputAction> {
+        // borrowed from the field so `editing` can still be set
+        let page = &mut self.history[self.history_index].page;
+        let kind = page.controls[index].kind;
+        let submit = match kind {
+            ControlKind::Text | ControlKind::Password if self.editing => true,
+            ControlKind::TextArea if self.editing => {
+                page.controls[index].value.push('\n');
+                false
+            }
+            ControlKind::Text | ControlKind::Password | ControlKind::TextArea => {
+                self.editing = true;
+                false
+            }
+            ControlKind::Checkbox => {
+                let control = &mut page.controls[index];
+                control.checked = !control.checked;
+                false
+            }
+            ControlKind::Radio => {
+                page.check_radio(index);
+                false
+            }
+            ControlKind::Select => {
+                let control = &mut page.controls[index];
+                control.selected = (control.selected + 1) % control.options.len().max(1);
+                false
+            }
+            ControlKind::Submit => true,
+            ControlKind::Hidden => false,
+        };
+        self.update_control(index);
+        if !submit {
+            return None;
+        }
+        self.editing = false;
+        let submission = self.get_imutable_page().page.submit_form(index)?;
+        info!("submitting {:?}", submission);
+        self.submission = Some(submission);
+        Some(OutputAction::Command(SUBMIT_FORM_COMMAND.into()))
+    }
+    /// Types a character into the form control being edited. A backspace
+    /// removes the last one.
+    pub fn type_into_control(&mut self, key: &TextAction) -> bool {
+        let Some(index) = self.selected_control().filter(|_| self.editing) else {
+            return false;
         };
-        self.history.push(pg);
-        self.history_index = self.history.len() - 1;
+        let value = &mut self.get_current_rendered_page().page.controls[index].value;
+        match key {
+            TextAction::TypedAscii(key) if *key == b' ' || key.is_ascii_graphic() => value.push(*key as char),
+            TextAction::BackDelete => {
+                value.pop();
+            }
+            _ => return false,
+        }
+        self.update_control(index);
+        true
+    }
+    /// Shows a changed control's value on the current page, along with the
+    /// rest of its radio group. Only the lines of their blocks are broken
+    /// again. The values are copied to the full page while reader mode is on,
+    /// so they are still there when it is turned off.
+    fn update_control(&mut self, index: usize) {
+        let columns = self.columns;
+        let rp = &mut self.history[self.history_index];
+        let mut relaid = true;
+        for other in rp.page.control_group(index) {
+            rp.page.refresh_control(other);
+            if let Some(original) = rp.original.as_mut() {
+                original.copy_control(&rp.page, other);
+            }
+            if let Some(block) = rp.page.controls[other].block {
+                relaid &= rp.relayout_block(block, columns);
+            }
+        }
+        if !relaid {
+            self.rerender(self.history_index);
+            return;
+        }
+        if let Some(find) = self.find.as_mut() {
+            find.matches = self.history[self.history_index].find_matches(&find.query);
+            find.current = find.current.min(find.matches.len().saturating_sub(1));
+        }
+    }
+    /// The last form submitted, to send to the network.
+    pub fn take_submission(&mut self) -> Option<FormSubmission> {
+        self.submission.take()
+    }
+    /// Lays a table out as a grid, or as labelled lines for each row when the
+    /// columns don't fit.
+    fn table_lines(&self, page: &Page, table: &PageTable, next_link: &mut usize) -> Vec<(Option<TextLine>, LineInfo)> {
+        match column_widths(page, table, self.columns as usize) {
+            Some(widths) => grid_lines(page, table, &widths, next_link),
+            None => stacked_lines(page, table, self.columns, next_link),
+        }
     }
     pub(crate) fn prev_page(&mut self) {
+        self.editing = false;
+        self.find = None;
         if self.history_index > 0 {
             self.history_index -= 1;
         }
+        self.apply_reader_mode();
     }
     pub(crate) fn next_page(&mut self) {
+        self.editing = false;
+        self.find = None;
         if self.history_index < self.history.len() - 1 {
             self.history_index += 1;
         }
+        self.apply_reader_mode();
+    }
+    /// Scrolls preformatted lines sideways by `delta` characters. Returns false
+    /// if none of them are wider than the view.
+    pub fn scroll_horizontally(&mut self, delta: i32) -> bool {
+        let columns = self.columns as usize;
+        let rp = self.get_current_rendered_page();
+        let max_x = rp.preformatted_width.saturating_sub(columns);
+        if max_x == 0 {
+            return false;
+        }
+        let step = delta.unsigned_abs() as usize;
+        rp.scroll_x = if delta < 0 {
+            rp.scroll_x.saturating_sub(step)
+        } else {
+            (rp.scroll_x + step).min(max_x)
+        };
+        true
     }
     pub fn prev_link(&mut self) {
         let rp = self.get_current_rendered_page();
@@ -141,20 +940,30 @@ impl PageView {
         }
     }
     pub(crate) fn nav_current_link(&mut self) -> Option<OutputAction> {
+        if let Some(index) = self.selected_control() {
+            return self.activate_control(index);
+        }
         let rp = self.get_current_rendered_page();
-        if let Some(href) = rp.find_href_by_index(rp.page.selection) {
-            info!("loading the href {}", href);
-            let mut href = href.to_string();
-            if !href.starts_with("http") {
-                info!("doing a relative link. base is {}", rp.page.url);
-                href = format!("{}{}", rp.page.url, href);
-                info!("final url is {}", href);
-            }
-            Some(OutputAction::Command(href))
+        if let Some(link) = rp.find_link_by_index(rp.page.selection) {
+            if let Some(fragment) = rp.page.same_page_fragment(link) {
+                info!("scrolling to #{}", fragment);
+                rp.scroll_to_fragment(&fragment);
+                return None;
+            }
+            info!("loading the href {} as {}", link.href, link.url);
+            Some(OutputAction::Command(link.url.clone()))
         } else {
             None
         }
     }
+    /// The page being shown, in full even when reader mode is on.
+    pub fn current_page(&self) -> &Page {
+        let rp = self.get_imutable_page();
+        rp.original.as_ref().unwrap_or(&rp.page)
+    }
+    pub fn current_title(&self) -> &str {
+        self.get_imutable_page().page.display_title()
+    }
     fn get_current_rendered_page(&mut self) -> &mut RenderedPage {
         &mut self.history[self.history_index]
     }
@@ -169,9 +978,9 @@ fn draw(e: &mut DrawEvent) {
     }
     // let font = context.theme.font;
     let font = FONT_9X15_BOLD;
-    let line_height = font.character_size.height + 2;
+    let line_height = LINE_HEIGHT;
     // let viewport_height: i32 = (context.display.size().height / line_height) as i32;
-    let viewport_height: i32 = 240 / line_height as i32;
+    let viewport_bottom: i32 = 240;
     let char_width = font.character_size.width as i32;
 
     e.ctx.fill_rect(&e.view.bounds, &e.theme.standard.fill);
@@ -180,59 +989,222 @@ fn draw(e: &mut DrawEvent) {
     if let Some(state) = &e.view.state {
         if let Some(state) = state.downcast_ref::<PageView>() {
             let rpage = state.get_imutable_page();
-            let mut end: usize = (rpage.scroll_index + viewport_height) as usize;
-            if end >= rpage.lines.len() {
-                end = rpage.lines.len();
+            let mut start = (max(rpage.scroll_index, 0) as usize).min(rpage.lines.len());
+            // start with the whole table row
+            while start > 0 && rpage.line_info.get(start).is_some_and(|info| info.same_row) {
+                start -= 1;
             }
-            let start = max(rpage.scroll_index, 0) as usize;
-            let viewport_lines = &rpage.lines[start..end];
+            let viewport_lines = &rpage.lines[start..];
 
             let x_inset = 8;
             let y_inset = 5;
 
-            let mut link_count = -1;
-            // draw the lines
+            let selected = usize::try_from(rpage.page.selection).ok();
+            // table columns are counted in characters of the theme font
+            let column_width = e.theme.font.character_size.width as i32;
+            // draw the lines until the viewport is full
+            let mut y = 10;
+            let mut row_height = 0;
             for (j, line) in viewport_lines.iter().enumerate() {
-                let mut inset_chars: usize = 0;
-                let y = j as i32 * (line_height as i32) + 10;
+                let info = rpage.line_info.get(start + j);
+                if !info.is_some_and(|info| info.same_row) {
+                    y += row_height;
+                    row_height = 0;
+                }
+                if y + line_height as i32 > viewport_bottom {
+                    break;
+                }
+                if let Some(bitmap) = info
+                    .filter(|info| info.image)
+                    .and_then(|info| rpage.page.image_for_block(info.block))
+                    .and_then(|image| image.bitmap.as_ref())
+                {
+                    // one rectangle per run of same colored pixels in a row
+                    let width = (bitmap.width as usize)
+                        .min(max(e.view.bounds.size.w - x_inset * 2, 0) as usize);
+                    for (row, pixels) in bitmap.pixels.chunks(max(bitmap.width, 1) as usize).enumerate() {
+                        let pixels = &pixels[..width];
+                        let mut run_start = 0;
+                        for i in 1..=width {
+                            if i == width || pixels[i] != pixels[run_start] {
+                                let bounds = Bounds::new(
+                                    x_inset + run_start as i32,
+                                    y + y_inset + row as i32,
+                                    (i - run_start) as i32,
+                                    1,
+                                );
+                                e.ctx.fill_rect(&bounds, &pixels[run_start]);
+                                run_start = i;
+                            }
+                        }
+                    }
+                    let rows = max(bitmap.height.div_ceil(line_height), 1);
+                    row_height = rows as i32 * line_height as i32;
+                    continue;
+                }
+                // a bar down the left of every quote the line is in
+                if let Some(indent) = info.and_then(|info| rpage.page.indent_for_block(info.block)) {
+                    for level in 0..indent.level.min(MAX_INDENT as u8) {
+                        if indent.quotes & (1 << level) != 0 {
+                            let bar_x = x_inset + (level as u32 * INDENT_COLUMNS) as i32 * column_width + 2;
+                            e.ctx.fill_rect(&Bounds::new(bar_x, y, 2, line_height as i32), &e.theme.standard.text);
+                        }
+                    }
+                }
+                if let Some(info) = info.filter(|info| info.preformatted) {
+                    let spans = &rpage.page.blocks[info.block].spans;
+                    let link_ids = run_link_ids(spans, info.first_link);
+                    let background = Bounds::new(
+                        x_inset - 4,
+                        y,
+                        e.view.bounds.size.w - x_inset * 2 + 8,
+                        line_height as i32,
+                    );
+                    e.ctx.fill_rect(&background, &e.theme.panel.fill);
+                    // only the characters left in view by the horizontal scroll
+                    let first = rpage.scroll_x;
+                    let last = first + state.columns.saturating_sub(info.column) as usize;
+                    for ((run, link_id), column) in spans.iter().zip(link_ids).zip(run_columns(spans)) {
+                        let len = run.text.chars().count();
+                        let from = column.max(first);
+                        let to = (column + len).min(last);
+                        if from < to {
+                            let text: String = run.text.chars().skip(from - column).take(to - from).collect();
+                            let x = x_inset + (info.column as usize + from - first) as i32 * column_width;
+                            let text_style = run_style(e.theme, run, link_id.is_some() && link_id == selected);
+                            e.ctx.fill_text(&Bounds::new(x, y + y_inset, 100, 10), &text, &text_style);
+                        }
+                    }
+                    if let Some(find) = &state.find {
+                        for (range, current) in line_marks(find, start + j, first..last) {
+                            let x = x_inset + (info.column as usize + range.start - first) as i32 * column_width;
+                            let width = range.len() as i32 * column_width;
+                            if current {
+                                let text = rpage.line_text(start + j, range);
+                                e.ctx.fill_rect(&Bounds::new(x, y, width, line_height as i32), &e.theme.standard.text);
+                                let text_style = TextStyle::new(&e.theme.font, &e.theme.standard.fill)
+                                    .with_halign(Align::Start);
+                                e.ctx.fill_text(&Bounds::new(x, y + y_inset, 100, 10), &text, &text_style);
+                            } else {
+                                e.ctx.fill_rect(&Bounds::new(x, y + line_height as i32 - 2, width, 2), &e.theme.standard.text);
+                            }
+                        }
+                    }
+                    row_height = max(row_height, line_height as i32);
+                    continue;
+                }
+                let Some(line) = line else {
+                    continue;
+                };
+                let first_link = info.map_or(0, |info| info.first_link);
+                let link_ids = run_link_ids(&line.runs, first_link);
+                let x = x_inset + info.map_or(0, |info| info.column as i32) * column_width;
                 // let style = match line.block_type {
                 //     BlockType::Paragraph => MonoTextStyle::new(&font, &theme.fg),
                 //     BlockType::ListItem => MonoTextStyle::new(&font, &theme.fg),
                 //     BlockType::Header => MonoTextStyle::new(&font, &theme.fg),
                 // };
-                // draw a bullet
-                if line.block_type == BlockType::ListItem {
-                    e.ctx.fill_rect(&Bounds::new(2, y, 4, 3), &e.theme.standard.text);
-                }
-                for run in &line.runs {
-                    let pos = Point::new(inset_chars as i32 * char_width + x_inset, y + y_inset);
-                    let plain_style =
-                        TextStyle::new(&e.theme.font, &e.theme.standard.text).with_halign(Align::Start);
-                    let text_style = match &run.style {
-                        RunStyle::Link(href) => {
-                            info!("found a link: {:?}", href);
-                            link_count += 1;
-                            if rpage.page.selection == link_count {
-                                plain_style.with_underline(true)
-                            } else {
-                                plain_style
-                            }
+                // draw a bullet or the item number on the first line of a list item
+                let first_line = start + j == 0
+                    || rpage.line_info.get(start + j - 1).map(|prev| prev.block) != info.map(|info| info.block);
+                if line.block_type == BlockType::ListItem && first_line {
+                    let number = info
+                        .and_then(|info| rpage.page.indent_for_block(info.block))
+                        .and_then(|indent| indent.number);
+                    match number {
+                        Some(number) => {
+                            let marker = format!("{}.", number);
+                            let marker_x = max(x - (marker.len() as i32 + 1) * char_width, 0);
+                            let text_style = TextStyle::new(&e.theme.font, &e.theme.standard.text)
+                                .with_halign(Align::Start);
+                            e.ctx
+                                .fill_text(&Bounds::new(marker_x, y + y_inset, 100, 10), &marker, &text_style);
                         }
-                        RunStyle::Plain => plain_style,
-                        RunStyle::Bold => plain_style,
-                    };
+                        None => e.ctx.fill_rect(&Bounds::new(x - 6, y, 4, 3), &e.theme.standard.text),
+                    }
+                }
+                let columns = run_columns(&line.runs);
+                for ((run, link_id), column) in line.runs.iter().zip(link_ids).zip(columns) {
+                    let pos = Point::new(column as i32 * char_width + x, y + y_inset);
+                    let is_selected = link_id.is_some() && link_id == selected;
+                    let mut text_style = run_style(e.theme, run, is_selected);
+                    // the form control being typed into
+                    if is_selected && state.editing {
+                        let width = run.text.chars().count() as i32 * char_width;
+                        e.ctx.fill_rect(&Bounds::new(pos.x, y, width, line_height as i32), &e.theme.selected.fill);
+                        text_style = TextStyle::new(&e.theme.font, &e.theme.selected.text).with_halign(Align::Start);
+                    }
                     e.ctx
                         .fill_text(&Bounds::new(pos.x, pos.y, 100, 10), &run.text, &text_style);
-                    inset_chars += run.text.len();
                 }
+                // every match is underlined and the current one drawn inverted
+                if let Some(find) = &state.find {
+                    for (range, current) in line_marks(find, start + j, 0..usize::MAX) {
+                        let mark_x = x + range.start as i32 * char_width;
+                        let width = range.len() as i32 * char_width;
+                        if current {
+                            let text = rpage.line_text(start + j, range);
+                            e.ctx.fill_rect(&Bounds::new(mark_x, y, width, line_height as i32), &e.theme.standard.text);
+                            let text_style = TextStyle::new(&e.theme.font, &e.theme.standard.fill)
+                                .with_halign(Align::Start);
+                            e.ctx.fill_text(&Bounds::new(mark_x, y + y_inset, 100, 10), &text, &text_style);
+                        } else {
+                            e.ctx.fill_rect(&Bounds::new(mark_x, y + line_height as i32 - 2, width, 2), &e.theme.standard.text);
+                        }
+                    }
+                }
+                row_height = max(row_height, line_height as i32);
+            }
+        }
+    }
+}
+
+/// Links are underlined when they are selected.
+fn run_style<'a>(theme: &'a Theme, run: &TextRun, selected: bool) -> TextStyle<'a> {
+    let plain_style = TextStyle::new(&theme.font, &theme.standard.text).with_halign(Align::Start);
+    match &run.style {
+        RunStyle::Link(_) => {
+            if selected {
+                plain_style.with_underline(true)
+            } else {
+                plain_style
             }
         }
+        RunStyle::Plain => plain_style,
+        RunStyle::Bold => plain_style,
     }
 }
 
 fn handle_input(event: &mut GuiEvent) -> Option<OutputAction> {
     event.scene.mark_dirty_view(event.target);
     if let Some(state) = event.scene.get_view_state::<PageView>(event.target) {
+        if state.find.is_some() {
+            let step = match &event.event_type {
+                InputEvent::Text(TextAction::TypedAscii(b'n')) | InputEvent::Text(TextAction::Down) => Some(true),
+                InputEvent::Text(TextAction::TypedAscii(b'p' | b'N')) | InputEvent::Text(TextAction::Up) => Some(false),
+                InputEvent::Scroll(delta) if delta.y != 0 => Some(delta.y > 0),
+                _ => None,
+            };
+            let done = matches!(event.event_type, InputEvent::Text(TextAction::Enter));
+            if let Some(forward) = step {
+                state.find_next(forward);
+            }
+            if done {
+                state.find = None;
+            }
+            if step.is_some() || done {
+                show_find_status(event.scene);
+                return None;
+            }
+        }
+        if state.editing {
+            match &event.event_type {
+                InputEvent::Text(TextAction::Enter) => return state.nav_current_link(),
+                InputEvent::Text(key) if state.type_into_control(key) => return None,
+                // moving off the field stops typing into it
+                _ => state.editing = false,
+            }
+        }
         match &event.event_type {
             InputEvent::Text(TextAction::TypedAscii(key)) => {
                 match key {
@@ -244,8 +1216,15 @@ fn handle_input(event: &mut GuiEvent) -> Option<OutputAction> {
                         let page = state.get_current_rendered_page();
                         page.scroll_index = max(page.scroll_index - 10, 0)
                     }
+                    b'f' => return Some(OutputAction::Command(FIND_COMMAND.into())),
                     b'a' => state.prev_link(),
                     b's' => state.next_link(),
+                    b'h' => {
+                        state.scroll_horizontally(-(SCROLL_X_STEP as i32));
+                    }
+                    b'l' => {
+                        state.scroll_horizontally(SCROLL_X_STEP as i32);
+                    }
                     _ => {
                         warn!("Unhandled key {:?}", key);
                     }
@@ -254,7 +1233,15 @@ fn handle_input(event: &mut GuiEvent) -> Option<OutputAction> {
             }
             InputEvent::Text(TextAction::Up) => state.prev_link(),
             InputEvent::Text(TextAction::Down) => state.next_link(),
+            InputEvent::Text(TextAction::Left) => {
+                state.scroll_horizontally(-(SCROLL_X_STEP as i32));
+            }
+            InputEvent::Text(TextAction::Right) => {
+                state.scroll_horizontally(SCROLL_X_STEP as i32);
+            }
             InputEvent::Text(TextAction::Enter) => return state.nav_current_link(),
+            // sideways trackball moves scroll wide code blocks, if there are any
+            InputEvent::Scroll(delta) if delta.x != 0 && state.scroll_horizontally(delta.x * 2) => {}
             InputEvent::Scroll(delta) => {
                 if (delta.x < 0) || (delta.y < 0) {
                     state.prev_link();
@@ -268,3 +1255,235 @@ fn handle_input(event: &mut GuiEvent) -> Option<OutputAction> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{parse_page_part, PageBuilder};
+    use crate::form::CONTROL_HREF_PREFIX;
+
+    fn view(columns: u32, budget: PageBudget) -> PageView {
+        PageView {
+            dirty: true,
+            history: vec![RenderedPage {
+                link_count: 0,
+                lines: vec![],
+                line_info: vec![],
+                page: Page::new(),
+                original: None,
+                scroll_index: 0,
+                scroll_x: 0,
+                preformatted_width: 0,
+            }],
+            history_index: 0,
+            visible: true,
+            bounds: Bounds::new(0, 0, 320, 240),
+            columns,
+            budget,
+            reader_mode: false,
+            editing: false,
+            submission: None,
+            find: None,
+        }
+    }
+
+    fn text(block: &Block) -> String {
+        block.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    #[test]
+    fn running_out_of_lines_links_to_the_next_part() {
+        let html: String = (0..100).map(|n| format!("<p>paragraph number {}</p>", n)).collect();
+        let html = format!("<html><body>{}</body></html>", html);
+        let budget = PageBudget {
+            max_lines: 40,
+            ..PageBudget::default()
+        };
+        let mut view = view(30, budget);
+        view.load_page(Page::from_bytes(html.as_bytes(), "http://example.com/"));
+        let rp = view.get_imutable_page();
+        assert!(rp.lines.len() <= 42);
+        let link = rp.page.links.last().unwrap();
+        assert_eq!(rp.link_count as usize, rp.page.links.len());
+        let (url, offset) = parse_page_part(&link.url).unwrap();
+        assert_eq!(url, "http://example.com/");
+        // the next part starts right after the last block kept
+        let kept = rp.page.blocks.len() - 1;
+        let last_kept = text(&rp.page.blocks[kept - 1]);
+        let mut next = PageBuilder::new(url, Some("text/html")).resume_at(offset);
+        next.push(html.as_bytes());
+        let next = next.finish();
+        assert_eq!(last_kept, format!("paragraph number {}", kept - 1));
+        assert_eq!(text(&next.blocks[0]), format!("paragraph number {}", kept));
+    }
+
+    #[test]
+    fn long_item_numbers_fit_in_the_indent() {
+        let items: String = (0..120).map(|n| format!("<li>item {}</li>", n)).collect();
+        let html = format!("<html><body><ol>{}</ol></body></html>", items);
+        let mut view = view(40, PageBudget::default());
+        view.load_page(Page::from_bytes(html.as_bytes(), "http://example.com/"));
+        let page = view.current_page();
+        let lines = &view.history[view.history_index].line_info;
+        assert_eq!(page.indent_for_block(100).and_then(|indent| indent.number), Some(101));
+        // "101." and a space
+        assert!(lines.iter().all(|info| info.column >= 5));
+    }
+
+    #[test]
+    fn short_lists_keep_the_usual_indent() {
+        let html = "<html><body><ol><li>one</li><li>two</li></ol><ul><li>dot</li></ul></body></html>";
+        let mut view = view(40, PageBudget::default());
+        view.load_page(Page::from_bytes(html.as_bytes(), "http://example.com/"));
+        let lines = &view.history[view.history_index].line_info;
+        assert!(lines.iter().all(|info| info.column == INDENT_COLUMNS));
+    }
+
+    /// Selects the link of the first form control on the current page.
+    fn select_control(view: &mut PageView) {
+        let page = &mut view.get_current_rendered_page().page;
+        let link = page.links.iter().position(|link| link.url.starts_with(CONTROL_HREF_PREFIX));
+        page.selection = link.unwrap() as i32;
+    }
+
+    #[test]
+    fn typing_only_breaks_the_lines_of_the_field() {
+        let html = "<html><body><p>Name</p><form><input name=\"q\"></form><p>After</p></body></html>";
+        let mut view = view(40, PageBudget::default());
+        view.load_page(Page::from_bytes(html.as_bytes(), "http://example.com/"));
+        select_control(&mut view);
+        view.editing = true;
+        let lines = view.history[view.history_index].lines.as_ptr();
+        assert!(view.type_into_control(&TextAction::TypedAscii(b'x')));
+        let rp = &view.history[view.history_index];
+        assert_eq!(rp.lines.as_ptr(), lines);
+        let texts: Vec<String> = rp
+            .lines
+            .iter()
+            .flatten()
+            .map(|line| line.runs.iter().map(|run| run.text.as_str()).collect())
+            .collect();
+        assert!(texts.iter().any(|text| text.starts_with("[x_")), "{:?}", texts);
+    }
+
+    #[test]
+    fn values_typed_in_reader_mode_are_kept() {
+        let html = "<html><body><article><p>Story</p><form><input name=\"q\"></form></article></body></html>";
+        let mut view = view(40, PageBudget::default());
+        view.load_page(Page::from_bytes(html.as_bytes(), "http://example.com/"));
+        view.set_reader_mode(true);
+        assert!(view.history[view.history_index].original.is_some());
+        select_control(&mut view);
+        view.editing = true;
+        view.type_into_control(&TextAction::TypedAscii(b'h'));
+        view.type_into_control(&TextAction::TypedAscii(b'i'));
+        view.set_reader_mode(false);
+        let page = view.current_page();
+        assert_eq!(page.controls[0].value, "hi");
+        let block = page.controls[0].block.unwrap();
+        assert_eq!(text(&page.blocks[block]), page.controls[0].label());
+    }
+
+    #[test]
+    fn the_rest_of_a_preview_keeps_the_search_and_typing() {
+        let paragraphs: String = (0..100).map(|n| format!("<p>paragraph {}</p>", n)).collect();
+        let html = format!("<html><body><form><input name=\"q\"></form>{}</body></html>", paragraphs);
+        let mut builder = PageBuilder::new("http://example.com/", Some("text/html"));
+        builder.push(html.as_bytes());
+        let preview = builder.preview().unwrap();
+        let page = builder.finish();
+        let mut view = view(40, PageBudget::default());
+        view.load_page(preview);
+        let pages = view.history.len();
+        let found = view.find("paragraph");
+        select_control(&mut view);
+        view.editing = true;
+        view.type_into_control(&TextAction::TypedAscii(b'x'));
+        view.load_page(page);
+        assert_eq!(view.history.len(), pages);
+        assert!(view.editing);
+        assert_eq!(view.current_page().controls[0].value, "x");
+        assert!(view.find.as_ref().unwrap().matches.len() > found);
+        // a different page starts afresh
+        view.load_page(Page::from_bytes(b"<p>other</p>", "http://example.com/other"));
+        assert!(!view.editing);
+        assert!(view.find.is_none());
+    }
+
+    fn line_texts(view: &PageView) -> Vec<String> {
+        view.history[view.history_index]
+            .lines
+            .iter()
+            .flatten()
+            .map(|line| line.runs.iter().map(|run| run.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn tables_are_stacked_when_the_columns_dont_fit() {
+        let html = "<html><body><table><tr><th>Name</th><th>Size</th></tr><tr><td>notes</td><td>12</td></tr></table></body></html>";
+        let mut wide = view(40, PageBudget::default());
+        wide.load_page(Page::from_bytes(html.as_bytes(), "http://example.com/"));
+        assert_eq!(line_texts(&wide), ["Name", "Size", "notes", "12"]);
+        let columns: Vec<(u32, bool)> = wide.history[wide.history_index]
+            .line_info
+            .iter()
+            .map(|info| (info.column, info.same_row))
+            .collect();
+        assert_eq!(columns, [(0, false), (6, true), (0, false), (6, true)]);
+        let mut narrow = view(8, PageBudget::default());
+        narrow.load_page(Page::from_bytes(html.as_bytes(), "http://example.com/"));
+        // each cell labelled with its heading, which takes a line of its own here
+        assert_eq!(line_texts(&narrow), ["Name: ", "notes", "Size: ", "12"]);
+    }
+
+    #[test]
+    fn matches_line_up_with_runs_after_multibyte_text() {
+        let spans = vec![
+            TextRun {
+                style: RunStyle::Plain,
+                text: String::from("Café "),
+            },
+            TextRun {
+                style: RunStyle::Bold,
+                text: String::from("menu"),
+            },
+        ];
+        // "é" is two bytes but one column
+        assert_eq!(run_columns(&spans), [0, 5]);
+        let mut page = Page::new();
+        page.blocks.push(Block {
+            block_type: BlockType::Paragraph,
+            spans,
+        });
+        // a preformatted line keeps the runs of its block together
+        page.mark_preformatted(0..1);
+        let mut view = view(40, PageBudget::default());
+        view.load_page(page);
+        assert_eq!(view.find("menu"), 1);
+        let rp = view.get_imutable_page();
+        let found = &rp.find_matches("menu")[0];
+        assert_eq!(run_columns(rp.line_runs(found.line)), [0, 5]);
+        assert_eq!(found.start, 5);
+    }
+
+    #[test]
+    fn preformatted_lines_scroll_sideways_instead_of_wrapping() {
+        let html = format!("<html><body><pre>{}</pre><p>{}</p></body></html>", "x".repeat(50), "word ".repeat(20));
+        let mut narrow = view(20, PageBudget::default());
+        narrow.load_page(Page::from_bytes(html.as_bytes(), "http://example.com/"));
+        let rp = narrow.get_imutable_page();
+        let pre = rp.line_info.iter().filter(|info| info.preformatted).count();
+        assert_eq!(pre, 1);
+        assert_eq!(rp.preformatted_width, 50);
+        assert!(narrow.scroll_horizontally(8));
+        assert!(narrow.scroll_horizontally(100));
+        assert_eq!(narrow.get_imutable_page().scroll_x, 30);
+        assert!(narrow.scroll_horizontally(-8));
+        assert_eq!(narrow.get_imutable_page().scroll_x, 22);
+        // nothing to scroll when every line fits
+        let mut wide = view(80, PageBudget::default());
+        wide.load_page(Page::from_bytes(html.as_bytes(), "http://example.com/"));
+        assert!(!wide.scroll_horizontally(8));
+    }
+}
@@ -0,0 +1,321 @@
+use crate::builder::parse_page_part;
+use crate::page::{Anchor, Indent, Page, PageImage, PageTable, TableCell};
+use alloc::vec;
+use alloc::vec::Vec;
+use nostd_html_parser::blocks::{Block, BlockType};
+use nostd_html_parser::lines::RunStyle;
+
+/// Paragraphs with at least this many characters count as article text.
+const MIN_PARAGRAPH: usize = 80;
+
+/// How many headings or short lines before the first paragraph are kept, for the
+/// article's title and byline.
+const LEAD_BLOCKS: usize = 3;
+
+/// Characters of text in a block, and how many of them are link text.
+struct TextStats {
+    text: usize,
+    links: usize,
+}
+
+fn text_stats(block: &Block) -> TextStats {
+    let mut stats = TextStats { text: 0, links: 0 };
+    for span in &block.spans {
+        let len = span.text.trim().chars().count();
+        stats.text += len;
+        if let RunStyle::Link(_) = span.style {
+            stats.links += len;
+        }
+    }
+    stats
+}
+
+/// Mostly links, like a menu or a list of related posts.
+fn is_linky(stats: &TextStats) -> bool {
+    stats.links * 2 > stats.text
+}
+
+fn is_paragraph(block: &Block, stats: &TextStats) -> bool {
+    block.block_type == BlockType::Paragraph
+        && stats.text >= MIN_PARAGRAPH
+        && stats.links * 4 < stats.text
+}
+
+/// The link to the next part of a truncated page has to survive.
+fn has_page_part(block: &Block) -> bool {
+    block.spans.iter().any(|span| match &span.style {
+        RunStyle::Link(href) => parse_page_part(href).is_some(),
+        _ => false,
+    })
+}
+
+/// Picks the blocks that make up the article. Inside an `<article>` or `<main>`
+/// everything but link lists is kept. Otherwise the article runs from the first
+/// real paragraph, plus the headings just before it, to the last one.
+fn reader_blocks(page: &Page) -> Vec<usize> {
+    let len = page.blocks.len();
+    let stats: Vec<TextStats> = page.blocks.iter().map(text_stats).collect();
+    let range = match &page.main_content {
+        Some(range) if range.end <= len => range.clone(),
+        _ => {
+            let paragraphs: Vec<usize> = (0..len)
+                .filter(|&i| is_paragraph(&page.blocks[i], &stats[i]))
+                .collect();
+            match (paragraphs.first(), paragraphs.last()) {
+                (Some(&first), Some(&last)) => {
+                    let mut start = first;
+                    while start > 0 && first - start < LEAD_BLOCKS {
+                        let prev = start - 1;
+                        let short = stats[prev].text < MIN_PARAGRAPH && !is_linky(&stats[prev]);
+                        if page.blocks[prev].block_type != BlockType::Header && !short {
+                            break;
+                        }
+                        start = prev;
+                    }
+                    start..last + 1
+                }
+                _ => 0..len,
+            }
+        }
+    };
+    // blank lines in code blocks are part of the code, and form controls look
+    // like links but belong to the article
+    let mut kept: Vec<usize> = range
+        .filter(|&i| {
+            (stats[i].text > 0 || page.is_preformatted(i))
+                && (!is_linky(&stats[i]) || page.control_for_block(i).is_some())
+        })
+        .collect();
+    if let Some(last) = len.checked_sub(1) {
+        if has_page_part(&page.blocks[last]) && kept.last() != Some(&last) {
+            kept.push(last);
+        }
+    }
+    kept
+}
+
+/// The table with its cells moved to where their blocks ended up, if none of
+/// them were dropped.
+fn kept_table(table: &PageTable, kept: &[usize]) -> Option<PageTable> {
+    let blocks = table.blocks();
+    let start = kept.iter().position(|&i| i == blocks.start)?;
+    if !kept.get(start..start + blocks.len())?.iter().copied().eq(blocks.clone()) {
+        return None;
+    }
+    let rows = table
+        .rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|cell| TableCell {
+                    blocks: cell.blocks.start - blocks.start + start..cell.blocks.end - blocks.start + start,
+                    header: cell.header,
+                })
+                .collect()
+        })
+        .collect();
+    Some(PageTable { rows })
+}
+
+/// Returns a copy of the page with the menus, sidebars and footers left out.
+/// Pages where nothing looks like an article come back unchanged.
+pub fn reader_page(page: &Page) -> Page {
+    let kept = reader_blocks(page);
+    let mut reader = page.clone();
+    reader.selection = 0;
+    reader.main_content = None;
+    if kept.is_empty() {
+        return reader;
+    }
+    reader.blocks = kept.iter().map(|&i| page.blocks[i].clone()).collect();
+    // anchors in dropped blocks point at the next block that was kept
+    let last = reader.blocks.len() - 1;
+    reader.anchors = page
+        .anchors
+        .iter()
+        .map(|anchor| Anchor {
+            name: anchor.name.clone(),
+            block: kept.iter().position(|&i| i >= anchor.block).unwrap_or(last),
+        })
+        .collect();
+    reader.images = page
+        .images
+        .iter()
+        .filter_map(|image| {
+            let block = kept.iter().position(|&i| i == image.block)?;
+            Some(PageImage {
+                block,
+                ..image.clone()
+            })
+        })
+        .collect();
+    reader.tables = page
+        .tables
+        .iter()
+        .filter_map(|table| kept_table(table, &kept))
+        .collect();
+    // every control is kept so the values of dropped ones are still sent
+    for control in reader.controls.iter_mut() {
+        control.block = control.block.and_then(|block| kept.iter().position(|&i| i == block));
+    }
+    reader.indents = page
+        .indents
+        .iter()
+        .filter_map(|indent| {
+            let block = kept.iter().position(|&i| i == indent.block)?;
+            Some(Indent { block, ..*indent })
+        })
+        .collect();
+    // resuming where a dropped block started only brings back blocks that are
+    // dropped again, so the offset works for the next block kept
+    reader.part_offsets = vec![];
+    for &(block, offset) in &page.part_offsets {
+        let Some(block) = kept.iter().position(|&i| i >= block) else {
+            break;
+        };
+        if reader.part_offsets.last().is_some_and(|(last, _)| *last == block) {
+            reader.part_offsets.pop();
+        }
+        reader.part_offsets.push((block, offset));
+    }
+    reader.preformatted = vec![];
+    for (new, &old) in kept.iter().enumerate() {
+        if page.is_preformatted(old) {
+            reader.mark_preformatted(new..new + 1);
+        }
+    }
+    let has_heading = reader.blocks[0].block_type == BlockType::Header;
+    if let (false, Some(title)) = (has_heading, &page.title) {
+        reader.blocks.insert(0, Block::new_of_type(BlockType::Header, title));
+        for anchor in reader.anchors.iter_mut() {
+            anchor.block += 1;
+        }
+        for image in reader.images.iter_mut() {
+            image.block += 1;
+        }
+        for indent in reader.indents.iter_mut() {
+            indent.block += 1;
+        }
+        for block in reader.controls.iter_mut().filter_map(|control| control.block.as_mut()) {
+            *block += 1;
+        }
+        for (block, _) in reader.part_offsets.iter_mut() {
+            *block += 1;
+        }
+        for range in reader.preformatted.iter_mut() {
+            *range = range.start + 1..range.end + 1;
+        }
+        for cell in reader.tables.iter_mut().flat_map(|table| table.rows.iter_mut().flatten()) {
+            cell.blocks = cell.blocks.start + 1..cell.blocks.end + 1;
+        }
+    }
+    reader.index_links();
+    reader
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::page_part_href;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use nostd_html_parser::lines::TextRun;
+
+    const STORY: &str = "This paragraph is long enough to count as the text of an article rather than a caption or a menu item.";
+
+    fn paragraph(text: &str) -> Block {
+        Block::new_of_type(BlockType::Paragraph, text)
+    }
+
+    fn links(hrefs: &[&str]) -> Block {
+        Block {
+            block_type: BlockType::Paragraph,
+            spans: hrefs
+                .iter()
+                .map(|href| TextRun {
+                    style: RunStyle::Link(href.to_string()),
+                    text: format!("Go to {}", href),
+                })
+                .collect(),
+        }
+    }
+
+    fn page(blocks: Vec<Block>) -> Page {
+        let mut page = Page::new();
+        page.url = "http://example.com/".to_string();
+        page.blocks = blocks;
+        page.index_links();
+        page
+    }
+
+    fn texts(page: &Page) -> Vec<String> {
+        page.blocks
+            .iter()
+            .map(|block| block.spans.iter().map(|span| span.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn article_or_main_is_used() {
+        let mut page = page(vec![
+            paragraph(STORY),
+            Block::new_of_type(BlockType::Header, "Story"),
+            paragraph("Short one."),
+            links(&["/a", "/b"]),
+            paragraph("Footer"),
+        ]);
+        page.main_content = Some(1..4);
+        let reader = reader_page(&page);
+        assert_eq!(texts(&reader), ["Story", "Short one."]);
+        assert_eq!(reader.main_content, None);
+    }
+
+    #[test]
+    fn link_lists_and_edges_are_dropped() {
+        let reader = reader_page(&page(vec![
+            links(&["/", "/news"]),
+            paragraph("Site name"),
+            Block::new_of_type(BlockType::Header, "Title"),
+            paragraph(STORY),
+            links(&["/related", "/more"]),
+            paragraph(STORY),
+            paragraph("Footer"),
+        ]));
+        assert_eq!(texts(&reader), ["Site name", "Title", STORY, STORY]);
+        assert!(reader.links.is_empty());
+    }
+
+    #[test]
+    fn title_heading_is_added() {
+        let mut page = page(vec![paragraph(STORY), paragraph(STORY)]);
+        page.title = Some("The Title".to_string());
+        page.mark_preformatted(1..2);
+        let reader = reader_page(&page);
+        assert_eq!(texts(&reader), ["The Title", STORY, STORY]);
+        assert_eq!(reader.blocks[0].block_type, BlockType::Header);
+        assert!(reader.is_preformatted(2));
+        assert!(!reader.is_preformatted(1));
+    }
+
+    #[test]
+    fn next_part_link_and_offsets_are_kept() {
+        // stories at the even blocks, menus at the odd ones
+        let mut blocks = vec![];
+        for n in 0..30 {
+            blocks.push(paragraph(&format!("{} {}", STORY, n)));
+            blocks.push(links(&["/menu"]));
+        }
+        let mut page = page(blocks);
+        page.title = Some("Parts".to_string());
+        page.part_offsets = vec![(0, 0), (20, 2000), (33, 3300), (34, 3400)];
+        page.push_next_part(6000);
+        let reader = reader_page(&page);
+        assert_eq!(reader.links.len(), 1);
+        assert_eq!(reader.links[0].url, page_part_href("http://example.com/", 6000));
+        // story n ends up at block n + 1, after the title. The menu at block 33
+        // is dropped, so its offset goes to the story after it, which has one of its own.
+        assert_eq!(reader.part_offsets, [(1, 0), (11, 2000), (18, 3400)]);
+        assert_eq!(texts(&reader)[18], format!("{} 17", STORY));
+        assert_eq!(reader.part_offset_before(17), Some((11, 2000)));
+    }
+}
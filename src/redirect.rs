@@ -0,0 +1,158 @@
+use crate::url::Url;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// How many redirects a load follows before giving up.
+pub const MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedirectError {
+    TooMany,
+    /// the redirect went back to a URL already visited, with no cookies set
+    /// in between
+    Loop(String),
+    /// a redirect without a usable `Location`
+    BadLocation,
+}
+
+/// HTTP statuses that send the client somewhere else.
+pub fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+/// Whether the request after a redirect repeats the method and body. Only
+/// 307 and 308 do. Browsers turn the rest into a GET.
+pub fn keeps_method(status: u16) -> bool {
+    matches!(status, 307 | 308)
+}
+
+/// The URLs a single load has gone through.
+pub struct RedirectChain {
+    urls: Vec<String>,
+    /// index into `urls` of the first URL requested since cookies were last set
+    cookies_set: usize,
+}
+
+impl RedirectChain {
+    pub fn new(url: &str) -> RedirectChain {
+        RedirectChain {
+            urls: vec![url.to_string()],
+            cookies_set: 0,
+        }
+    }
+    /// Notes that the response for the current URL set cookies. Going back to
+    /// a URL from before it isn't a loop then, as with a consent or login page
+    /// that sends the user back where they came from.
+    pub fn cookies_changed(&mut self) {
+        self.cookies_set = self.urls.len();
+    }
+    /// Where the load has got to, the final URL once it is done.
+    pub fn current(&self) -> &str {
+        self.urls.last().map_or("", |url| url.as_str())
+    }
+    /// The current URL without its fragment, which servers don't want.
+    pub fn request_url(&self) -> String {
+        match Url::parse(self.current()) {
+            Some(url) => url.without_fragment().to_string(),
+            None => self.current().to_string(),
+        }
+    }
+    /// How many redirects have been followed.
+    pub fn hops(&self) -> usize {
+        self.urls.len() - 1
+    }
+    /// Moves on to `location`, resolved against the current URL. A location
+    /// without a fragment keeps the one the current URL had. Going back to a
+    /// URL already visited since cookies were last set is a loop.
+    pub fn follow(&mut self, location: Option<&str>) -> Result<&str, RedirectError> {
+        if self.hops() >= MAX_REDIRECTS {
+            return Err(RedirectError::TooMany);
+        }
+        let location = location.map(str::trim).filter(|location| !location.is_empty());
+        let current = Url::parse(self.current());
+        let mut next = current
+            .as_ref()
+            .zip(location)
+            .and_then(|(current, location)| current.join(location))
+            .ok_or(RedirectError::BadLocation)?;
+        if next.fragment.is_none() {
+            next.fragment = current.and_then(|current| current.fragment);
+        }
+        let target = next.without_fragment();
+        let seen = self.urls[self.cookies_set..]
+            .iter()
+            .any(|url| Url::parse(url).is_some_and(|url| url.without_fragment() == target));
+        if seen {
+            return Err(RedirectError::Loop(target.to_string()));
+        }
+        self.urls.push(next.to_string());
+        Ok(self.current())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test]
+    fn resolves_relative_locations() {
+        let mut chain = RedirectChain::new("http://a.com/x/y?q#top");
+        assert_eq!(chain.request_url(), "http://a.com/x/y?q");
+        assert_eq!(chain.follow(Some("z")).unwrap(), "http://a.com/x/z#top");
+        assert_eq!(chain.follow(Some("../w?page=2")).unwrap(), "http://a.com/w?page=2#top");
+        assert_eq!(chain.follow(Some("//b.com/")).unwrap(), "http://b.com/#top");
+        assert_eq!(chain.follow(Some(" https://c.com/q#sec ")).unwrap(), "https://c.com/q#sec");
+        assert_eq!(chain.hops(), 4);
+        assert_eq!(chain.follow(None), Err(RedirectError::BadLocation));
+        assert_eq!(chain.follow(Some("  ")), Err(RedirectError::BadLocation));
+    }
+
+    #[test]
+    fn method_after_redirect() {
+        for status in [301, 302, 303] {
+            assert!(is_redirect(status) && !keeps_method(status));
+        }
+        for status in [307, 308] {
+            assert!(is_redirect(status) && keeps_method(status));
+        }
+        assert!(!is_redirect(304) && !is_redirect(200));
+    }
+
+    #[test]
+    fn hop_limit() {
+        let mut chain = RedirectChain::new("http://a.com/0");
+        for n in 1..=MAX_REDIRECTS {
+            chain.follow(Some(&format!("/{}", n))).unwrap();
+        }
+        assert_eq!(chain.follow(Some("/next")), Err(RedirectError::TooMany));
+        // coming back after cookies were set still counts towards the limit
+        let mut chain = RedirectChain::new("http://a.com/");
+        for _ in 0..MAX_REDIRECTS / 2 {
+            chain.follow(Some("/consent")).unwrap();
+            chain.cookies_changed();
+            chain.follow(Some("/")).unwrap();
+            chain.cookies_changed();
+        }
+        assert_eq!(chain.follow(Some("/consent")), Err(RedirectError::TooMany));
+    }
+
+    #[test]
+    fn returning_after_cookies_is_not_a_loop() {
+        let mut chain = RedirectChain::new("http://a.com/");
+        chain.follow(Some("/consent")).unwrap();
+        chain.cookies_changed();
+        assert_eq!(chain.follow(Some("/")).unwrap(), "http://a.com/");
+        // but bouncing back again without a new cookie is
+        chain.follow(Some("/consent")).unwrap();
+        assert_eq!(chain.follow(Some("/")), Err(RedirectError::Loop("http://a.com/".into())));
+    }
+
+    #[test]
+    fn loops_without_cookies() {
+        let mut chain = RedirectChain::new("http://a.com/#top");
+        chain.follow(Some("/b")).unwrap();
+        assert_eq!(chain.follow(Some("/#other")), Err(RedirectError::Loop("http://a.com/".into())));
+    }
+}
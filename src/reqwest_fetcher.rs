@@ -0,0 +1,93 @@
+use crate::error::LoadError;
+use crate::fetch::{Fetcher, Method, Request, ResponseHead, ResponseSink};
+use alloc::string::ToString;
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::redirect::Policy;
+use std::io::{ErrorKind, Read};
+
+/// Fetches with reqwest, for the simulator. Its requests block, holding up
+/// whatever task runs them.
+pub struct ReqwestFetcher {
+    client: Client,
+}
+
+impl ReqwestFetcher {
+    pub fn new() -> Result<ReqwestFetcher, LoadError> {
+        let client = ClientBuilder::new()
+            .use_rustls_tls()
+            .redirect(Policy::none())
+            .build()
+            .map_err(load_error)?;
+        Ok(ReqwestFetcher { client })
+    }
+}
+
+impl Fetcher for ReqwestFetcher {
+    async fn fetch(&mut self, request: &Request<'_>, sink: &mut impl ResponseSink) -> Result<(), LoadError> {
+        let mut builder = match request.method {
+            Method::Get => self.client.get(request.url),
+            Method::Post => self.client.post(request.url),
+        };
+        if let Some(body) = request.body {
+            builder = builder.body(body.to_string());
+        }
+        for (name, value) in &request.headers {
+            builder = builder.header(*name, value.as_str());
+        }
+        let mut res = builder.send().map_err(load_error)?;
+        let head = ResponseHead {
+            status: res.status().as_u16(),
+            headers: res
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+                .collect(),
+        };
+        if !sink.head(&head).await {
+            return Ok(());
+        }
+        let mut chunk = [0u8; 4096];
+        loop {
+            let count = res.read(&mut chunk).map_err(|err| match err.kind() {
+                ErrorKind::TimedOut => LoadError::Timeout,
+                _ => LoadError::Other(err.to_string()),
+            })?;
+            if count == 0 || !sink.chunk(&chunk[..count]).await {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Sorts a reqwest failure into a [`LoadError`]. reqwest only says what
+/// stage failed, so the cause is found by walking its source chain.
+fn load_error(err: reqwest::Error) -> LoadError {
+    if err.is_timeout() {
+        return LoadError::Timeout;
+    }
+    if err.is_builder() {
+        return LoadError::InvalidUrl;
+    }
+    let mut source = std::error::Error::source(&err);
+    while let Some(cause) = source {
+        if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+            match io.kind() {
+                ErrorKind::ConnectionRefused => return LoadError::ConnectionRefused,
+                ErrorKind::TimedOut => return LoadError::Timeout,
+                _ => {}
+            }
+            // rustls failures arrive wrapped in an io error
+            if io.get_ref().is_some_and(|inner| inner.is::<rustls::Error>()) {
+                return LoadError::Tls;
+            }
+        }
+        if cause.is::<rustls::Error>() {
+            return LoadError::Tls;
+        }
+        if cause.to_string().starts_with("dns error") {
+            return LoadError::Dns;
+        }
+        source = cause.source();
+    }
+    LoadError::Other(err.to_string())
+}
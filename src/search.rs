@@ -0,0 +1,141 @@
+use crate::url::{form_encode, Url};
+use alloc::format;
+use alloc::string::{String, ToString};
+
+/// A search engine, as a URL with `{query}` where the search terms go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchEngine {
+    pub name: &'static str,
+    pub template: &'static str,
+}
+
+impl SearchEngine {
+    pub const fn new(name: &'static str, template: &'static str) -> SearchEngine {
+        SearchEngine { name, template }
+    }
+    /// The URL that searches for `query`.
+    pub fn search_url(&self, query: &str) -> String {
+        self.template.replace("{query}", &form_encode(query.trim()))
+    }
+}
+
+/// Engines with plain HTML results, small enough to load on the device.
+pub const SEARCH_ENGINES: [SearchEngine; 3] = [
+    SearchEngine::new("DuckDuckGo", "https://lite.duckduckgo.com/lite/?q={query}"),
+    SearchEngine::new("Wiby", "https://wiby.me/?q={query}"),
+    SearchEngine::new("FrogFind", "http://frogfind.com/?q={query}"),
+];
+
+pub const DEFAULT_SEARCH_ENGINE: &SearchEngine = &SEARCH_ENGINES[0];
+
+/// Schemes we can load. Anything else before a colon is taken as a host and port.
+const KNOWN_SCHEMES: [&str; 5] = ["http", "https", "gemini", "gopher", "file"];
+
+/// Endings that make a bare dotted word look like a file name rather than a
+/// host. Some are top level domains too, so a port or path still makes it a host.
+const FILE_EXTENSIONS: [&str; 20] = [
+    "html", "htm", "txt", "md", "gmi", "pdf", "png", "jpg", "jpeg", "gif", "bmp", "zip", "gz", "tar", "json",
+    "xml", "css", "js", "rs", "py",
+];
+
+/// Turns what was typed in the URL bar into a URL to load. URLs with a scheme
+/// we know are kept as they are, host names get a scheme put in front and
+/// anything else is searched for with `engine`. Blank input gives `None`.
+pub fn url_for_input(input: &str, engine: &SearchEngine) -> Option<String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return None;
+    }
+    if input.contains(char::is_whitespace) {
+        return Some(engine.search_url(input));
+    }
+    if Url::parse(input).is_some_and(|url| KNOWN_SCHEMES.contains(&url.scheme.as_str())) {
+        return Some(input.to_string());
+    }
+    match typed_host(input) {
+        // local servers rarely have certificates
+        Some(host) if host.eq_ignore_ascii_case("localhost") || is_ipv4(host) => {
+            Some(format!("http://{}", input))
+        }
+        Some(_) => Some(format!("https://{}", input)),
+        None => Some(engine.search_url(input)),
+    }
+}
+
+/// The host of input like `example.com`, `localhost:8000/docs` or
+/// `192.168.1.2`: a dotted name ending in letters, `localhost` or an IPv4
+/// address, optionally followed by a port and a path. A name on its own that
+/// ends like a file, such as `notes.txt`, isn't taken as a host.
+fn typed_host(input: &str) -> Option<&str> {
+    let authority = input.split(['/', '?', '#']).next()?;
+    let bare = authority == input && !authority.contains(':');
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => host,
+        Some(_) => return None,
+        None => authority,
+    };
+    let labels_ok = !host.is_empty()
+        && host
+            .split('.')
+            .all(|label| !label.is_empty() && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-'));
+    if !labels_ok {
+        return None;
+    }
+    let top = host.rsplit('.').next().unwrap_or("");
+    let file_like = bare && FILE_EXTENSIONS.iter().any(|extension| top.eq_ignore_ascii_case(extension));
+    let named = host.contains('.') && top.len() >= 2 && top.bytes().all(|b| b.is_ascii_alphabetic()) && !file_like;
+    if named || host.eq_ignore_ascii_case("localhost") || is_ipv4(host) {
+        Some(host)
+    } else {
+        None
+    }
+}
+
+fn is_ipv4(host: &str) -> bool {
+    host.split('.').count() == 4 && host.split('.').all(|part| part.parse::<u8>().is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(input: &str) -> Option<String> {
+        url_for_input(input, &SearchEngine::new("Test", "https://search.test/?q={query}"))
+    }
+
+    #[test]
+    fn words_are_searched_for() {
+        assert_eq!(url("rust no_std"), Some("https://search.test/?q=rust+no_std".to_string()));
+        assert_eq!(url("embassy").as_deref(), Some("https://search.test/?q=embassy"));
+        assert_eq!(url("example.com is down").as_deref(), Some("https://search.test/?q=example.com+is+down"));
+        assert_eq!(url("   "), None);
+    }
+
+    #[test]
+    fn file_names_are_searched_for() {
+        assert_eq!(url("homepage.html").as_deref(), Some("https://search.test/?q=homepage.html"));
+        assert_eq!(url("notes.TXT").as_deref(), Some("https://search.test/?q=notes.TXT"));
+        assert_eq!(url("v1.2").as_deref(), Some("https://search.test/?q=v1.2"));
+        // with a path they are hosts after all
+        assert_eq!(url("docs.rs/log").as_deref(), Some("https://docs.rs/log"));
+    }
+
+    #[test]
+    fn bare_hosts_get_a_scheme() {
+        assert_eq!(url("example.com").as_deref(), Some("https://example.com"));
+        assert_eq!(url("news.ycombinator.com/item?id=1").as_deref(), Some("https://news.ycombinator.com/item?id=1"));
+        assert_eq!(url("localhost:8000/docs").as_deref(), Some("http://localhost:8000/docs"));
+        assert_eq!(url("localhost").as_deref(), Some("http://localhost"));
+        assert_eq!(url("192.168.1.2:8080").as_deref(), Some("http://192.168.1.2:8080"));
+        assert_eq!(url("example.com:http").as_deref(), Some("https://search.test/?q=example.com%3Ahttp"));
+    }
+
+    #[test]
+    fn explicit_schemes_are_kept() {
+        assert_eq!(url("http://example.com/a b").as_deref(), Some("https://search.test/?q=http%3A%2F%2Fexample.com%2Fa+b"));
+        assert_eq!(url(" gemini://capsule.org/ ").as_deref(), Some("gemini://capsule.org/"));
+        assert_eq!(url("gopher://sdf.org").as_deref(), Some("gopher://sdf.org"));
+        assert_eq!(url("file:saved/page.nbpa").as_deref(), Some("file:saved/page.nbpa"));
+        assert_eq!(url("HTTPS://Example.com").as_deref(), Some("HTTPS://Example.com"));
+    }
+}
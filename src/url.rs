@@ -0,0 +1,382 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+
+/// An absolute URL split into its RFC 3986 components.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+    pub scheme: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub path: String,
+    pub query: Option<String>,
+    pub fragment: Option<String>,
+}
+
+/// The pieces of a URL reference before it is resolved. Any of them may be missing.
+struct Parts<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+fn split(input: &str) -> Parts<'_> {
+    let mut rest = input;
+    let mut fragment = None;
+    if let Some(n) = rest.find('#') {
+        fragment = Some(&rest[n + 1..]);
+        rest = &rest[..n];
+    }
+    let mut query = None;
+    if let Some(n) = rest.find('?') {
+        query = Some(&rest[n + 1..]);
+        rest = &rest[..n];
+    }
+    let mut scheme = None;
+    if let Some(n) = rest.find(':') {
+        let candidate = &rest[..n];
+        if is_scheme(candidate) {
+            scheme = Some(candidate);
+            rest = &rest[n + 1..];
+        }
+    }
+    let mut authority = None;
+    if let Some(after) = rest.strip_prefix("//") {
+        let end = after.find('/').unwrap_or(after.len());
+        authority = Some(&after[..end]);
+        rest = &after[end..];
+    }
+    Parts {
+        scheme,
+        authority,
+        path: rest,
+        query,
+        fragment,
+    }
+}
+
+fn is_scheme(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(ch) if ch.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '+' || ch == '-' || ch == '.')
+}
+
+/// Splits `user@host:port` into host and port. User info is dropped.
+fn split_authority(authority: &str) -> (String, Option<u16>) {
+    let hostport = match authority.rfind('@') {
+        Some(n) => &authority[n + 1..],
+        None => authority,
+    };
+    // skip past an IPv6 literal before looking for the port
+    let search_from = if hostport.starts_with('[') {
+        hostport.find(']').unwrap_or(0)
+    } else {
+        0
+    };
+    if let Some(n) = hostport[search_from..].rfind(':') {
+        let n = n + search_from;
+        let port = &hostport[n + 1..];
+        if port.is_empty() {
+            return (hostport[..n].to_ascii_lowercase(), None);
+        }
+        if let Ok(port) = port.parse::<u16>() {
+            return (hostport[..n].to_ascii_lowercase(), Some(port));
+        }
+    }
+    (hostport.to_ascii_lowercase(), None)
+}
+
+/// Removes `.` and `..` segments as described in RFC 3986 section 5.2.4.
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output = String::new();
+    while !input.is_empty() {
+        if let Some(rest) = input.strip_prefix("../") {
+            input = rest;
+        } else if let Some(rest) = input.strip_prefix("./") {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") || input == "/.." {
+            input = if input == "/.." { "/" } else { &input[3..] };
+            match output.rfind('/') {
+                Some(n) => output.truncate(n),
+                None => output.clear(),
+            }
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            let start = if input.starts_with('/') { 1 } else { 0 };
+            let end = input[start..]
+                .find('/')
+                .map(|n| n + start)
+                .unwrap_or(input.len());
+            output.push_str(&input[..end]);
+            input = &input[end..];
+        }
+    }
+    output
+}
+
+/// Strips the whitespace that the HTML spec says to ignore in URL attributes.
+fn clean(input: &str) -> String {
+    input
+        .trim()
+        .chars()
+        .filter(|ch| *ch != '\t' && *ch != '\n' && *ch != '\r')
+        .collect()
+}
+
+impl Url {
+    /// Parses an absolute URL. Returns `None` for relative references.
+    pub fn parse(input: &str) -> Option<Url> {
+        let input = clean(input);
+        let parts = split(&input);
+        let scheme = parts.scheme?.to_ascii_lowercase();
+        let (host, port) = match parts.authority {
+            Some(authority) => {
+                let (host, port) = split_authority(authority);
+                (Some(host), port)
+            }
+            None => (None, None),
+        };
+        let mut path = parts.path.to_string();
+        if host.is_some() && path.is_empty() {
+            path.push('/');
+        }
+        Some(Url {
+            scheme,
+            host,
+            port,
+            path,
+            query: parts.query.map(|q| q.to_string()),
+            fragment: parts.fragment.map(|f| f.to_string()),
+        })
+    }
+
+    /// Resolves `href` against this URL following RFC 3986 section 5.2.
+    pub fn join(&self, href: &str) -> Option<Url> {
+        let href = clean(href);
+        let r = split(&href);
+        if let Some(scheme) = r.scheme {
+            let mut url = Url::parse(&href)?;
+            url.path = remove_dot_segments(&url.path);
+            if url.host.is_some() && url.path.is_empty() {
+                url.path.push('/');
+            }
+            url.scheme = scheme.to_ascii_lowercase();
+            return Some(url);
+        }
+        let mut url = Url {
+            scheme: self.scheme.clone(),
+            host: self.host.clone(),
+            port: self.port,
+            path: String::new(),
+            query: None,
+            fragment: r.fragment.map(|f| f.to_string()),
+        };
+        if let Some(authority) = r.authority {
+            let (host, port) = split_authority(authority);
+            url.host = Some(host);
+            url.port = port;
+            url.path = remove_dot_segments(r.path);
+            url.query = r.query.map(|q| q.to_string());
+        } else if r.path.is_empty() {
+            url.path = self.path.clone();
+            url.query = match r.query {
+                Some(q) => Some(q.to_string()),
+                None => self.query.clone(),
+            };
+        } else {
+            if r.path.starts_with('/') {
+                url.path = remove_dot_segments(r.path);
+            } else {
+                url.path = remove_dot_segments(&self.merge(r.path));
+            }
+            url.query = r.query.map(|q| q.to_string());
+        }
+        if url.host.is_some() && url.path.is_empty() {
+            url.path.push('/');
+        }
+        Some(url)
+    }
+
+    fn merge(&self, path: &str) -> String {
+        if self.host.is_some() && self.path.is_empty() {
+            return format!("/{}", path);
+        }
+        match self.path.rfind('/') {
+            Some(n) => format!("{}{}", &self.path[..n + 1], path),
+            None => path.to_string(),
+        }
+    }
+
+    /// Returns a copy of this URL without the `#fragment`.
+    pub fn without_fragment(&self) -> Url {
+        let mut url = self.clone();
+        url.fragment = None;
+        url
+    }
+}
+
+/// Decodes `%XX` escapes. Invalid escapes and non UTF-8 results are left as is.
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).unwrap_or_default();
+        // from_str_radix would also take a sign, like "%+1"
+        if bytes[i] == b'%' && hex.len() == 2 && hex.iter().all(u8::is_ascii_hexdigit) {
+            let hex = core::str::from_utf8(hex).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(out).unwrap_or_else(|_| input.to_string())
+}
+
+impl Display for Url {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.scheme)?;
+        if let Some(host) = &self.host {
+            write!(f, "//{}", host)?;
+            if let Some(port) = self.port {
+                write!(f, ":{}", port)?;
+            }
+        }
+        write!(f, "{}", self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{}", query)?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{}", fragment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a name or value for an `application/x-www-form-urlencoded` body.
+/// Spaces become `+` and everything but letters, digits and `*-._` is escaped.
+pub fn form_encode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for b in input.bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'*' | b'-' | b'.' | b'_' => out.push(b as char),
+            b' ' => out.push('+'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "http://a/b/c/d;p?q";
+
+    fn resolve(href: &str) -> String {
+        Url::parse(BASE).unwrap().join(href).unwrap().to_string()
+    }
+
+    // RFC 3986 section 5.4.1
+    #[test]
+    fn normal_examples() {
+        let examples = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            // an empty path after a host is written as "/"
+            ("//g", "http://g/"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            ("g?y#s", "http://a/b/c/g?y#s"),
+            (";x", "http://a/b/c/;x"),
+            ("g;x", "http://a/b/c/g;x"),
+            ("g;x?y#s", "http://a/b/c/g;x?y#s"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../", "http://a/"),
+            ("../../g", "http://a/g"),
+        ];
+        for (href, expected) in examples {
+            assert_eq!(resolve(href), expected, "resolving {:?}", href);
+        }
+    }
+
+    // RFC 3986 section 5.4.2
+    #[test]
+    fn abnormal_examples() {
+        let examples = [
+            ("../../../g", "http://a/g"),
+            ("../../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            (".g", "http://a/b/c/.g"),
+            ("g..", "http://a/b/c/g.."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("./g/.", "http://a/b/c/g/"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/./y", "http://a/b/c/g;x=1/y"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+            ("g?y/./x", "http://a/b/c/g?y/./x"),
+            ("g?y/../x", "http://a/b/c/g?y/../x"),
+            ("g#s/./x", "http://a/b/c/g#s/./x"),
+            ("g#s/../x", "http://a/b/c/g#s/../x"),
+            // the strict reading: a scheme makes the reference absolute
+            ("http:g", "http:g"),
+        ];
+        for (href, expected) in examples {
+            assert_eq!(resolve(href), expected, "resolving {:?}", href);
+        }
+    }
+
+    #[test]
+    fn hosts_and_ports() {
+        let url = Url::parse("HTTP://User@Example.COM:8080/a?b#c").unwrap();
+        assert_eq!(url.scheme, "http");
+        assert_eq!(url.host.as_deref(), Some("example.com"));
+        assert_eq!(url.port, Some(8080));
+        assert_eq!(url.to_string(), "http://example.com:8080/a?b#c");
+        assert_eq!(url.without_fragment().to_string(), "http://example.com:8080/a?b");
+        assert!(Url::parse("/relative").is_none());
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        // only two hex digits make an escape
+        assert_eq!(percent_decode("%+1%-1%zz%4"), "%+1%-1%zz%4");
+        assert_eq!(percent_decode("100%"), "100%");
+        // bytes that aren't UTF-8 leave the input as it was
+        assert_eq!(percent_decode("%FF"), "%FF");
+    }
+}